@@ -1,79 +1,10 @@
-use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use lob_processor::{basic::BasicProcessor, improved::ImprovedProcessor};
-use std::fs;
-use std::io::Write;
-use std::path::Path;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use fnv::FnvHashMap;
+use lob_processor::generator::{self, FeedGenerator, GeneratorConfig};
+use lob_processor::tick::TickSide;
+use lob_processor::{basic::BasicProcessor, improved::ImprovedProcessor, Side};
 use tempfile::tempdir;
 
-struct TestDataGenerator {
-    num_securities: u64,
-    num_incrementals: u64,
-}
-
-impl TestDataGenerator {
-    fn new(num_securities: u64, num_incrementals: u64) -> Self {
-        Self {
-            num_securities,
-            num_incrementals,
-        }
-    }
-
-    fn generate_snapshot_file(&self, path: &Path) -> std::io::Result<()> {
-        let mut file = fs::File::create(path)?;
-
-        for sec_id in 1..=self.num_securities {
-            // Snapshot header
-            file.write_all(&1000u64.to_le_bytes())?;
-            file.write_all(&0u64.to_le_bytes())?;
-            file.write_all(&sec_id.to_le_bytes())?;
-
-            // 5 bid and ask levels
-            for i in 0..5 {
-                let bid_price = 100.0 - (i as f64) * 0.01;
-                let ask_price = 100.0 + (i as f64) * 0.01;
-
-                file.write_all(&bid_price.to_bits().to_le_bytes())?;
-                file.write_all(&(100 * (5 - i) as u64).to_le_bytes())?;
-
-                file.write_all(&ask_price.to_bits().to_le_bytes())?;
-                file.write_all(&(100 * (5 - i) as u64).to_le_bytes())?;
-            }
-        }
-
-        Ok(())
-    }
-
-    fn generate_incremental_file(&self, path: &Path) -> std::io::Result<()> {
-        let mut file = fs::File::create(path)?;
-
-        for msg_idx in 1..=self.num_incrementals {
-            let security_id = (msg_idx % self.num_securities) + 1;
-            let num_updates = 3u64;
-
-            file.write_all(&(1000 + msg_idx).to_le_bytes())?;
-            file.write_all(&msg_idx.to_le_bytes())?;
-            file.write_all(&security_id.to_le_bytes())?;
-            file.write_all(&num_updates.to_le_bytes())?;
-
-            for i in 0..num_updates {
-                let side = (i % 2) as u8;
-                let price = if side == 0 {
-                    99.99 - (i as f64) * 0.01
-                } else {
-                    100.01 + (i as f64) * 0.01
-                };
-                let qty = if i == 0 { 0 } else { 200 + i * 50 };
-
-                file.write_all(&[side])?;
-                file.write_all(&price.to_bits().to_le_bytes())?;
-                file.write_all(&qty.to_le_bytes())?;
-            }
-        }
-
-        Ok(())
-    }
-}
-
 fn bench_implementations(c: &mut Criterion) {
     let temp_dir = tempdir().expect("Failed to create temp directory");
 
@@ -87,13 +18,18 @@ fn bench_implementations(c: &mut Criterion) {
         let snapshot_path = temp_dir.path().join(format!("snapshot_{}.bin", name));
         let incremental_path = temp_dir.path().join(format!("incremental_{}.bin", name));
 
-        let generator = TestDataGenerator::new(num_securities, num_incrementals);
-        generator
-            .generate_snapshot_file(&snapshot_path)
-            .expect("Failed to generate snapshot");
-        generator
-            .generate_incremental_file(&incremental_path)
-            .expect("Failed to generate incrementals");
+        let config = GeneratorConfig {
+            seed: 42,
+            securities: num_securities,
+            messages: num_incrementals,
+            ..GeneratorConfig::default()
+        };
+        generator::generate_files(
+            config,
+            snapshot_path.to_str().unwrap(),
+            incremental_path.to_str().unwrap(),
+        )
+        .expect("Failed to generate test data");
 
         let mut group = c.benchmark_group(format!("orderbook_{}", name));
 
@@ -134,17 +70,22 @@ fn bench_implementations(c: &mut Criterion) {
 fn bench_single_operations(c: &mut Criterion) {
     use lob_processor::basic::Basic;
     use lob_processor::improved::ImprovedSide;
-    use lob_processor::{BookSide, Side};
+    use lob_processor::BookSide;
 
     let mut group = c.benchmark_group("single_operations");
 
-    let updates: Vec<(Side, f64, u64)> = (0..100)
-        .map(|i| {
-            let side = if i % 3 == 0 { Side::B } else { Side::A };
-            let price = 100.0 + (i as f64 % 10.0) * 0.01;
-            let qty = if i % 10 == 0 { 0 } else { 100 + i };
-            (side, price, qty)
-        })
+    // bid side of one generated security, starting from an empty side
+    let mut feed = FeedGenerator::new(GeneratorConfig {
+        seed: 42,
+        securities: 1,
+        messages: 1000,
+        ..GeneratorConfig::default()
+    })
+    .expect("Failed to create generator");
+    let updates: Vec<(Side, f64, u64)> = std::iter::from_fn(|| feed.next_message())
+        .flat_map(|message| message.updates)
+        .filter(|&(side, _, _)| side == Side::B)
+        .take(100)
         .collect();
 
     group.bench_function("Improved_updates", |b| {
@@ -175,8 +116,182 @@ fn bench_single_operations(c: &mut Criterion) {
         });
     });
 
+    group.bench_function("Tick_updates", |b| {
+        b.iter(|| {
+            let mut book_side = TickSide::new(true);
+            for (_side, price, qty) in &updates {
+                if *qty == 0 {
+                    book_side.remove_l(*price);
+                } else {
+                    book_side.update_l(*price, *qty);
+                }
+            }
+            std::hint::black_box(book_side.get_l());
+        });
+    });
+
+    group.finish();
+}
+
+// f64 against tick search on a full side, names carry the path this cpu runs, build
+// with --features force-avx2 (or force-sse2, force-scalar) to bench the others
+fn bench_search(c: &mut Criterion) {
+    use lob_processor::improved::{ImprovedSide, MAX_LEVELS};
+    use lob_processor::simd::{self, SearchPath};
+    use lob_processor::BookSide;
+
+    let mut group = c.benchmark_group("search");
+
+    // every level of a full bid side, then prices between levels that miss
+    let prices: Vec<f64> = (0..MAX_LEVELS as i64)
+        .map(|i| (10_000 - i * 2) as f64 * 0.01)
+        .collect();
+    let lookups: Vec<f64> = prices
+        .iter()
+        .chain(prices.iter())
+        .map(|&price| price - 0.01)
+        .chain(prices.iter().copied())
+        .collect();
+
+    let path = simd::search_path();
+    let f64_path = path.min(SearchPath::Avx2);
+    let tick_path = if path == SearchPath::Sse2 {
+        SearchPath::Scalar
+    } else {
+        path
+    };
+
+    let mut f64_side = ImprovedSide::new(true);
+    let mut tick_side = TickSide::new(true);
+    for &price in &prices {
+        f64_side.update_l(price, 100);
+        tick_side.update_l(price, 100);
+    }
+
+    // misses are dropped on the full side, hits update in place, neither changes levels
+    group.bench_function(format!("f64_{}", f64_path), |b| {
+        b.iter(|| {
+            for &price in &lookups {
+                std::hint::black_box(f64_side.update_l(price, 100));
+            }
+        });
+    });
+
+    group.bench_function(format!("tick_{}", tick_path), |b| {
+        b.iter(|| {
+            for &price in &lookups {
+                std::hint::black_box(tick_side.update_l(price, 100));
+            }
+        });
+    });
+
+    group.finish();
+}
+
+// map of whole books against the arena, the same updates through both, and the arena
+// with the into_books copy process_files makes. sparse ids are pushed past DIRECT_IDS so
+// the arena falls back to its hash map
+fn bench_book_storage(c: &mut Criterion) {
+    use lob_processor::arena::{BookArena, DIRECT_IDS};
+    use lob_processor::improved::ImprovedSide;
+    use lob_processor::{Lob, SecurityId};
+
+    let mut group = c.benchmark_group("book_storage");
+
+    let config = GeneratorConfig {
+        seed: 42,
+        securities: 1000,
+        messages: 20_000,
+        ..GeneratorConfig::default()
+    };
+    let mut feed = FeedGenerator::new(config.clone()).expect("Failed to create generator");
+    let initial: Vec<Lob<ImprovedSide>> = (1..=config.securities)
+        .filter_map(|id| feed.book(id, ImprovedSide::new))
+        .collect();
+    let messages: Vec<_> = std::iter::from_fn(|| feed.next_message()).collect();
+
+    for (layout, to_id) in [
+        ("dense", (|id| id) as fn(SecurityId) -> SecurityId),
+        ("sparse", |id| DIRECT_IDS + id * 7919),
+    ] {
+        let mut map = FnvHashMap::default();
+        let mut arena = BookArena::new();
+        for book in &initial {
+            let mut book = book.clone();
+            book.security_id = to_id(book.security_id);
+            map.insert(book.security_id, book.clone());
+            arena.insert(book);
+        }
+
+        group.bench_function(BenchmarkId::new("map", layout), |b| {
+            b.iter_batched_ref(
+                || map.clone(),
+                |books| {
+                    for message in &messages {
+                        if let Some(book) = books.get_mut(&to_id(message.security_id)) {
+                            for &(side, price, qty) in &message.updates {
+                                book.update(side, price, qty);
+                            }
+                            book.last_update_seq = Some(message.seq_no);
+                            book.last_exchange_ts = Some(message.timestamp);
+                        }
+                    }
+                },
+                BatchSize::LargeInput,
+            );
+        });
+
+        group.bench_function(BenchmarkId::new("arena", layout), |b| {
+            b.iter_batched_ref(
+                || arena.clone(),
+                |books| {
+                    for message in &messages {
+                        if let Some(slot) = books.slot(to_id(message.security_id)) {
+                            let book = books.sides_mut(slot);
+                            for &(side, price, qty) in &message.updates {
+                                book.update(side, price, qty);
+                            }
+                            let meta = books.meta_mut(slot);
+                            meta.last_update_seq = Some(message.seq_no);
+                            meta.last_exchange_ts = Some(message.timestamp);
+                        }
+                    }
+                },
+                BatchSize::LargeInput,
+            );
+        });
+
+        // what process_files pays: the same run, then the books copied out into a map
+        group.bench_function(BenchmarkId::new("arena_into_books", layout), |b| {
+            b.iter_batched(
+                || arena.clone(),
+                |mut books| {
+                    for message in &messages {
+                        if let Some(slot) = books.slot(to_id(message.security_id)) {
+                            let book = books.sides_mut(slot);
+                            for &(side, price, qty) in &message.updates {
+                                book.update(side, price, qty);
+                            }
+                            let meta = books.meta_mut(slot);
+                            meta.last_update_seq = Some(message.seq_no);
+                            meta.last_exchange_ts = Some(message.timestamp);
+                        }
+                    }
+                    books.into_books()
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+
     group.finish();
 }
 
-criterion_group!(benches, bench_implementations, bench_single_operations);
+criterion_group!(
+    benches,
+    bench_implementations,
+    bench_single_operations,
+    bench_search,
+    bench_book_storage
+);
 criterion_main!(benches);
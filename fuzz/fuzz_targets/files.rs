@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lob_processor::basic::BasicProcessor;
+use lob_processor::improved::ImprovedProcessor;
+
+// first two bytes say where the snapshot ends and the incremental file starts, both
+// processors must return Ok or Err on anything, never panic or read past the end
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let split = u16::from_le_bytes([data[0], data[1]]) as usize;
+    let data = &data[2..];
+    let (snapshot, incremental) = data.split_at(split.min(data.len()));
+
+    let _ = BasicProcessor::new().process_bytes(snapshot, incremental);
+    let _ = ImprovedProcessor::new().process_bytes(snapshot, incremental);
+});
@@ -0,0 +1,42 @@
+#![no_main]
+
+use crossbeam::channel;
+use libfuzzer_sys::fuzz_target;
+use lob_processor::basic::BasicProcessor;
+use lob_processor::improved::ImprovedProcessor;
+use lob_processor::{MessageType, StreamMessage};
+
+// input is a run of datagrams: a type byte (0 is EndOfSnapshot), a u16 length and the
+// payload, cut short at the end of the input. lengths are not tied to the message type
+// so short and oversized datagrams of every type come up
+fn messages(mut data: &[u8]) -> Vec<StreamMessage> {
+    let mut messages = Vec::new();
+
+    while data.len() >= 3 {
+        let tag = data[0] & 0b11;
+        let len = (u16::from_le_bytes([data[1], data[2]]) as usize).min(data.len() - 3);
+        let payload = data[3..3 + len].to_vec();
+        data = &data[3 + len..];
+
+        messages.push(match MessageType::from_u8(tag) {
+            Some(msg_type) => StreamMessage::Data(msg_type, payload),
+            None => StreamMessage::EndOfSnapshot,
+        });
+    }
+
+    messages
+}
+
+fn send_all(data: &[u8]) -> channel::Receiver<StreamMessage> {
+    let (sender, receiver) = channel::unbounded();
+    for message in messages(data) {
+        sender.send(message).unwrap();
+    }
+    // closed channel ends the processing loop
+    receiver
+}
+
+fuzz_target!(|data: &[u8]| {
+    let _ = BasicProcessor::new().process_stream(send_all(data), 0);
+    let _ = ImprovedProcessor::new().process_stream(send_all(data), 0);
+});
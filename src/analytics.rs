@@ -0,0 +1,237 @@
+use crate::instruments::Instruments;
+use crate::tick::DEFAULT_TICK_SIZE;
+use crate::*;
+use fnv::FnvHashMap;
+
+// features strategies derive from the top of the book, read level by level through
+// BookSide::level so no side is copied. a function is None when a side it needs is empty
+// or the result has no meaning (no quantity, a mid of 0). a crossed book is not an error,
+// its spread comes out negative
+
+/// Levels and band [`Analytics::compute`] uses.
+#[derive(Debug, Clone)]
+pub struct AnalyticsConfig {
+    /// Levels per side for the imbalance and the weighted mid.
+    pub levels: usize,
+    /// Half width of the depth band around the mid, in basis points.
+    pub depth_bps: f64,
+    /// Tick the spread is counted in, for books without an instrument.
+    pub tick_size: f64,
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            levels: 5,
+            depth_bps: 10.0,
+            tick_size: DEFAULT_TICK_SIZE,
+        }
+    }
+}
+
+/// Quantity resting within a band around the mid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Depth {
+    pub bids: Qty,
+    pub asks: Qty,
+}
+
+#[inline(always)]
+fn top<B: BookSide>(book: &Lob<B>) -> Option<((f64, Qty), (f64, Qty))> {
+    Some((book.bids.level(0)?, book.asks.level(0)?))
+}
+
+/// (best bid + best ask) / 2.
+#[inline(always)]
+pub fn mid<B: BookSide>(book: &Lob<B>) -> Option<f64> {
+    let ((bid, _), (ask, _)) = top(book)?;
+    Some((bid + ask) / 2.0)
+}
+
+/// Best ask - best bid.
+#[inline(always)]
+pub fn spread<B: BookSide>(book: &Lob<B>) -> Option<f64> {
+    let ((bid, _), (ask, _)) = top(book)?;
+    Some(ask - bid)
+}
+
+pub fn spread_ticks<B: BookSide>(book: &Lob<B>, tick_size: f64) -> Option<f64> {
+    Some(spread(book)? / tick_size)
+}
+
+/// Spread over the mid in basis points.
+pub fn spread_bps<B: BookSide>(book: &Lob<B>) -> Option<f64> {
+    let mid = mid(book)?;
+    (mid != 0.0).then(|| spread(book).map(|spread| spread / mid * 10_000.0))?
+}
+
+/// Mid weighted toward the side with less quantity on top, where the next trade is more
+/// likely to move the price: (bid * ask qty + ask * bid qty) / (bid qty + ask qty).
+pub fn microprice<B: BookSide>(book: &Lob<B>) -> Option<f64> {
+    let ((bid, bid_qty), (ask, ask_qty)) = top(book)?;
+    let (bid_qty, ask_qty) = (bid_qty as f64, ask_qty as f64);
+    let total = bid_qty + ask_qty;
+    (total > 0.0).then(|| (bid * ask_qty + ask * bid_qty) / total)
+}
+
+/// (bid qty - ask qty) / (bid qty + ask qty) over the top `levels` of each side, from -1
+/// (only asks) to 1 (only bids).
+pub fn imbalance<B: BookSide>(book: &Lob<B>, levels: usize) -> Option<f64> {
+    let bids = side_qty(&book.bids, levels);
+    let asks = side_qty(&book.asks, levels);
+    let total = bids + asks;
+    (total > 0.0).then(|| (bids - asks) / total)
+}
+
+/// Mid of the size weighted average bid and ask prices over the top `levels` of each side.
+pub fn weighted_mid<B: BookSide>(book: &Lob<B>, levels: usize) -> Option<f64> {
+    Some((vwap(&book.bids, levels)? + vwap(&book.asks, levels)?) / 2.0)
+}
+
+/// Quantity on each side priced within `bps` basis points of the mid.
+pub fn depth_within_bps<B: BookSide>(book: &Lob<B>, bps: f64) -> Option<Depth> {
+    let mid = mid(book)?;
+    let band = mid.abs() * bps / 10_000.0;
+
+    // sides are ordered best first, stop at the first level out of the band
+    let within = |side: &B, inside: &dyn Fn(f64) -> bool| {
+        let mut qty: Qty = 0;
+        for i in 0..side.depth() {
+            match side.level(i) {
+                Some((price, level_qty)) if inside(price) => qty = qty.saturating_add(level_qty),
+                _ => break,
+            }
+        }
+        qty
+    };
+
+    Some(Depth {
+        bids: within(&book.bids, &|price| price >= mid - band),
+        asks: within(&book.asks, &|price| price <= mid + band),
+    })
+}
+
+fn side_qty<S: BookSide>(side: &S, levels: usize) -> f64 {
+    (0..levels.min(side.depth()))
+        .filter_map(|i| side.level(i))
+        .map(|(_, qty)| qty as f64)
+        .sum()
+}
+
+fn vwap<S: BookSide>(side: &S, levels: usize) -> Option<f64> {
+    let (mut notional, mut qty) = (0.0, 0.0);
+    for (price, level_qty) in (0..levels.min(side.depth())).filter_map(|i| side.level(i)) {
+        notional += price * level_qty as f64;
+        qty += level_qty as f64;
+    }
+    (qty > 0.0).then(|| notional / qty)
+}
+
+/// Every feature of one book at one point.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Analytics {
+    pub mid: Option<f64>,
+    pub microprice: Option<f64>,
+    pub imbalance: Option<f64>,
+    pub weighted_mid: Option<f64>,
+    pub spread_ticks: Option<f64>,
+    pub spread_bps: Option<f64>,
+    pub depth: Option<Depth>,
+}
+
+impl Analytics {
+    pub fn compute<B: BookSide>(book: &Lob<B>, config: &AnalyticsConfig) -> Self {
+        Self::compute_at(book, config, config.tick_size)
+    }
+
+    /// Same as `compute`, the spread counted in the tick of the book's instrument when
+    /// `instruments` has one.
+    pub fn compute_with<B: BookSide>(
+        book: &Lob<B>,
+        config: &AnalyticsConfig,
+        instruments: &Instruments,
+    ) -> Self {
+        let tick_size = instruments
+            .get(book.security_id)
+            .map_or(config.tick_size, |instrument| instrument.tick_size);
+        Self::compute_at(book, config, tick_size)
+    }
+
+    #[inline(always)]
+    fn compute_at<B: BookSide>(book: &Lob<B>, config: &AnalyticsConfig, tick_size: f64) -> Self {
+        Self {
+            mid: mid(book),
+            microprice: microprice(book),
+            imbalance: imbalance(book, config.levels),
+            weighted_mid: weighted_mid(book, config.levels),
+            spread_ticks: spread_ticks(book, tick_size),
+            spread_bps: spread_bps(book),
+            depth: depth_within_bps(book, config.depth_bps),
+        }
+    }
+}
+
+/// Incremental mode: keeps the [`Analytics`] of every security current, recomputed from
+/// the book each time the stream path builds or changes it.
+///
+/// Outside the stream path call `update` after applying a message, e.g. after each
+/// `Replay::step`.
+#[derive(Debug, Clone, Default)]
+pub struct AnalyticsObserver {
+    config: AnalyticsConfig,
+    instruments: Option<Instruments>,
+    per_security: FnvHashMap<SecurityId, Analytics>,
+    updates: u64,
+}
+
+impl AnalyticsObserver {
+    pub fn new(config: AnalyticsConfig) -> Self {
+        Self {
+            config,
+            instruments: None,
+            per_security: FnvHashMap::default(),
+            updates: 0,
+        }
+    }
+
+    /// Spreads counted in each instrument's own tick, `config.tick_size` for the rest.
+    pub fn with_instruments(mut self, instruments: Instruments) -> Self {
+        self.instruments = Some(instruments);
+        self
+    }
+
+    #[inline(always)]
+    pub fn update<B: BookSide>(&mut self, book: &Lob<B>) -> &Analytics {
+        self.updates += 1;
+        let analytics = match &self.instruments {
+            Some(instruments) => Analytics::compute_with(book, &self.config, instruments),
+            None => Analytics::compute(book, &self.config),
+        };
+        let entry = self.per_security.entry(book.security_id).or_default();
+        *entry = analytics;
+        entry
+    }
+
+    pub fn get(&self, security_id: SecurityId) -> Option<&Analytics> {
+        self.per_security.get(&security_id)
+    }
+
+    pub fn all(&self) -> &FnvHashMap<SecurityId, Analytics> {
+        &self.per_security
+    }
+
+    /// Books seen so far, one per snapshot and applied incremental.
+    pub fn updates(&self) -> u64 {
+        self.updates
+    }
+}
+
+impl<B: BookSide> StreamObserver<B> for AnalyticsObserver {
+    fn on_snapshot(&mut self, book: &Lob<B>) {
+        self.update(book);
+    }
+
+    fn on_applied(&mut self, book: &Lob<B>) {
+        self.update(book);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::*;
+use fnv::FnvHashMap;
+
+// books in two parallel vecs indexed by slot instead of a map of whole Lobs. the sides,
+// searched on every update, sit next to each other without the bookkeeping fields in
+// between, and a lookup is one array read for the usual small dense ids. slots are handed
+// out in insertion order and never move or get reused, a map rehash moved whole books
+
+/// Ids below this are indexed by a flat array, the rest go through a hash map.
+pub const DIRECT_IDS: SecurityId = 1 << 16;
+
+const NO_SLOT: u32 = u32::MAX;
+
+/// The part of a book touched by every update.
+#[derive(Clone)]
+pub struct BookSides<B: BookSide> {
+    pub bids: B,
+    pub asks: B,
+}
+
+impl<B: BookSide> BookSides<B> {
+    /// Same as `Lob::update`.
+    #[inline(always)]
+    pub fn update(&mut self, side: Side, price: f64, qty: Qty) -> LevelChange {
+        let book_side = match side {
+            Side::B => &mut self.bids,
+            Side::A => &mut self.asks,
+        };
+        if qty == 0 {
+            book_side.remove_l(price)
+        } else {
+            book_side.update_l(price, qty)
+        }
+    }
+}
+
+/// The rest of a `Lob`, written once per message and read when books are handed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookMeta {
+    pub security_id: SecurityId,
+    pub last_update_seq: Option<SeqNo>,
+    pub last_exchange_ts: Option<u64>,
+    pub last_apply_ts: Option<u64>,
+}
+
+// security id -> slot
+#[derive(Clone, Default)]
+struct SlotIndex {
+    direct: Vec<u32>,
+    sparse: FnvHashMap<SecurityId, u32>,
+}
+
+impl SlotIndex {
+    #[inline(always)]
+    fn get(&self, security_id: SecurityId) -> Option<usize> {
+        if security_id < DIRECT_IDS {
+            match self.direct.get(security_id as usize) {
+                Some(&slot) if slot != NO_SLOT => Some(slot as usize),
+                _ => None,
+            }
+        } else {
+            self.sparse.get(&security_id).map(|&slot| slot as usize)
+        }
+    }
+
+    fn insert(&mut self, security_id: SecurityId, slot: u32) {
+        if security_id < DIRECT_IDS {
+            let i = security_id as usize;
+            if i >= self.direct.len() {
+                // grows to the highest id seen, at most DIRECT_IDS entries
+                self.direct.resize(i + 1, NO_SLOT);
+            }
+            self.direct[i] = slot;
+        } else {
+            self.sparse.insert(security_id, slot);
+        }
+    }
+}
+
+/// Books of many securities, sides and bookkeeping stored apart.
+#[derive(Clone)]
+pub struct BookArena<B: BookSide> {
+    hot: Vec<BookSides<B>>,
+    cold: Vec<BookMeta>,
+    index: SlotIndex,
+}
+
+impl<B: BookSide> BookArena<B> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Room for `books` books before the vecs grow.
+    pub fn with_capacity(books: usize) -> Self {
+        Self {
+            hot: Vec::with_capacity(books),
+            cold: Vec::with_capacity(books),
+            index: SlotIndex::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hot.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hot.is_empty()
+    }
+
+    /// Slot of the security's book, valid for the life of the arena.
+    #[inline(always)]
+    pub fn slot(&self, security_id: SecurityId) -> Option<usize> {
+        self.index.get(security_id)
+    }
+
+    /// Stores `book`, replacing the security's book if it has one, and returns its slot.
+    pub fn insert(&mut self, book: Lob<B>) -> usize {
+        let meta = BookMeta {
+            security_id: book.security_id,
+            last_update_seq: book.last_update_seq,
+            last_exchange_ts: book.last_exchange_ts,
+            last_apply_ts: book.last_apply_ts,
+        };
+        let sides = BookSides {
+            bids: book.bids,
+            asks: book.asks,
+        };
+
+        if let Some(slot) = self.slot(meta.security_id) {
+            self.hot[slot] = sides;
+            self.cold[slot] = meta;
+            return slot;
+        }
+
+        let slot = self.hot.len();
+        // NO_SLOT is never a real slot
+        assert!(slot < NO_SLOT as usize, "book arena is full");
+        self.hot.push(sides);
+        self.cold.push(meta);
+        self.index.insert(meta.security_id, slot as u32);
+        slot
+    }
+
+    #[inline(always)]
+    pub fn sides(&self, slot: usize) -> &BookSides<B> {
+        &self.hot[slot]
+    }
+
+    #[inline(always)]
+    pub fn sides_mut(&mut self, slot: usize) -> &mut BookSides<B> {
+        &mut self.hot[slot]
+    }
+
+    #[inline(always)]
+    pub fn meta(&self, slot: usize) -> &BookMeta {
+        &self.cold[slot]
+    }
+
+    #[inline(always)]
+    pub fn meta_mut(&mut self, slot: usize) -> &mut BookMeta {
+        &mut self.cold[slot]
+    }
+
+    /// Copy of the security's book put back together.
+    pub fn book(&self, security_id: SecurityId) -> Option<Lob<B>>
+    where
+        B: Clone,
+    {
+        let slot = self.slot(security_id)?;
+        Some(join(self.hot[slot].clone(), &self.cold[slot]))
+    }
+
+    /// Books keyed by security, what the processors return.
+    pub fn into_books(self) -> FnvHashMap<SecurityId, Lob<B>> {
+        let mut books = FnvHashMap::with_capacity_and_hasher(self.hot.len(), Default::default());
+        for (sides, meta) in self.hot.into_iter().zip(self.cold.iter()) {
+            books.insert(meta.security_id, join(sides, meta));
+        }
+        books
+    }
+}
+
+impl<B: BookSide> Default for BookArena<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn join<B: BookSide>(sides: BookSides<B>, meta: &BookMeta) -> Lob<B> {
+    Lob {
+        security_id: meta.security_id,
+        bids: sides.bids,
+        asks: sides.asks,
+        last_update_seq: meta.last_update_seq,
+        last_exchange_ts: meta.last_exchange_ts,
+        last_apply_ts: meta.last_apply_ts,
+    }
+}
@@ -1,7 +1,14 @@
+use crate::instruments::{self, Instrument, Instruments, Route};
+#[cfg(feature = "latency-metrics")]
+use crate::latency::{self, LatencyRecorder};
+use crate::stats::{ProcessingStats, StatsRecorder};
 use crate::*;
 use anyhow::{bail, Context, Result};
 use crossbeam::channel::Receiver;
 use memmap2::Mmap;
+#[cfg(feature = "latency-metrics")]
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fs::File;
 
@@ -14,30 +21,36 @@ pub struct Basic {
 
 impl Basic {
     pub fn new(is_b: bool) -> Self {
+        Self::with_capacity(is_b, 100)
+    }
+
+    pub fn with_capacity(is_b: bool, levels: usize) -> Self {
         Self {
-            levels: Vec::with_capacity(100),
+            levels: Vec::with_capacity(levels),
             is_b,
         }
     }
 
     pub fn find_position(&self, price: f64) -> Result<usize, usize> {
+        // NaN never matches a level, the processors skip it before it gets here
         if self.is_b {
             // h to l for bids
             self.levels
-                .binary_search_by(|level| price.partial_cmp(&level.price).unwrap())
+                .binary_search_by(|level| price.partial_cmp(&level.price).unwrap_or(Ordering::Less))
         } else {
             // l to h for asks
             self.levels
-                .binary_search_by(|level| level.price.partial_cmp(&price).unwrap())
+                .binary_search_by(|level| level.price.partial_cmp(&price).unwrap_or(Ordering::Less))
         }
     }
 }
 
 impl BookSide for Basic {
-    fn update_l(&mut self, price: f64, qty: Qty) {
+    fn update_l(&mut self, price: f64, qty: Qty) -> LevelChange {
         match self.find_position(price) {
             Ok(pos) => {
                 self.levels[pos].quantity = qty;
+                LevelChange::Updated
             }
             Err(pos) => {
                 //simple insert with vec shifting
@@ -48,27 +61,74 @@ impl BookSide for Basic {
                         quantity: qty,
                     },
                 );
+                LevelChange::Inserted
             }
         }
     }
 
-    fn remove_l(&mut self, price: f64) {
+    fn remove_l(&mut self, price: f64) -> LevelChange {
         if let Ok(pos) = self.find_position(price) {
             self.levels.remove(pos);
+            LevelChange::Removed
+        } else {
+            LevelChange::NotFound
         }
     }
 
     fn get_l(&self) -> Vec<Level> {
         self.levels.clone()
     }
+
+    fn depth(&self) -> usize {
+        self.levels.len()
+    }
+
+    fn level(&self, i: usize) -> Option<(f64, Qty)> {
+        self.levels
+            .get(i)
+            .map(|level| (level.price, level.quantity))
+    }
 }
 
-pub struct BasicProcessor;
+pub struct BasicProcessor {
+    // decode and apply time per message, only with the latency-metrics feature
+    #[cfg(feature = "latency-metrics")]
+    latency: RefCell<LatencyRecorder>,
+    instruments: Option<Instruments>,
+}
 
 //simple hashmap for basic implementations
 impl BasicProcessor {
     pub fn new() -> Self {
-        Self
+        Self {
+            #[cfg(feature = "latency-metrics")]
+            latency: RefCell::new(LatencyRecorder::new()),
+            instruments: None,
+        }
+    }
+
+    /// Checks the file paths against reference data: every instrument starts with an
+    /// empty book sized for its max depth, off tick updates and snapshot levels are
+    /// skipped and unknown securities follow `instruments.unknown`. The stream path
+    /// refuses it.
+    pub fn with_instruments(mut self, instruments: Instruments) -> Self {
+        self.instruments = Some(instruments);
+        self
+    }
+
+    pub fn instruments(&self) -> Option<&Instruments> {
+        self.instruments.as_ref()
+    }
+
+    /// Decode and apply timings of every message processed so far.
+    #[cfg(feature = "latency-metrics")]
+    pub fn latency(&self) -> LatencyRecorder {
+        self.latency.borrow().clone()
+    }
+
+    #[cfg(feature = "latency-metrics")]
+    pub fn reset_latency(&self) {
+        self.latency.borrow_mut().reset();
     }
 
     pub fn process_files(
@@ -76,6 +136,57 @@ impl BasicProcessor {
         snapshot_path: &str,
         incremental_path: &str,
     ) -> Result<HashMap<SecurityId, Lob<Basic>>> {
+        let (books, _) = self.process_files_impl(snapshot_path, incremental_path, &mut ())?;
+        Ok(books)
+    }
+
+    /// Same as `process_files`, also returning the books of unknown securities kept apart
+    /// under `UnknownPolicy::Quarantine`.
+    #[allow(clippy::type_complexity)]
+    pub fn process_files_with_quarantine(
+        &self,
+        snapshot_path: &str,
+        incremental_path: &str,
+    ) -> Result<(
+        HashMap<SecurityId, Lob<Basic>>,
+        HashMap<SecurityId, Lob<Basic>>,
+    )> {
+        self.process_files_impl(snapshot_path, incremental_path, &mut ())
+    }
+
+    /// Same as `process_files`, also counting what happened to every message.
+    ///
+    /// Updates with an invalid side are counted and skipped instead of failing the run.
+    pub fn process_files_with_stats(
+        &self,
+        snapshot_path: &str,
+        incremental_path: &str,
+    ) -> Result<(HashMap<SecurityId, Lob<Basic>>, ProcessingStats)> {
+        let mut stats = ProcessingStats::new();
+        let (books, _) = self.process_files_impl(snapshot_path, incremental_path, &mut stats)?;
+        Ok((books, stats))
+    }
+
+    /// Same as `process_files` on buffers already in memory.
+    pub fn process_bytes(
+        &self,
+        snapshot: &[u8],
+        incremental: &[u8],
+    ) -> Result<HashMap<SecurityId, Lob<Basic>>> {
+        let (books, _) = self.process_bytes_impl(snapshot, incremental, &mut ())?;
+        Ok(books)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn process_files_impl<S: StatsRecorder>(
+        &self,
+        snapshot_path: &str,
+        incremental_path: &str,
+        stats: &mut S,
+    ) -> Result<(
+        HashMap<SecurityId, Lob<Basic>>,
+        HashMap<SecurityId, Lob<Basic>>,
+    )> {
         let snapshot_file = File::open(snapshot_path)
             .with_context(|| format!("Failed to open snapshot file: {}", snapshot_path))?;
         let incremental_file = File::open(incremental_path)
@@ -84,50 +195,124 @@ impl BasicProcessor {
         let snapshot_mmap = unsafe { Mmap::map(&snapshot_file)? };
         let incremental_mmap = unsafe { Mmap::map(&incremental_file)? };
 
+        self.process_bytes_impl(&snapshot_mmap, &incremental_mmap, stats)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn process_bytes_impl<S: StatsRecorder>(
+        &self,
+        snapshot: &[u8],
+        incremental: &[u8],
+        stats: &mut S,
+    ) -> Result<(
+        HashMap<SecurityId, Lob<Basic>>,
+        HashMap<SecurityId, Lob<Basic>>,
+    )> {
+        let instruments = self.instruments.as_ref();
         // get latest seq_no
         let mut books = HashMap::new();
+        let mut quarantined = HashMap::new();
         let mut offset = 0;
         let mut max_snapshot_seq = 0u64;
 
-        while offset + SNAPSHOT_SIZE <= snapshot_mmap.len() {
-            let (security_id, seq_no, book) = self.parse_snapshot(&snapshot_mmap, offset)?;
+        // known securities have a book before any message
+        if let Some(instruments) = instruments {
+            books.reserve(instruments.len());
+            for instrument in instruments.iter() {
+                books.insert(instrument.security_id, instrument_book(instrument));
+            }
+        }
+
+        while offset + SNAPSHOT_SIZE <= snapshot.len() {
+            let (security_id, seq_no, book) = self.parse_snapshot(snapshot, offset)?;
             max_snapshot_seq = max_snapshot_seq.max(seq_no);
-            books.insert(security_id, book);
+            stats.snapshot(security_id);
+            match instruments::route(instruments, security_id) {
+                Route::Book(Some(instrument)) => {
+                    let target = books
+                        .entry(security_id)
+                        .or_insert_with(|| instrument_book(instrument));
+                    fill_on_tick(target, book, instrument, stats);
+                }
+                Route::Book(None) => {
+                    books.insert(security_id, book);
+                }
+                Route::Quarantine => {
+                    stats.unknown(security_id);
+                    quarantined.insert(security_id, book);
+                }
+                Route::Reject => stats.unknown(security_id),
+            }
             offset += SNAPSHOT_SIZE;
         }
 
         // offset for mmaped file
         offset = 0;
 
-        while offset + INCREMENTAL_HEADER_SIZE <= incremental_mmap.len() {
-            let (new_offset, security_id, seq_no, updates) =
-                self.parse_incremental(&incremental_mmap, offset)?;
+        while offset + INCREMENTAL_HEADER_SIZE <= incremental.len() {
+            #[cfg(feature = "latency-metrics")]
+            let start = latency::now_ticks();
+
+            let (new_offset, timestamp, security_id, seq_no, updates) =
+                self.parse_incremental(incremental, offset)?;
+
+            #[cfg(feature = "latency-metrics")]
+            let decoded = latency::now_ticks();
+
+            stats.incremental(security_id);
 
             // check for ser_no
             if seq_no > max_snapshot_seq {
-                if let Some(book) = books.get_mut(&security_id) {
-                    // Apply updates
-                    for (side, price, qty) in updates {
-                        book.update(side, price, qty);
+                let target = match instruments::route(instruments, security_id) {
+                    Route::Book(instrument) => Some((&mut books, instrument)),
+                    Route::Quarantine => {
+                        stats.unknown(security_id);
+                        Some((&mut quarantined, None))
                     }
-                    book.last_update_seq = Some(seq_no);
-                } else {
+                    Route::Reject => {
+                        stats.unknown(security_id);
+                        None
+                    }
+                };
+
+                if let Some((books, instrument)) = target {
                     // Create new book for securities not in snapshot
-                    let mut new_book = Lob::new(security_id, Basic::new(true), Basic::new(false));
+                    let book = books.entry(security_id).or_insert_with(|| {
+                        stats.book_created(security_id);
+                        Lob::new(security_id, Basic::new(true), Basic::new(false))
+                    });
 
+                    // Apply updates
                     for (side, price, qty) in updates {
-                        new_book.update(side, price, qty);
+                        let side = match Side::of_update(side, price) {
+                            Ok(side) => side,
+                            Err(invalid) => {
+                                stats.skip_invalid(security_id, invalid)?;
+                                continue;
+                            }
+                        };
+                        if instrument.is_some_and(|instrument| !instrument.on_tick(price)) {
+                            stats.off_tick(security_id);
+                            continue;
+                        }
+                        stats.change(security_id, book.update(side, price, qty));
                     }
-                    new_book.last_update_seq = Some(seq_no);
-
-                    books.insert(security_id, new_book);
+                    book.last_update_seq = Some(seq_no);
+                    book.last_exchange_ts = Some(timestamp);
                 }
+            } else {
+                stats.stale(security_id, updates.len() as u64);
             }
 
+            #[cfg(feature = "latency-metrics")]
+            self.latency
+                .borrow_mut()
+                .record(start, decoded, latency::now_ticks());
+
             offset = new_offset;
         }
 
-        Ok(books)
+        Ok((books, quarantined))
     }
 
     pub fn process_stream(
@@ -135,48 +320,131 @@ impl BasicProcessor {
         receiver: Receiver<StreamMessage>,
         processor_core: usize,
     ) -> Result<HashMap<SecurityId, Lob<Basic>>> {
+        self.process_stream_with(receiver, Some(processor_core), &mut ())
+    }
+
+    /// Same as `process_stream`, `observer` sees every book right after it changes.
+    ///
+    /// The thread is pinned to `processor_core` if given, left to the scheduler otherwise.
+    /// Fails on a processor built `with_instruments`, reference data is only checked on
+    /// the file paths.
+    pub fn process_stream_with<O: StreamObserver<Basic>>(
+        &self,
+        receiver: Receiver<StreamMessage>,
+        processor_core: Option<usize>,
+        observer: &mut O,
+    ) -> Result<HashMap<SecurityId, Lob<Basic>>> {
+        if self.instruments.is_some() {
+            bail!("Instrument reference data isn't supported on the stream path");
+        }
         //busy polling for channel read thread
-        core_affinity::set_for_current(core_affinity::CoreId { id: processor_core });
+        if let Some(id) = processor_core {
+            core_affinity::set_for_current(core_affinity::CoreId { id });
+        }
+        // the wall clock is only read for observers that use it
+        let stamp_apply = observer.wants_apply_ts();
 
         let mut books = HashMap::new();
         // imaginary protocol sends snapshot first, then incrementals
         let mut is_snapshot = true;
         let mut max_snapshot_seq = 0u64;
+        let mut skips = StreamSkips::default();
 
         loop {
             match receiver.recv() {
-                Ok(StreamMessage::Data(msg_type, data)) => match msg_type {
-                    MessageType::Snapshot if is_snapshot => {
-                        let (security_id, seq_no, book) = self.parse_snapshot(&data, 0)?;
-                        max_snapshot_seq = max_snapshot_seq.max(seq_no);
-                        books.insert(security_id, book);
+                Ok(StreamMessage::Data(msg_type, data)) => {
+                    observer.on_message(msg_type);
+
+                    // wrong length, skip it instead of failing the whole stream
+                    if let Err(rejection) = codec::check_datagram(msg_type, &data) {
+                        skips.rejected(observer, msg_type, rejection);
+                        continue;
                     }
-                    MessageType::Incremental if !is_snapshot => {
-                        let (_, security_id, seq_no, updates) = self.parse_incremental(&data, 0)?;
 
-                        if seq_no > max_snapshot_seq {
-                            if let Some(book) = books.get_mut(&security_id) {
-                                for (side, price, qty) in updates {
-                                    book.update(side, price, qty);
-                                }
-                                book.last_update_seq = Some(seq_no);
-                                //if sequence is broken assume this price\qtu as new
-                            } else {
-                                let mut new_book =
-                                    Lob::new(security_id, Basic::new(true), Basic::new(false));
+                    match msg_type {
+                        MessageType::Snapshot if is_snapshot => {
+                            #[cfg(feature = "latency-metrics")]
+                            let start = latency::now_ticks();
+
+                            let (security_id, seq_no, mut book) = self.parse_snapshot(&data, 0)?;
+
+                            #[cfg(feature = "latency-metrics")]
+                            let decoded = latency::now_ticks();
 
+                            max_snapshot_seq = max_snapshot_seq.max(seq_no);
+                            if stamp_apply {
+                                book.last_apply_ts = Some(unix_nanos());
+                            }
+                            observer.on_snapshot(&book);
+                            books.insert(security_id, book);
+
+                            #[cfg(feature = "latency-metrics")]
+                            self.latency
+                                .borrow_mut()
+                                .record(start, decoded, latency::now_ticks());
+                        }
+                        MessageType::Incremental if !is_snapshot => {
+                            #[cfg(feature = "latency-metrics")]
+                            let start = latency::now_ticks();
+
+                            let (_, timestamp, security_id, seq_no, updates) =
+                                self.parse_incremental(&data, 0)?;
+
+                            #[cfg(feature = "latency-metrics")]
+                            let decoded = latency::now_ticks();
+
+                            if seq_no > max_snapshot_seq {
+                                // broken updates are skipped and counted, not failing the stream
+                                let mut valid = Vec::with_capacity(updates.len());
                                 for (side, price, qty) in updates {
-                                    new_book.update(side, price, qty);
+                                    match Side::of_update(side, price) {
+                                        Ok(side) => valid.push((side, price, qty)),
+                                        Err(invalid) => {
+                                            skips.invalid_update(observer, security_id, invalid)
+                                        }
+                                    }
+                                }
+                                let updates = valid;
+
+                                if let Some(book) = books.get_mut(&security_id) {
+                                    for (side, price, qty) in updates {
+                                        book.update(side, price, qty);
+                                    }
+                                    book.last_update_seq = Some(seq_no);
+                                    book.last_exchange_ts = Some(timestamp);
+                                    if stamp_apply {
+                                        book.last_apply_ts = Some(unix_nanos());
+                                    }
+                                    observer.on_applied(book);
+                                    //if sequence is broken assume this price\qtu as new
+                                } else {
+                                    let mut new_book =
+                                        Lob::new(security_id, Basic::new(true), Basic::new(false));
+
+                                    for (side, price, qty) in updates {
+                                        new_book.update(side, price, qty);
+                                    }
+                                    new_book.last_update_seq = Some(seq_no);
+                                    new_book.last_exchange_ts = Some(timestamp);
+                                    if stamp_apply {
+                                        new_book.last_apply_ts = Some(unix_nanos());
+                                    }
+                                    observer.on_applied(&new_book);
+
+                                    books.insert(security_id, new_book);
                                 }
-                                new_book.last_update_seq = Some(seq_no);
-
-                                books.insert(security_id, new_book);
                             }
+
+                            #[cfg(feature = "latency-metrics")]
+                            self.latency
+                                .borrow_mut()
+                                .record(start, decoded, latency::now_ticks());
                         }
+                        _ => {}
                     }
-                    _ => {}
-                },
+                }
                 Ok(StreamMessage::EndOfSnapshot) => {
+                    observer.on_message(MessageType::EndOfSnapshot);
                     is_snapshot = false;
                     eprintln!("Snapshot phase completed, max_seq: {}", max_snapshot_seq);
                 }
@@ -184,6 +452,8 @@ impl BasicProcessor {
             }
         }
 
+        skips.report();
+
         Ok(books)
     }
 
@@ -192,10 +462,16 @@ impl BasicProcessor {
         data: &[u8],
         offset: usize,
     ) -> Result<(SecurityId, SeqNo, Lob<Basic>)> {
+        if offset + SNAPSHOT_SIZE > data.len() {
+            bail!(
+                "Not enough data for snapshot: {} bytes",
+                data.len() - offset
+            );
+        }
         let mut pos = offset;
 
         //Timestamp	u64	Timestamp in milliseconds
-        let _timestamp = u64::from_le_bytes(data[pos..pos + 8].try_into()?);
+        let timestamp = u64::from_le_bytes(data[pos..pos + 8].try_into()?);
         pos += 8;
 
         //SeqNo	u64	Sequence number of the last processed incremental
@@ -209,6 +485,7 @@ impl BasicProcessor {
         let mut book = Lob::new(security_id, Basic::new(true), Basic::new(false));
         //latest seq_no
         book.last_update_seq = Some(seq_no);
+        book.last_exchange_ts = Some(timestamp);
 
         let mut b_levels = Vec::new();
         let mut a_levels = Vec::new();
@@ -263,13 +540,19 @@ impl BasicProcessor {
         &self,
         data: &[u8],
         offset: usize,
-    ) -> Result<(usize, SecurityId, SeqNo, Vec<(Side, f64, Qty)>)> {
+    ) -> Result<(usize, u64, SecurityId, SeqNo, Vec<(u8, f64, Qty)>)> {
+        if offset + INCREMENTAL_HEADER_SIZE > data.len() {
+            bail!(
+                "Not enough data for incremental header: {} bytes",
+                data.len() - offset
+            );
+        }
         let mut pos = offset;
 
         // read with checks
 
         //Timestamp	u64	Timestamp in milliseconds
-        let _timestamp = u64::from_le_bytes(data[pos..pos + 8].try_into()?);
+        let timestamp = u64::from_le_bytes(data[pos..pos + 8].try_into()?);
         pos += 8;
 
         //SeqNo	u64	Sequence number
@@ -284,22 +567,19 @@ impl BasicProcessor {
         let num_updates = u64::from_le_bytes(data[pos..pos + 8].try_into()?);
         pos += 8;
 
-        //broken update edge case
-        let updates_size = num_updates as usize * INCREMENTAL_SIZE;
-        if pos + updates_size > data.len() {
-            bail!("File is broken, not enough data");
+        //broken update edge case, num_updates can be anything
+        match codec::incremental_record_size(num_updates) {
+            Some(size) if size <= data.len() - offset => {}
+            _ => bail!("File is broken, not enough data"),
         }
         // alloc but for basic implementation ok
         let mut updates = Vec::with_capacity(num_updates as usize);
 
+        // sides and prices are checked by the callers, only applied updates need valid ones
         for _ in 0..num_updates {
-            let side_byte = data[pos];
+            let side = data[pos];
             pos += 1;
 
-            //broken side check
-            let side =
-                Side::from_u8(side_byte).with_context(|| format!("Invalid side: {}", side_byte))?;
-
             let price_bits = u64::from_le_bytes(data[pos..pos + 8].try_into()?);
             let price = f64::from_bits(price_bits);
             pos += 8;
@@ -310,8 +590,39 @@ impl BasicProcessor {
             updates.push((side, price, qty));
         }
 
-        Ok((pos, security_id, seq_no, updates))
+        Ok((pos, timestamp, security_id, seq_no, updates))
+    }
+}
+
+// empty book with both sides preallocated at the instrument's max depth
+fn instrument_book(instrument: &Instrument) -> Lob<Basic> {
+    let new_side = |is_b| Basic::with_capacity(is_b, instrument.max_depth);
+    Lob::new(instrument.security_id, new_side(true), new_side(false))
+}
+
+// snapshot levels into the instrument's book, keeping its allocation, off tick levels
+// skipped the same way off tick updates are
+fn fill_on_tick<S: StatsRecorder>(
+    book: &mut Lob<Basic>,
+    snapshot: Lob<Basic>,
+    instrument: &Instrument,
+    stats: &mut S,
+) {
+    for (side, levels) in [
+        (&mut book.bids, snapshot.bids.levels),
+        (&mut book.asks, snapshot.asks.levels),
+    ] {
+        side.levels.clear();
+        for level in levels {
+            if instrument.on_tick(level.price) {
+                side.levels.push(level);
+            } else {
+                stats.off_tick(book.security_id);
+            }
+        }
     }
+    book.last_update_seq = snapshot.last_update_seq;
+    book.last_exchange_ts = snapshot.last_exchange_ts;
 }
 
 impl Default for BasicProcessor {
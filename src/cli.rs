@@ -0,0 +1,785 @@
+use anyhow::{anyhow, bail, Context, Result};
+use fnv::FnvHashSet;
+use lob_processor::faults::FaultConfig;
+use lob_processor::generator::GeneratorConfig;
+use lob_processor::inspect::InspectFilter;
+use lob_processor::instruments::{Instruments, UnknownPolicy};
+use lob_processor::replay::Until;
+use lob_processor::SecurityId;
+use std::str::FromStr;
+
+// hand rolled argument parsing, the binary has no dependencies beyond the library's
+
+pub const USAGE: &str = "\
+Usage: lob_processor <command> [options]
+
+Commands:
+  process  <snapshot.bin> <incremental.bin>   build books and print them
+  stats    <snapshot.bin> <incremental.bin>   per security processing counters
+  inspect  [--snapshot <file>] [--incremental <file>]
+                                              dump decoded records
+  validate [--snapshot <file>] [--incremental <file>]
+                                              check files without building books
+  convert  <snapshot.bin> <incremental.bin> --output <file>
+                                              write the final books as a snapshot file
+  generate <snapshot.bin> <incremental.bin>   write a synthetic feed
+  replay   <snapshot.bin> <incremental.bin>   replay in timestamp order and print books
+  diff     <from_snapshot.bin> <to_snapshot.bin>
+                                              level changes between two snapshot files
+  check    <snapshot.bin> <incremental.bin>   run both backends and compare their books
+
+Options:
+  --backend basic|improved   processor to use (default improved)
+  --security <id,id,..>      only these securities
+  --symbol <sym,sym,..>      only these symbols, needs --instruments (process, stats,
+                             convert), combines with --security
+  --depth <n>                print at most n levels per side, for generate the depth
+                             books grow to (default 10)
+  --format text|json|csv     book output format (process, replay), json is one line
+                             per security, prices are printed in full precision
+  --timestamps               print exchange and apply timestamps (process, replay)
+  --latency                  run the stream path and report latency (process)
+  --metrics <addr>           run the stream path and serve prometheus metrics on
+                             addr at /metrics, keeps serving after the books are
+                             printed until killed (process, needs the metrics feature)
+  --core <n>                 pin the stream path to this core (process --latency or
+                             --metrics, default unpinned)
+  --instruments <file>       instrument reference file: books for every instrument up
+                             front, off tick updates skipped, symbols printed next to
+                             ids (process, stats, convert), for generate the file to write
+  --unknown create|reject|quarantine
+                             securities missing from --instruments get a book, are
+                             skipped, or are built apart and reported (default create)
+  --output <file>            output file (convert, diff)
+  --securities <n>           number of securities (generate, default 10)
+  --messages <n>             number of incrementals (generate, default 1000)
+  --seed <n>                 same seed, same files (generate, default 1)
+  --hot <n>                  securities 1..=n get most of the traffic (generate, default 2)
+  --drop, --duplicate, --reorder, --corrupt, --invalid-side, --nan-price <rate>
+                             damage this share of incrementals (generate)
+  --truncate, --truncate-snapshot
+                             cut the last record of the file short (generate)
+  --truth <file>             csv of the books a processor should reach without the
+                             damage (generate)
+  --fault-log <file>         csv of every injected fault (generate)
+  --until-ts <ms>            stop before the first message after this Timestamp (replay)
+  --until-seq <seq>          stop before the first message after this SeqNo (replay)
+  --speed <x>                pace the replay at x times the recorded rate (replay)
+  --from-seq, --to-seq <seq> only records in this SeqNo range (inspect)
+  --from-ts, --to-ts <ms>    only records in this Timestamp range (inspect)
+  --max-issues <n>           list at most n issues, all are counted (validate, default 100)
+  --anomalies                only records with an invalid side, NaN price or zero
+                             price with qty (inspect)
+
+Running with two paths and no command is the same as `process`.
+
+Exit codes: 0 ok, 1 validate found issues or check found mismatches, 2 bad arguments or the command failed.";
+
+// options that never take a value
+const SWITCHES: [&str; 5] = [
+    "timestamps",
+    "latency",
+    "anomalies",
+    "truncate",
+    "truncate-snapshot",
+];
+
+const COMMANDS: [&str; 9] = [
+    "process", "stats", "inspect", "validate", "convert", "generate", "replay", "diff", "check",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Basic,
+    Improved,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "basic" => Ok(Backend::Basic),
+            "improved" => Ok(Backend::Improved),
+            _ => bail!("Unknown backend '{}', expected basic or improved", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    /// One JSON object per security per line.
+    Json,
+    /// security,side,level,price,qty,seq
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => bail!("Unknown format '{}', expected text, json or csv", s),
+        }
+    }
+}
+
+/// Securities selected with `--security` and `--symbol`, everything when both are empty.
+#[derive(Debug, Clone, Default)]
+pub struct SecurityFilter {
+    ids: Option<FnvHashSet<SecurityId>>,
+    // not matched until resolve turns them into ids
+    symbols: Vec<String>,
+}
+
+impl SecurityFilter {
+    fn parse(list: &str) -> Result<Self> {
+        let ids = list
+            .split(',')
+            .map(|id| {
+                id.trim()
+                    .parse::<SecurityId>()
+                    .with_context(|| format!("Invalid security id '{}'", id))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            ids: Some(ids),
+            symbols: Vec::new(),
+        })
+    }
+
+    /// Adds the ids of the `--symbol` symbols, fails on one the instruments don't list.
+    pub fn resolve(&mut self, instruments: Option<&Instruments>) -> Result<()> {
+        if self.symbols.is_empty() {
+            return Ok(());
+        }
+        let Some(instruments) = instruments else {
+            bail!("--symbol needs --instruments <file>");
+        };
+
+        let ids = self.ids.get_or_insert_with(FnvHashSet::default);
+        for symbol in self.symbols.drain(..) {
+            let instrument = instruments
+                .by_symbol(&symbol)
+                .with_context(|| format!("Unknown symbol '{}'", symbol))?;
+            ids.insert(instrument.security_id);
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    pub fn matches(&self, security_id: SecurityId) -> bool {
+        self.ids
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&security_id))
+    }
+}
+
+/// How books are selected and printed.
+#[derive(Debug, Clone)]
+pub struct BookOptions {
+    pub backend: Backend,
+    pub filter: SecurityFilter,
+    pub depth: Option<usize>,
+    pub format: Format,
+    pub timestamps: bool,
+}
+
+/// Instrument file given with `--instruments` and the `--unknown` policy.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub path: String,
+    pub unknown: UnknownPolicy,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Process {
+        snapshot: String,
+        incremental: String,
+        books: BookOptions,
+        latency: bool,
+        /// Address to serve metrics on, runs the stream path.
+        metrics: Option<String>,
+        /// Core the stream path is pinned to, unpinned if `None`.
+        core: Option<usize>,
+        reference: Option<Reference>,
+    },
+    Stats {
+        snapshot: String,
+        incremental: String,
+        backend: Backend,
+        filter: SecurityFilter,
+        reference: Option<Reference>,
+    },
+    Inspect {
+        snapshot: Option<String>,
+        incremental: Option<String>,
+        filter: InspectFilter,
+    },
+    Validate {
+        snapshot: Option<String>,
+        incremental: Option<String>,
+        max_issues: usize,
+    },
+    Convert {
+        snapshot: String,
+        incremental: String,
+        output: String,
+        backend: Backend,
+        filter: SecurityFilter,
+        reference: Option<Reference>,
+    },
+    Generate {
+        snapshot: String,
+        incremental: String,
+        config: GeneratorConfig,
+        faults: Option<FaultConfig>,
+        truth: Option<String>,
+        fault_log: Option<String>,
+        instruments: Option<String>,
+    },
+    Replay {
+        snapshot: String,
+        incremental: String,
+        books: BookOptions,
+        until: Option<Until>,
+        speed: Option<f64>,
+    },
+    Diff {
+        from: String,
+        to: String,
+        output: Option<String>,
+        backend: Backend,
+        filter: SecurityFilter,
+    },
+    Check {
+        snapshot: String,
+        incremental: String,
+    },
+    Help,
+}
+
+impl Command {
+    /// Parses everything after the program name.
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let Some(first) = args.first() else {
+            return Ok(Command::Help);
+        };
+
+        if first == "help" || first == "--help" || first == "-h" {
+            return Ok(Command::Help);
+        }
+
+        // old form: two paths and flags
+        let (name, rest) = if COMMANDS.contains(&first.as_str()) {
+            (first.as_str(), &args[1..])
+        } else {
+            ("process", args)
+        };
+
+        let mut args = Args::parse(rest)?;
+
+        let command = match name {
+            "process" => {
+                let [snapshot, incremental] = args.paths(["snapshot.bin", "incremental.bin"])?;
+                let latency = args.switch("latency")?;
+                let metrics = args.value("metrics")?;
+                let core = args.parse_value("core")?;
+                let reference = args.reference()?;
+                if metrics.is_some() && !cfg!(feature = "metrics") {
+                    bail!("--metrics needs a build with the metrics feature");
+                }
+                let stream = latency || metrics.is_some();
+                if stream && reference.is_some() {
+                    bail!(
+                        "--instruments only applies to the file path, not --latency or --metrics"
+                    );
+                }
+                if core.is_some() && !stream {
+                    bail!(
+                        "--core only applies to the stream path, use it with --latency or --metrics"
+                    );
+                }
+                Command::Process {
+                    snapshot,
+                    incremental,
+                    books: args.book_options()?,
+                    latency,
+                    metrics,
+                    core,
+                    reference,
+                }
+            }
+            "stats" => {
+                let [snapshot, incremental] = args.paths(["snapshot.bin", "incremental.bin"])?;
+                Command::Stats {
+                    snapshot,
+                    incremental,
+                    backend: args.backend()?,
+                    filter: args.filter()?,
+                    reference: args.reference()?,
+                }
+            }
+            "inspect" => {
+                args.paths([])?;
+                let (snapshot, incremental) = args.input_files()?;
+                let mut securities = args.filter()?;
+                securities.resolve(None)?;
+                Command::Inspect {
+                    snapshot,
+                    incremental,
+                    filter: InspectFilter {
+                        securities: securities.ids,
+                        min_seq: args.parse_value("from-seq")?,
+                        max_seq: args.parse_value("to-seq")?,
+                        min_timestamp: args.parse_value("from-ts")?,
+                        max_timestamp: args.parse_value("to-ts")?,
+                        anomalies_only: args.switch("anomalies")?,
+                    },
+                }
+            }
+            "validate" => {
+                args.paths([])?;
+                let (snapshot, incremental) = args.input_files()?;
+                Command::Validate {
+                    snapshot,
+                    incremental,
+                    max_issues: args.parse_value("max-issues")?.unwrap_or(100),
+                }
+            }
+            "convert" => {
+                let [snapshot, incremental] = args.paths(["snapshot.bin", "incremental.bin"])?;
+                Command::Convert {
+                    snapshot,
+                    incremental,
+                    output: args
+                        .value("output")?
+                        .ok_or_else(|| anyhow!("convert needs --output <file>"))?,
+                    backend: args.backend()?,
+                    filter: args.filter()?,
+                    reference: args.reference()?,
+                }
+            }
+            "generate" => {
+                let [snapshot, incremental] = args.paths(["snapshot.bin", "incremental.bin"])?;
+                let mut config = GeneratorConfig::default();
+                if let Some(securities) = args.parse_value("securities")? {
+                    config.securities = securities;
+                }
+                if let Some(messages) = args.parse_value("messages")? {
+                    config.messages = messages;
+                }
+                if let Some(seed) = args.parse_value("seed")? {
+                    config.seed = seed;
+                }
+                if let Some(depth) = args.parse_value("depth")? {
+                    config.depth = depth;
+                }
+                if let Some(hot) = args.parse_value("hot")? {
+                    config.hot_securities = hot;
+                }
+
+                let mut faults = FaultConfig {
+                    seed: config.seed,
+                    ..FaultConfig::default()
+                };
+                let rates = [
+                    ("drop", &mut faults.drop_rate),
+                    ("duplicate", &mut faults.duplicate_rate),
+                    ("reorder", &mut faults.reorder_rate),
+                    ("corrupt", &mut faults.corrupt_rate),
+                    ("invalid-side", &mut faults.invalid_side_rate),
+                    ("nan-price", &mut faults.nan_price_rate),
+                ];
+                let mut faulty = false;
+                for (name, rate) in rates {
+                    if let Some(value) = args.parse_value::<f64>(name)? {
+                        if !(0.0..=1.0).contains(&value) {
+                            bail!("--{} must be between 0 and 1", name);
+                        }
+                        *rate = value;
+                        faulty = true;
+                    }
+                }
+                faults.truncate_snapshot = args.switch("truncate-snapshot")?;
+                faults.truncate_incremental = args.switch("truncate")?;
+                faulty |= faults.truncate_snapshot || faults.truncate_incremental;
+
+                let fault_log = args.value("fault-log")?;
+                if fault_log.is_some() && !faulty {
+                    bail!("--fault-log needs at least one fault option");
+                }
+
+                Command::Generate {
+                    snapshot,
+                    incremental,
+                    config,
+                    faults: faulty.then_some(faults),
+                    truth: args.value("truth")?,
+                    fault_log,
+                    instruments: args.value("instruments")?,
+                }
+            }
+            "replay" => {
+                let [snapshot, incremental] = args.paths(["snapshot.bin", "incremental.bin"])?;
+                let until = match (
+                    args.parse_value("until-ts")?,
+                    args.parse_value("until-seq")?,
+                ) {
+                    (Some(_), Some(_)) => bail!("Use either --until-ts or --until-seq"),
+                    (Some(ts), None) => Some(Until::Timestamp(ts)),
+                    (None, Some(seq)) => Some(Until::SeqNo(seq)),
+                    (None, None) => None,
+                };
+                let speed: Option<f64> = args.parse_value("speed")?;
+                if speed.is_some_and(|speed| !(speed > 0.0 && speed.is_finite())) {
+                    bail!("--speed must be a positive number");
+                }
+                let mut books = args.book_options()?;
+                books.filter.resolve(None)?;
+                Command::Replay {
+                    snapshot,
+                    incremental,
+                    books,
+                    until,
+                    speed,
+                }
+            }
+            "diff" => {
+                let [from, to] = args.paths(["from_snapshot.bin", "to_snapshot.bin"])?;
+                let mut filter = args.filter()?;
+                filter.resolve(None)?;
+                Command::Diff {
+                    from,
+                    to,
+                    output: args.value("output")?,
+                    backend: args.backend()?,
+                    filter,
+                }
+            }
+            "check" => {
+                let [snapshot, incremental] = args.paths(["snapshot.bin", "incremental.bin"])?;
+                Command::Check {
+                    snapshot,
+                    incremental,
+                }
+            }
+            _ => unreachable!(),
+        };
+
+        args.finish(name)?;
+        Ok(command)
+    }
+}
+
+// options are taken out as they are read, whatever is left at the end is unknown
+struct Args {
+    paths: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut paths = Vec::new();
+        let mut options = Vec::new();
+        let mut iter = args.iter().peekable();
+
+        while let Some(arg) = iter.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                paths.push(arg.clone());
+                continue;
+            };
+
+            // --name=value, --name value or a bare switch
+            let option = match name.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None if SWITCHES.contains(&name) => (name.to_string(), None),
+                None => {
+                    let value = iter.next_if(|next| !next.starts_with("--")).cloned();
+                    (name.to_string(), value)
+                }
+            };
+            options.push(option);
+        }
+
+        Ok(Self { paths, options })
+    }
+
+    fn paths<const N: usize>(&mut self, names: [&str; N]) -> Result<[String; N]> {
+        if self.paths.len() != N {
+            if N == 0 {
+                bail!("Unexpected argument '{}'", self.paths[0]);
+            }
+            bail!(
+                "Expected {} paths ({}), got {}",
+                N,
+                names.join(" "),
+                self.paths.len()
+            );
+        }
+
+        Ok(std::array::from_fn(|i| std::mem::take(&mut self.paths[i])))
+    }
+
+    fn take(&mut self, name: &str) -> Option<Option<String>> {
+        let pos = self.options.iter().position(|(n, _)| n == name)?;
+        Some(self.options.remove(pos).1)
+    }
+
+    fn switch(&mut self, name: &str) -> Result<bool> {
+        match self.take(name) {
+            Some(None) => Ok(true),
+            Some(Some(_)) => bail!("--{} takes no value", name),
+            None => Ok(false),
+        }
+    }
+
+    fn value(&mut self, name: &str) -> Result<Option<String>> {
+        match self.take(name) {
+            Some(None) => bail!("--{} needs a value", name),
+            value => Ok(value.flatten()),
+        }
+    }
+
+    fn parse_value<T>(&mut self, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        match self.take(name) {
+            None => Ok(None),
+            Some(None) => bail!("--{} needs a value", name),
+            Some(Some(value)) => value
+                .parse()
+                .map(Some)
+                .map_err(|e| anyhow!("Invalid value '{}' for --{}: {}", value, name, e)),
+        }
+    }
+
+    fn backend(&mut self) -> Result<Backend> {
+        Ok(self.parse_value("backend")?.unwrap_or(Backend::Improved))
+    }
+
+    fn filter(&mut self) -> Result<SecurityFilter> {
+        let mut filter = match self.value("security")? {
+            None => SecurityFilter::default(),
+            Some(list) => SecurityFilter::parse(&list)?,
+        };
+        if let Some(list) = self.value("symbol")? {
+            filter.symbols = list.split(',').map(|s| s.trim().to_string()).collect();
+        }
+        Ok(filter)
+    }
+
+    fn reference(&mut self) -> Result<Option<Reference>> {
+        let unknown = self.parse_value("unknown")?;
+        match (self.value("instruments")?, unknown) {
+            (Some(path), unknown) => Ok(Some(Reference {
+                path,
+                unknown: unknown.unwrap_or_default(),
+            })),
+            (None, Some(_)) => bail!("--unknown needs --instruments <file>"),
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn input_files(&mut self) -> Result<(Option<String>, Option<String>)> {
+        let snapshot = self.value("snapshot")?;
+        let incremental = self.value("incremental")?;
+        if snapshot.is_none() && incremental.is_none() {
+            bail!("Give at least one of --snapshot <file> and --incremental <file>");
+        }
+        Ok((snapshot, incremental))
+    }
+
+    fn book_options(&mut self) -> Result<BookOptions> {
+        Ok(BookOptions {
+            backend: self.backend()?,
+            filter: self.filter()?,
+            depth: self.parse_value("depth")?,
+            format: self.parse_value("format")?.unwrap_or(Format::Text),
+            timestamps: self.switch("timestamps")?,
+        })
+    }
+
+    fn finish(self, command: &str) -> Result<()> {
+        if let Some((name, _)) = self.options.first() {
+            bail!("Unknown option --{} for {}", name, command);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Command> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        Command::parse(&args)
+    }
+
+    fn error(line: &str) -> String {
+        parse(line).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn two_paths_are_process() {
+        let Command::Process {
+            snapshot,
+            incremental,
+            books,
+            latency,
+            core,
+            reference,
+            ..
+        } = parse("s.bin i.bin").unwrap()
+        else {
+            panic!("not process");
+        };
+        assert_eq!(
+            (snapshot.as_str(), incremental.as_str()),
+            ("s.bin", "i.bin")
+        );
+        assert_eq!(books.backend, Backend::Improved);
+        assert_eq!(books.format, Format::Text);
+        assert_eq!(books.depth, None);
+        assert!(!latency && !books.timestamps);
+        assert!(core.is_none() && reference.is_none());
+
+        assert!(matches!(parse("").unwrap(), Command::Help));
+        assert!(matches!(parse("--help").unwrap(), Command::Help));
+    }
+
+    #[test]
+    fn values_and_switches() {
+        let Command::Process { books, latency, .. } = parse(
+            "process s.bin i.bin --backend basic --depth=3 --format json --timestamps \
+             --latency --security 1,2",
+        )
+        .unwrap() else {
+            panic!("not process");
+        };
+        assert_eq!(books.backend, Backend::Basic);
+        assert_eq!(books.depth, Some(3));
+        assert_eq!(books.format, Format::Json);
+        assert!(books.timestamps && latency);
+        assert!(books.filter.matches(2) && !books.filter.matches(3));
+
+        let Command::Replay { until, speed, .. } =
+            parse("replay s.bin i.bin --until-seq 9 --speed 2").unwrap()
+        else {
+            panic!("not replay");
+        };
+        assert_eq!(until, Some(Until::SeqNo(9)));
+        assert_eq!(speed, Some(2.0));
+
+        let Command::Stats { reference, .. } =
+            parse("stats s.bin i.bin --instruments ref.csv --unknown reject").unwrap()
+        else {
+            panic!("not stats");
+        };
+        let reference = reference.unwrap();
+        assert_eq!(reference.path, "ref.csv");
+        assert_eq!(reference.unknown, UnknownPolicy::Reject);
+    }
+
+    #[test]
+    fn missing_values_are_errors() {
+        assert_eq!(error("diff a.bin b.bin --output"), "--output needs a value");
+        assert_eq!(
+            error("process s.bin i.bin --instruments --latency"),
+            "--instruments needs a value"
+        );
+        assert_eq!(
+            error("inspect --snapshot --incremental i.bin"),
+            "--snapshot needs a value"
+        );
+        assert_eq!(error("s.bin i.bin --depth"), "--depth needs a value");
+        assert_eq!(error("s.bin i.bin --symbol"), "--symbol needs a value");
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert_eq!(
+            error("s.bin i.bin --bogus 1"),
+            "Unknown option --bogus for process"
+        );
+        assert_eq!(
+            error("s.bin"),
+            "Expected 2 paths (snapshot.bin incremental.bin), got 1"
+        );
+        assert_eq!(
+            error("s.bin i.bin --latency=yes"),
+            "--latency takes no value"
+        );
+        assert!(error("s.bin i.bin --backend fast").contains("Unknown backend 'fast'"));
+        assert!(error("s.bin i.bin --depth many").starts_with("Invalid value 'many' for --depth"));
+        assert!(error("s.bin i.bin --security 1,x").contains("Invalid security id 'x'"));
+        assert_eq!(
+            error("diff a.bin b.bin --symbol ABC"),
+            "--symbol needs --instruments <file>"
+        );
+        assert_eq!(
+            error("s.bin i.bin --core 1"),
+            "--core only applies to the stream path, use it with --latency or --metrics"
+        );
+        assert_eq!(
+            error("replay s.bin i.bin --until-ts 1 --until-seq 2"),
+            "Use either --until-ts or --until-seq"
+        );
+        assert_eq!(
+            error("replay s.bin i.bin --speed 0"),
+            "--speed must be a positive number"
+        );
+        assert_eq!(
+            error("generate s.bin i.bin --drop 2"),
+            "--drop must be between 0 and 1"
+        );
+        assert_eq!(
+            error("convert s.bin i.bin"),
+            "convert needs --output <file>"
+        );
+        assert_eq!(
+            error("stats s.bin i.bin --unknown reject"),
+            "--unknown needs --instruments <file>"
+        );
+    }
+
+    #[test]
+    fn generate_collects_faults() {
+        let Command::Generate {
+            config,
+            faults,
+            fault_log,
+            ..
+        } = parse(
+            "generate s.bin i.bin --seed 7 --messages 50 --drop 0.1 --truncate --fault-log f.csv",
+        )
+        .unwrap()
+        else {
+            panic!("not generate");
+        };
+        assert_eq!((config.seed, config.messages), (7, 50));
+        let faults = faults.unwrap();
+        assert_eq!(faults.seed, 7);
+        assert_eq!(faults.drop_rate, 0.1);
+        assert!(faults.truncate_incremental && !faults.truncate_snapshot);
+        assert_eq!(fault_log.as_deref(), Some("f.csv"));
+
+        let Command::Generate { faults, .. } = parse("generate s.bin i.bin").unwrap() else {
+            panic!("not generate");
+        };
+        assert!(faults.is_none());
+        assert_eq!(
+            error("generate s.bin i.bin --fault-log f.csv"),
+            "--fault-log needs at least one fault option"
+        );
+    }
+}
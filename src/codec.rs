@@ -0,0 +1,514 @@
+use crate::*;
+use anyhow::{anyhow, bail, Result};
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::{self, Write};
+
+// encoding side of the file formats, mirrors what the processors parse
+//
+// snapshot.bin only exists as fixed SNAPSHOT_SIZE records of SNAPSHOT_LEVELS levels a
+// side, neither parser knows a variable-depth snapshot, so there is none to encode. books
+// deeper than that lose everything past the top levels when written out
+
+/// Encodes the top `SNAPSHOT_LEVELS` of each side of `book` as one `snapshot.bin` record.
+///
+/// SeqNo is taken from `last_update_seq` (0 if the book never saw one), missing levels are
+/// written as zero price/qty which both parsers skip.
+pub fn encode_snapshot<B: BookSide>(book: &Lob<B>, timestamp: u64) -> [u8; SNAPSHOT_SIZE] {
+    let bids = top_levels(&book.bids);
+    let asks = top_levels(&book.asks);
+
+    encode_snapshot_levels(
+        timestamp,
+        book.last_update_seq.unwrap_or(0),
+        book.security_id,
+        &bids,
+        &asks,
+    )
+}
+
+/// Encodes a snapshot record from raw levels, bids h to l and asks l to h.
+///
+/// Anything past `SNAPSHOT_LEVELS` is ignored.
+pub fn encode_snapshot_levels(
+    timestamp: u64,
+    seq_no: SeqNo,
+    security_id: SecurityId,
+    bids: &[(f64, Qty)],
+    asks: &[(f64, Qty)],
+) -> [u8; SNAPSHOT_SIZE] {
+    let mut buf = [0u8; SNAPSHOT_SIZE];
+
+    //Timestamp, SeqNo, SecurityID
+    buf[0..8].copy_from_slice(&timestamp.to_le_bytes());
+    buf[8..16].copy_from_slice(&seq_no.to_le_bytes());
+    buf[16..24].copy_from_slice(&security_id.to_le_bytes());
+
+    // levels are interleaved: bid price, bid qty, ask price, ask qty
+    let mut pos = 24;
+    for i in 0..SNAPSHOT_LEVELS {
+        if let Some(&(price, qty)) = bids.get(i) {
+            buf[pos..pos + 8].copy_from_slice(&price.to_bits().to_le_bytes());
+            buf[pos + 8..pos + 16].copy_from_slice(&qty.to_le_bytes());
+        }
+
+        if let Some(&(price, qty)) = asks.get(i) {
+            buf[pos + 16..pos + 24].copy_from_slice(&price.to_bits().to_le_bytes());
+            buf[pos + 24..pos + 32].copy_from_slice(&qty.to_le_bytes());
+        }
+
+        pos += 32;
+    }
+
+    buf
+}
+
+/// Writes `book` as a snapshot record, see [`encode_snapshot`].
+pub fn write_snapshot<B: BookSide, W: Write>(
+    out: &mut W,
+    book: &Lob<B>,
+    timestamp: u64,
+) -> io::Result<()> {
+    out.write_all(&encode_snapshot(book, timestamp))
+}
+
+/// Writes every book as a snapshot record, ordered by security id so output is reproducible.
+pub fn write_snapshots<'a, B, W, I>(out: &mut W, books: I, timestamp: u64) -> io::Result<()>
+where
+    B: BookSide + 'a,
+    W: Write,
+    I: IntoIterator<Item = &'a Lob<B>>,
+{
+    let mut sorted: Vec<_> = books.into_iter().collect();
+    sorted.sort_by_key(|book| book.security_id);
+
+    for book in sorted {
+        write_snapshot(out, book, timestamp)?;
+    }
+
+    Ok(())
+}
+
+/// Appends one incremental record (header + `INCREMENTAL_SIZE` per update) to `out`.
+pub fn encode_incremental(
+    out: &mut Vec<u8>,
+    timestamp: u64,
+    seq_no: SeqNo,
+    security_id: SecurityId,
+    updates: &[(Side, f64, Qty)],
+) {
+    out.reserve(INCREMENTAL_HEADER_SIZE + updates.len() * INCREMENTAL_SIZE);
+
+    //Timestamp, SeqNo, SecurityID, NumUpdates
+    out.extend_from_slice(&timestamp.to_le_bytes());
+    out.extend_from_slice(&seq_no.to_le_bytes());
+    out.extend_from_slice(&security_id.to_le_bytes());
+    out.extend_from_slice(&(updates.len() as u64).to_le_bytes());
+
+    for &(side, price, qty) in updates {
+        out.push(side as u8);
+        out.extend_from_slice(&price.to_bits().to_le_bytes());
+        out.extend_from_slice(&qty.to_le_bytes());
+    }
+}
+
+/// Shared encoder for snapshot and incremental files.
+///
+/// Validates what it writes so generated feeds are always parseable: prices must be finite
+/// and positive, snapshot sides must be sorted and at most `SNAPSHOT_LEVELS` deep, and
+/// incremental SeqNo must be strictly increasing. Use [`FeedWriter::write_raw`] to bypass it.
+pub struct FeedWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+    last_seq: Option<SeqNo>,
+    bytes_written: u64,
+}
+
+impl<W: Write> FeedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(INCREMENTAL_HEADER_SIZE + 16 * INCREMENTAL_SIZE),
+            last_seq: None,
+            bytes_written: 0,
+        }
+    }
+
+    /// Writes `book` as a snapshot record, only the top `SNAPSHOT_LEVELS` per side are kept.
+    pub fn write_snapshot<B: BookSide>(&mut self, book: &Lob<B>, timestamp: u64) -> Result<()> {
+        let bids = top_levels(&book.bids);
+        let asks = top_levels(&book.asks);
+
+        self.write_snapshot_levels(
+            timestamp,
+            book.last_update_seq.unwrap_or(0),
+            book.security_id,
+            &bids,
+            &asks,
+        )
+    }
+
+    /// Writes a snapshot record from raw levels, bids h to l and asks l to h.
+    pub fn write_snapshot_levels(
+        &mut self,
+        timestamp: u64,
+        seq_no: SeqNo,
+        security_id: SecurityId,
+        bids: &[(f64, Qty)],
+        asks: &[(f64, Qty)],
+    ) -> Result<()> {
+        if bids.len() > SNAPSHOT_LEVELS || asks.len() > SNAPSHOT_LEVELS {
+            bail!(
+                "Snapshot for {} has more than {} levels per side",
+                security_id,
+                SNAPSHOT_LEVELS
+            );
+        }
+
+        for (levels, is_b) in [(bids, true), (asks, false)] {
+            for &(price, qty) in levels {
+                check_price(security_id, price)?;
+                if qty == 0 {
+                    bail!("Snapshot for {} has zero qty at {}", security_id, price);
+                }
+            }
+
+            let sorted = levels.windows(2).all(|w| {
+                if is_b {
+                    w[0].0 > w[1].0
+                } else {
+                    w[0].0 < w[1].0
+                }
+            });
+            if !sorted {
+                bail!("Snapshot levels for {} are not sorted", security_id);
+            }
+        }
+
+        let record = encode_snapshot_levels(timestamp, seq_no, security_id, bids, asks);
+        self.write_raw(&record)
+    }
+
+    /// Writes one incremental record, SeqNo must be greater than the previous one.
+    pub fn write_incremental(
+        &mut self,
+        timestamp: u64,
+        seq_no: SeqNo,
+        security_id: SecurityId,
+        updates: &[(Side, f64, Qty)],
+    ) -> Result<()> {
+        if let Some(last) = self.last_seq {
+            if seq_no <= last {
+                bail!("SeqNo {} is not greater than previous {}", seq_no, last);
+            }
+        }
+
+        for &(_, price, _) in updates {
+            check_price(security_id, price)?;
+        }
+
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        encode_incremental(&mut buf, timestamp, seq_no, security_id, updates);
+        let res = self.write_raw(&buf);
+        self.buf = buf;
+        res?;
+
+        self.last_seq = Some(seq_no);
+        Ok(())
+    }
+
+    /// Writes bytes as is, no validation.
+    pub fn write_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.inner.write_all(bytes)?;
+        self.bytes_written += bytes.len() as u64;
+        Ok(())
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush()?;
+        Ok(self.inner)
+    }
+}
+
+fn top_levels<B: BookSide>(side: &B) -> Vec<(f64, Qty)> {
+    side.get_l()
+        .iter()
+        .take(SNAPSHOT_LEVELS)
+        .map(|level| (level.price, level.quantity))
+        .collect()
+}
+
+#[inline(always)]
+fn check_price(security_id: SecurityId, price: f64) -> Result<()> {
+    if !price.is_finite() || price <= 0.0 {
+        bail!("Invalid price {} for security {}", price, security_id);
+    }
+    Ok(())
+}
+
+/// Decoded `snapshot.bin` record, levels as stored (empty levels are zero).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotRecord {
+    pub timestamp: u64,
+    pub seq_no: SeqNo,
+    pub security_id: SecurityId,
+    pub bids: [(f64, Qty); SNAPSHOT_LEVELS],
+    pub asks: [(f64, Qty); SNAPSHOT_LEVELS],
+}
+
+impl SnapshotRecord {
+    /// Builds a book from the non-empty levels, same skip rule as `ImprovedProcessor`, NaN
+    /// prices are skipped too.
+    pub fn to_lob<B: BookSide>(&self, bids: B, asks: B) -> Lob<B> {
+        let mut book = Lob::new(self.security_id, bids, asks);
+        book.last_update_seq = Some(self.seq_no);
+        book.last_exchange_ts = Some(self.timestamp);
+
+        for &(price, qty) in &self.bids {
+            if price.to_bits() != 0 && qty != 0 && !price.is_nan() {
+                book.bids.update_l(price, qty);
+            }
+        }
+        for &(price, qty) in &self.asks {
+            if price.to_bits() != 0 && qty != 0 && !price.is_nan() {
+                book.asks.update_l(price, qty);
+            }
+        }
+
+        book
+    }
+}
+
+/// Decodes the snapshot record at the start of `data`.
+pub fn decode_snapshot(data: &[u8]) -> Result<SnapshotRecord> {
+    if data.len() < SNAPSHOT_SIZE {
+        bail!("Not enough data for snapshot: {} bytes", data.len());
+    }
+
+    let mut record = SnapshotRecord {
+        timestamp: read_u64(data, 0),
+        seq_no: read_u64(data, 8),
+        security_id: read_u64(data, 16),
+        bids: [(0.0, 0); SNAPSHOT_LEVELS],
+        asks: [(0.0, 0); SNAPSHOT_LEVELS],
+    };
+
+    let mut pos = 24;
+    for i in 0..SNAPSHOT_LEVELS {
+        record.bids[i] = (f64::from_bits(read_u64(data, pos)), read_u64(data, pos + 8));
+        record.asks[i] = (
+            f64::from_bits(read_u64(data, pos + 16)),
+            read_u64(data, pos + 24),
+        );
+        pos += 32;
+    }
+
+    Ok(record)
+}
+
+/// Decoded incremental header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IncrementalHeader {
+    pub timestamp: u64,
+    pub seq_no: SeqNo,
+    pub security_id: SecurityId,
+    pub num_updates: u64,
+}
+
+impl IncrementalHeader {
+    /// Size of the whole record, `None` if `num_updates` overflows.
+    pub fn record_size(&self) -> Option<usize> {
+        incremental_record_size(self.num_updates)
+    }
+}
+
+/// Size of an incremental record with `num_updates` updates, header included, `None` if
+/// it doesn't fit in a usize. NumUpdates is untrusted, never multiply it unchecked.
+#[inline(always)]
+pub fn incremental_record_size(num_updates: u64) -> Option<usize> {
+    usize::try_from(num_updates)
+        .ok()?
+        .checked_mul(INCREMENTAL_SIZE)?
+        .checked_add(INCREMENTAL_HEADER_SIZE)
+}
+
+/// Decodes the incremental header at the start of `data`.
+pub fn decode_incremental_header(data: &[u8]) -> Result<IncrementalHeader> {
+    if data.len() < INCREMENTAL_HEADER_SIZE {
+        bail!(
+            "Not enough data for incremental header: {} bytes",
+            data.len()
+        );
+    }
+
+    Ok(IncrementalHeader {
+        timestamp: read_u64(data, 0),
+        seq_no: read_u64(data, 8),
+        security_id: read_u64(data, 16),
+        num_updates: read_u64(data, 24),
+    })
+}
+
+/// One incremental record inside a larger buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct IncrementalRecord<'a> {
+    pub offset: usize,
+    pub header: IncrementalHeader,
+    // updates only, without the header
+    pub body: &'a [u8],
+}
+
+impl<'a> IncrementalRecord<'a> {
+    /// Raw updates, side is left undecoded so callers decide what to do with bad ones.
+    pub fn updates(&self) -> impl Iterator<Item = (u8, f64, Qty)> + 'a {
+        self.body.chunks_exact(INCREMENTAL_SIZE).map(|update| {
+            (
+                update[0],
+                f64::from_bits(read_u64(update, 1)),
+                read_u64(update, 9),
+            )
+        })
+    }
+}
+
+/// Iterates incremental records in file order, stops with an error on a truncated record.
+pub struct Incrementals<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Incrementals<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    /// Starts at `offset`, which must be the start of a record.
+    pub fn from_offset(data: &'a [u8], offset: usize) -> Self {
+        Self { data, offset }
+    }
+}
+
+impl<'a> Iterator for Incrementals<'a> {
+    type Item = Result<IncrementalRecord<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // same rule as the processors, a tail shorter than a header is ignored
+        match self.offset.checked_add(INCREMENTAL_HEADER_SIZE) {
+            Some(end) if end <= self.data.len() => {}
+            _ => return None,
+        }
+
+        let offset = self.offset;
+        let header = match decode_incremental_header(&self.data[offset..]) {
+            Ok(header) => header,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let end = match header
+            .record_size()
+            .and_then(|size| size.checked_add(offset))
+        {
+            Some(end) if end <= self.data.len() => end,
+            _ => {
+                // nothing sensible follows a broken record
+                self.offset = self.data.len();
+                return Some(Err(anyhow!(
+                    "Not enough data for {} updates at offset {}",
+                    header.num_updates,
+                    offset
+                )));
+            }
+        };
+
+        self.offset = end;
+
+        Some(Ok(IncrementalRecord {
+            offset,
+            header,
+            body: &self.data[offset + INCREMENTAL_HEADER_SIZE..end],
+        }))
+    }
+}
+
+#[inline(always)]
+fn read_u64(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+/// Splits recorded files into the stream protocol: every snapshot, `EndOfSnapshot`, then
+/// every incremental in file order. Used to push files through `process_stream`.
+pub fn stream_messages(snapshot: &[u8], incremental: &[u8]) -> Result<Vec<StreamMessage>> {
+    let mut messages = Vec::with_capacity(snapshot.len() / SNAPSHOT_SIZE + 1);
+
+    for record in snapshot.chunks_exact(SNAPSHOT_SIZE) {
+        messages.push(StreamMessage::Data(MessageType::Snapshot, record.to_vec()));
+    }
+    messages.push(StreamMessage::EndOfSnapshot);
+
+    for record in Incrementals::new(incremental) {
+        let record = record?;
+        let end = record.offset + INCREMENTAL_HEADER_SIZE + record.body.len();
+        messages.push(StreamMessage::Data(
+            MessageType::Incremental,
+            incremental[record.offset..end].to_vec(),
+        ));
+    }
+
+    Ok(messages)
+}
+
+/// Why a stream datagram was dropped before decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// Shorter than a snapshot, an incremental header, or the header and its NumUpdates.
+    TooShort { len: usize },
+    /// Longer than the message its header describes, a second message may be packed in
+    /// or the count is wrong, either way the datagram can't be trusted.
+    TooLong { len: usize },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::TooShort { len } => write!(f, "too short, {} bytes", len),
+            Rejection::TooLong { len } => write!(f, "too long, {} bytes", len),
+        }
+    }
+}
+
+/// Checks a stream datagram's length against its type before anything is read from it.
+///
+/// A snapshot must be exactly `SNAPSHOT_SIZE`, an incremental exactly its header plus
+/// NumUpdates updates. EndOfSnapshot carries nothing and is never rejected.
+pub fn check_datagram(msg_type: MessageType, data: &[u8]) -> Result<(), Rejection> {
+    let expected = match msg_type {
+        MessageType::Snapshot => SNAPSHOT_SIZE,
+        MessageType::Incremental => {
+            if data.len() < INCREMENTAL_HEADER_SIZE {
+                return Err(Rejection::TooShort { len: data.len() });
+            }
+            // a NumUpdates that overflows can't match any datagram
+            match incremental_record_size(read_u64(data, 24)) {
+                Some(size) => size,
+                None => return Err(Rejection::TooShort { len: data.len() }),
+            }
+        }
+        MessageType::EndOfSnapshot => return Ok(()),
+    };
+
+    match data.len().cmp(&expected) {
+        Ordering::Less => Err(Rejection::TooShort { len: data.len() }),
+        Ordering::Greater => Err(Rejection::TooLong { len: data.len() }),
+        Ordering::Equal => Ok(()),
+    }
+}
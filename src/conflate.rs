@@ -0,0 +1,172 @@
+use crate::*;
+use crossbeam::channel::{self, Receiver, Sender};
+use fnv::FnvHashMap;
+use std::time::{Duration, Instant};
+
+// conflation for slow consumers: per security only the latest state is kept and published as
+// one delta against what the consumer already has, so the consumer can skip any number of
+// intermediate incrementals and still end up with the true book
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflationMode {
+    /// Publish pending deltas at most once per interval.
+    ///
+    /// There is no timer: on an idle stream a pending delta waits for the next `record`,
+    /// `poll` or `flush`, so call `poll` periodically or `flush` when the stream goes quiet.
+    Interval(Duration),
+    /// Publish on every poll while the consumer channel has room.
+    WhenReady,
+}
+
+/// One conflated delta, applying `updates` in order via `Lob::update` brings the consumer's
+/// book to the state at `seq_no`.
+#[derive(Debug, Clone)]
+pub struct ConflatedUpdate {
+    pub security_id: SecurityId,
+    pub seq_no: SeqNo,
+    pub updates: Vec<(Side, f64, Qty)>,
+}
+
+#[derive(Default)]
+struct Slot {
+    // what the consumer has
+    published_bids: Vec<Level>,
+    published_asks: Vec<Level>,
+    // what the book has now
+    latest_bids: Vec<Level>,
+    latest_asks: Vec<Level>,
+    seq_no: SeqNo,
+    dirty: bool,
+}
+
+pub struct ConflatingPublisher {
+    mode: ConflationMode,
+    sender: Sender<ConflatedUpdate>,
+    slots: FnvHashMap<SecurityId, Slot>,
+    // dirty securities in the order they changed
+    dirty: Vec<SecurityId>,
+    last_publish: Instant,
+}
+
+impl ConflatingPublisher {
+    /// Publisher on top of an existing channel, should be bounded so a slow consumer
+    /// makes it conflate instead of queueing every delta.
+    pub fn new(mode: ConflationMode, sender: Sender<ConflatedUpdate>) -> Self {
+        Self {
+            mode,
+            sender,
+            slots: FnvHashMap::default(),
+            dirty: Vec::new(),
+            last_publish: Instant::now(),
+        }
+    }
+
+    /// Publisher with a bounded channel of `capacity` deltas.
+    pub fn channel(mode: ConflationMode, capacity: usize) -> (Self, Receiver<ConflatedUpdate>) {
+        let (sender, receiver) = channel::bounded(capacity);
+        (Self::new(mode, sender), receiver)
+    }
+
+    /// Records the current state of `book`, it is published on a later poll.
+    pub fn record<B: BookSide>(&mut self, book: &Lob<B>) {
+        let slot = self.slots.entry(book.security_id).or_default();
+
+        // copied into the slot's buffers, no allocation once they have grown
+        copy_levels(&book.bids, &mut slot.latest_bids);
+        copy_levels(&book.asks, &mut slot.latest_asks);
+        slot.seq_no = book.last_update_seq.unwrap_or(0);
+
+        if !slot.dirty {
+            slot.dirty = true;
+            self.dirty.push(book.security_id);
+        }
+    }
+
+    /// Publishes pending deltas if the mode allows it now, returns how many were sent.
+    pub fn poll(&mut self) -> usize {
+        if let ConflationMode::Interval(interval) = self.mode {
+            if self.last_publish.elapsed() < interval {
+                return 0;
+            }
+        }
+
+        self.publish()
+    }
+
+    /// Publishes pending deltas ignoring the interval, returns how many are still pending
+    /// because the consumer is not ready. Call until 0 at the end of a stream.
+    pub fn flush(&mut self) -> usize {
+        self.publish();
+        self.dirty.len()
+    }
+
+    /// Number of securities with changes the consumer hasn't seen yet.
+    pub fn pending(&self) -> usize {
+        self.dirty.len()
+    }
+
+    fn publish(&mut self) -> usize {
+        let mut sent = 0;
+        let mut done = 0;
+
+        for &security_id in &self.dirty {
+            let slot = self
+                .slots
+                .get_mut(&security_id)
+                .expect("dirty security always has a slot");
+
+            let updates = diff::diff_sides(
+                &slot.published_bids,
+                &slot.published_asks,
+                &slot.latest_bids,
+                &slot.latest_asks,
+            );
+
+            if !updates.is_empty() {
+                let update = ConflatedUpdate {
+                    security_id,
+                    seq_no: slot.seq_no,
+                    updates,
+                };
+
+                // consumer is full or gone, keep the rest dirty, next delta will include it
+                if self.sender.try_send(update).is_err() {
+                    break;
+                }
+
+                slot.published_bids.clone_from(&slot.latest_bids);
+                slot.published_asks.clone_from(&slot.latest_asks);
+                sent += 1;
+            }
+
+            slot.dirty = false;
+            done += 1;
+        }
+
+        self.dirty.drain(..done);
+        self.last_publish = Instant::now();
+
+        sent
+    }
+}
+
+fn copy_levels<B: BookSide>(side: &B, levels: &mut Vec<Level>) {
+    levels.clear();
+    levels.extend(
+        (0..side.depth())
+            .map_while(|i| side.level(i))
+            .map(|(price, quantity)| Level { price, quantity }),
+    );
+}
+
+impl<B: BookSide> StreamObserver<B> for ConflatingPublisher {
+    fn on_snapshot(&mut self, book: &Lob<B>) {
+        self.record(book);
+        self.poll();
+    }
+
+    fn on_applied(&mut self, book: &Lob<B>) {
+        self.record(book);
+        self.poll();
+    }
+}
@@ -0,0 +1,116 @@
+use crate::*;
+use anyhow::{bail, Result};
+
+// minimal delta between two book states of one security
+
+/// Returns the minimal updates that turn `from` into `to`.
+///
+/// Removals for both sides come first so a side capped at its level limit has room for
+/// the inserts that follow. Prices are matched exactly, same as the processors do.
+pub fn diff_books<B: BookSide>(from: &Lob<B>, to: &Lob<B>) -> Result<Vec<(Side, f64, Qty)>> {
+    if from.security_id != to.security_id {
+        bail!(
+            "Cannot diff books of different securities: {} and {}",
+            from.security_id,
+            to.security_id
+        );
+    }
+
+    Ok(diff_sides(
+        &from.bids.get_l(),
+        &from.asks.get_l(),
+        &to.bids.get_l(),
+        &to.asks.get_l(),
+    ))
+}
+
+/// Same as [`diff_books`] but on raw level lists, bids h to l and asks l to h.
+///
+/// Each list must be strictly sorted that way, as `get_l` returns it, the merge gives
+/// wrong updates otherwise. Checked in debug builds only.
+pub fn diff_sides(
+    from_bids: &[Level],
+    from_asks: &[Level],
+    to_bids: &[Level],
+    to_asks: &[Level],
+) -> Vec<(Side, f64, Qty)> {
+    let mut removes = Vec::new();
+    let mut upserts = Vec::new();
+
+    diff_levels(Side::B, from_bids, to_bids, &mut removes, &mut upserts);
+    diff_levels(Side::A, from_asks, to_asks, &mut removes, &mut upserts);
+
+    removes.extend(upserts);
+    removes
+}
+
+/// Encodes the delta between `from` and `to` as one incremental record.
+///
+/// SeqNo is `to.last_update_seq`, returns `None` when the books are already equal.
+pub fn diff_incremental<B: BookSide>(
+    from: &Lob<B>,
+    to: &Lob<B>,
+    timestamp: u64,
+) -> Result<Option<Vec<u8>>> {
+    let updates = diff_books(from, to)?;
+    if updates.is_empty() {
+        return Ok(None);
+    }
+
+    let mut buf = Vec::new();
+    codec::encode_incremental(
+        &mut buf,
+        timestamp,
+        to.last_update_seq.unwrap_or(0),
+        to.security_id,
+        &updates,
+    );
+
+    Ok(Some(buf))
+}
+
+// both lists are sorted in side order, single merge pass
+fn diff_levels(
+    side: Side,
+    from: &[Level],
+    to: &[Level],
+    removes: &mut Vec<(Side, f64, Qty)>,
+    upserts: &mut Vec<(Side, f64, Qty)>,
+) {
+    // true if a comes before b on this side
+    let before = |a: f64, b: f64| if side == Side::B { a > b } else { a < b };
+    let sorted = |levels: &[Level]| levels.windows(2).all(|w| before(w[0].price, w[1].price));
+    debug_assert!(sorted(from), "{:?} levels to diff from aren't sorted", side);
+    debug_assert!(sorted(to), "{:?} levels to diff to aren't sorted", side);
+
+    let mut i = 0;
+    let mut j = 0;
+
+    while i < from.len() && j < to.len() {
+        let (old, new) = (&from[i], &to[j]);
+
+        if old.price == new.price {
+            if old.quantity != new.quantity {
+                upserts.push((side, new.price, new.quantity));
+            }
+            i += 1;
+            j += 1;
+        } else if before(old.price, new.price) {
+            // gone from the new state
+            removes.push((side, old.price, 0));
+            i += 1;
+        } else {
+            // new level
+            upserts.push((side, new.price, new.quantity));
+            j += 1;
+        }
+    }
+
+    for old in &from[i..] {
+        removes.push((side, old.price, 0));
+    }
+
+    for new in &to[j..] {
+        upserts.push((side, new.price, new.quantity));
+    }
+}
@@ -0,0 +1,279 @@
+use crate::basic::BasicProcessor;
+use crate::improved::{ImprovedProcessor, MAX_LEVELS};
+use crate::*;
+use anyhow::{Context, Result};
+use fnv::{FnvHashMap, FnvHashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+
+// basic is the reference for improved: same input, same books. improved keeps at most
+// MAX_LEVELS per side and drops a new level on a full side, after that the two books
+// legitimately differ so those securities are left out instead of reported
+
+/// How the candidate book of one security differs from the reference.
+#[derive(Debug, Clone)]
+pub enum Difference {
+    /// Only one of the two has a book for the security.
+    MissingBook { in_reference: bool },
+    /// First level that differs, `None` on the side that has fewer levels.
+    Level {
+        side: Side,
+        /// 0 for the top of book.
+        level: usize,
+        reference: Option<Level>,
+        candidate: Option<Level>,
+    },
+    /// Same levels but a different last SeqNo or Timestamp.
+    LastUpdate {
+        reference: (Option<SeqNo>, Option<u64>),
+        candidate: (Option<SeqNo>, Option<u64>),
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::MissingBook { in_reference: true } => write!(f, "no candidate book"),
+            Difference::MissingBook {
+                in_reference: false,
+            } => write!(f, "no reference book"),
+            Difference::Level {
+                side,
+                level,
+                reference,
+                candidate,
+            } => {
+                let side = if *side == Side::B { "bid" } else { "ask" };
+                write!(
+                    f,
+                    "{} level {}: reference {}, candidate {}",
+                    side,
+                    level + 1,
+                    fmt_level(reference),
+                    fmt_level(candidate)
+                )
+            }
+            Difference::LastUpdate {
+                reference,
+                candidate,
+            } => write!(
+                f,
+                "last update: reference seq {:?} ts {:?}, candidate seq {:?} ts {:?}",
+                reference.0, reference.1, candidate.0, candidate.1
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub security_id: SecurityId,
+    pub difference: Difference,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sec {}: {}", self.security_id, self.difference)
+    }
+}
+
+/// Compares two sets of books security by security, prices bit for bit.
+///
+/// Securities in `skip` are not compared, mismatches are ordered by security id.
+pub fn compare_books<'a, 'b, R, C>(
+    reference: impl IntoIterator<Item = &'a Lob<R>>,
+    candidate: impl IntoIterator<Item = &'b Lob<C>>,
+    skip: &FnvHashSet<SecurityId>,
+) -> (u64, Vec<Mismatch>)
+where
+    R: BookSide + 'a,
+    C: BookSide + 'b,
+{
+    let mut candidate: FnvHashMap<_, _> = candidate
+        .into_iter()
+        .filter(|book| !skip.contains(&book.security_id))
+        .map(|book| (book.security_id, book))
+        .collect();
+
+    let mut compared = 0;
+    let mut mismatches = Vec::new();
+
+    for book in reference {
+        if skip.contains(&book.security_id) {
+            continue;
+        }
+        compared += 1;
+
+        let difference = match candidate.remove(&book.security_id) {
+            Some(other) => compare_book(book, other),
+            None => Some(Difference::MissingBook { in_reference: true }),
+        };
+        if let Some(difference) = difference {
+            mismatches.push(Mismatch {
+                security_id: book.security_id,
+                difference,
+            });
+        }
+    }
+
+    // left over candidates had no reference book
+    compared += candidate.len() as u64;
+    mismatches.extend(candidate.into_keys().map(|security_id| Mismatch {
+        security_id,
+        difference: Difference::MissingBook {
+            in_reference: false,
+        },
+    }));
+
+    mismatches.sort_by_key(|m| m.security_id);
+    (compared, mismatches)
+}
+
+/// First difference between two books of the same security, `None` when they're equal.
+pub fn compare_book<R: BookSide, C: BookSide>(
+    reference: &Lob<R>,
+    candidate: &Lob<C>,
+) -> Option<Difference> {
+    let sides = [
+        (Side::B, reference.bids.get_l(), candidate.bids.get_l()),
+        (Side::A, reference.asks.get_l(), candidate.asks.get_l()),
+    ];
+
+    for (side, reference, candidate) in sides {
+        for level in 0..reference.len().max(candidate.len()) {
+            let (r, c) = (reference.get(level), candidate.get(level));
+            let same = match (r, c) {
+                (Some(r), Some(c)) => {
+                    r.price.to_bits() == c.price.to_bits() && r.quantity == c.quantity
+                }
+                _ => false,
+            };
+            if !same {
+                return Some(Difference::Level {
+                    side,
+                    level,
+                    reference: r.cloned(),
+                    candidate: c.cloned(),
+                });
+            }
+        }
+    }
+
+    let reference = (reference.last_update_seq, reference.last_exchange_ts);
+    let candidate = (candidate.last_update_seq, candidate.last_exchange_ts);
+    (reference != candidate).then_some(Difference::LastUpdate {
+        reference,
+        candidate,
+    })
+}
+
+/// Result of running both processors over the same files.
+#[derive(Debug)]
+pub struct Comparison {
+    /// Securities compared, books of either processor.
+    pub compared: u64,
+    /// Securities left out because improved dropped levels at [`MAX_LEVELS`], sorted.
+    pub capped: Vec<SecurityId>,
+    /// Basic is the reference, improved the candidate.
+    pub mismatches: Vec<Mismatch>,
+    pub basic_error: Option<anyhow::Error>,
+    pub improved_error: Option<anyhow::Error>,
+}
+
+impl Comparison {
+    /// Same books for every compared security, or both processors rejected the input.
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty() && self.basic_error.is_some() == self.improved_error.is_some()
+    }
+
+    /// Processor errors, the mismatches, then a summary line.
+    pub fn report<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (name, error) in [
+            ("basic", &self.basic_error),
+            ("improved", &self.improved_error),
+        ] {
+            if let Some(e) = error {
+                writeln!(out, "{} failed: {:#}", name, e)?;
+            }
+        }
+
+        for mismatch in &self.mismatches {
+            writeln!(out, "{}", mismatch)?;
+        }
+
+        write!(out, "compared {} securities", self.compared)?;
+        if !self.capped.is_empty() {
+            write!(
+                out,
+                ", skipped {} over {} levels",
+                self.capped.len(),
+                MAX_LEVELS
+            )?;
+        }
+        writeln!(out)?;
+
+        if self.is_match() {
+            writeln!(out, "ok")
+        } else if self.mismatches.is_empty() {
+            writeln!(out, "only one processor failed")
+        } else {
+            writeln!(out, "{} mismatches", self.mismatches.len())
+        }
+    }
+}
+
+/// Runs both processors over the files and compares their books.
+///
+/// Both run with stats, so updates with an invalid side are skipped by both. Fails only
+/// when a file can't be opened, a processor rejecting the input is part of the comparison.
+pub fn compare_files(snapshot_path: &str, incremental_path: &str) -> Result<Comparison> {
+    File::open(snapshot_path)
+        .with_context(|| format!("Failed to open snapshot file: {}", snapshot_path))?;
+    File::open(incremental_path)
+        .with_context(|| format!("Failed to open incremental file: {}", incremental_path))?;
+
+    let basic = BasicProcessor::new()
+        .process_files_with_stats(snapshot_path, incremental_path)
+        .map(|(books, _)| books);
+    let improved =
+        ImprovedProcessor::new().process_files_with_stats(snapshot_path, incremental_path);
+
+    let mut comparison = Comparison {
+        compared: 0,
+        capped: Vec::new(),
+        mismatches: Vec::new(),
+        basic_error: None,
+        improved_error: None,
+    };
+
+    match (basic, improved) {
+        (Ok(basic), Ok((improved, stats))) => {
+            let capped: FnvHashSet<_> = stats
+                .per_security
+                .iter()
+                .filter(|(_, counters)| counters.levels_dropped > 0)
+                .map(|(&id, _)| id)
+                .collect();
+            let (compared, mismatches) = compare_books(basic.values(), improved.values(), &capped);
+
+            comparison.compared = compared;
+            comparison.mismatches = mismatches;
+            comparison.capped = capped.into_iter().collect();
+            comparison.capped.sort();
+        }
+        (basic, improved) => {
+            comparison.basic_error = basic.err();
+            comparison.improved_error = improved.err();
+        }
+    }
+
+    Ok(comparison)
+}
+
+fn fmt_level(level: &Option<Level>) -> String {
+    match level {
+        Some(level) => format!("{} x {}", level.price, level.quantity),
+        None => "none".to_string(),
+    }
+}
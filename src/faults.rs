@@ -0,0 +1,290 @@
+use crate::basic::Basic;
+use crate::codec::{self, FeedWriter};
+use crate::generator::{FeedGenerator, GeneratorConfig, SplitMix64};
+use crate::*;
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+// damaged feeds for recovery tests. the clean feed comes from FeedGenerator and faults are
+// laid over it with their own rng, so the same generator seed gives the same clean books
+// whatever faults are asked for. the generator's books stay the ground truth: what the
+// processors would hold if nothing had been damaged
+
+/// Per message fault rates (0..=1) and file truncation, all off by default.
+#[derive(Debug, Clone)]
+pub struct FaultConfig {
+    pub seed: u64,
+    /// Message not written at all.
+    pub drop_rate: f64,
+    /// Message written twice in a row.
+    pub duplicate_rate: f64,
+    /// Message written after the one that follows it.
+    pub reorder_rate: f64,
+    /// One random byte flipped, NumUpdates is never touched so the framing survives.
+    pub corrupt_rate: f64,
+    /// Side of one update set to a value other than 0 or 1.
+    pub invalid_side_rate: f64,
+    /// Price of one update set to NaN.
+    pub nan_price_rate: f64,
+    /// Cut the last snapshot record short.
+    pub truncate_snapshot: bool,
+    /// Cut the last incremental record short.
+    pub truncate_incremental: bool,
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            drop_rate: 0.0,
+            duplicate_rate: 0.0,
+            reorder_rate: 0.0,
+            corrupt_rate: 0.0,
+            invalid_side_rate: 0.0,
+            nan_price_rate: 0.0,
+            truncate_snapshot: false,
+            truncate_incremental: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    Dropped,
+    Duplicated,
+    Reordered,
+    Corrupted,
+    InvalidSide,
+    NanPrice,
+    Truncated,
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FaultKind::Dropped => "dropped",
+            FaultKind::Duplicated => "duplicated",
+            FaultKind::Reordered => "reordered",
+            FaultKind::Corrupted => "corrupted",
+            FaultKind::InvalidSide => "invalid_side",
+            FaultKind::NanPrice => "nan_price",
+            FaultKind::Truncated => "truncated",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One injected fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub kind: FaultKind,
+    /// Generated SeqNo of the message, 0 for the snapshot.
+    pub seq_no: SeqNo,
+    /// Security the clean message was for, a corrupted record may say otherwise.
+    pub security_id: SecurityId,
+    /// Where the record starts in the written file, for a dropped message where it would
+    /// have been.
+    pub offset: u64,
+}
+
+/// Header of [`write_fault_log`].
+pub const FAULT_LOG_HEADER: &str = "seq,security,fault,offset";
+
+/// Securities that any fault touched, sorted.
+pub fn affected_securities(faults: &[Fault]) -> Vec<SecurityId> {
+    let mut ids: Vec<_> = faults.iter().map(|f| f.security_id).collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+/// Generates a feed like [`generator::generate_files`] and damages it as `faults` asks.
+///
+/// Returns the generator, whose books are the undamaged state, and every fault injected,
+/// the snapshot's first and then the incrementals' in file order.
+pub fn generate_faulty_files(
+    config: GeneratorConfig,
+    faults: &FaultConfig,
+    snapshot_path: &str,
+    incremental_path: &str,
+) -> Result<(FeedGenerator, Vec<Fault>)> {
+    let mut generator = FeedGenerator::new(config)?;
+    let mut rng = SplitMix64::new(faults.seed);
+    let mut log = Vec::new();
+
+    let file = File::create(snapshot_path)
+        .with_context(|| format!("Failed to create snapshot file: {}", snapshot_path))?;
+    let mut writer = FeedWriter::new(BufWriter::new(file));
+    generator.write_snapshot(&mut writer)?;
+    writer.flush()?;
+    let snapshot_len = writer.bytes_written();
+    drop(writer);
+
+    if faults.truncate_snapshot && snapshot_len > 0 {
+        let offset = snapshot_len - SNAPSHOT_SIZE as u64;
+        truncate(snapshot_path, offset, SNAPSHOT_SIZE, &mut rng)?;
+        log.push(Fault {
+            kind: FaultKind::Truncated,
+            seq_no: 0,
+            security_id: generator.config().securities,
+            offset,
+        });
+    }
+
+    let file = File::create(incremental_path)
+        .with_context(|| format!("Failed to create incremental file: {}", incremental_path))?;
+    let mut writer = FeedWriter::new(BufWriter::new(file));
+    // a reordered message waiting for the next one to be written
+    let mut held: Option<Record> = None;
+    // the last record written, for truncation
+    let mut last: Option<(Fault, usize)> = None;
+
+    while let Some(message) = generator.next_message() {
+        let mut record = Record {
+            bytes: Vec::new(),
+            seq_no: message.seq_no,
+            security_id: message.security_id,
+            damage: Vec::new(),
+        };
+
+        if rng.chance(faults.drop_rate) {
+            log.push(record.fault(FaultKind::Dropped, writer.bytes_written()));
+            continue;
+        }
+
+        codec::encode_incremental(
+            &mut record.bytes,
+            message.timestamp,
+            message.seq_no,
+            message.security_id,
+            &message.updates,
+        );
+        let updates = message.updates.len() as u64;
+
+        if updates > 0 && rng.chance(faults.invalid_side_rate) {
+            let at = update_offset(rng.below(updates));
+            record.bytes[at] = 2 + rng.below(254) as u8;
+            record.damage.push(FaultKind::InvalidSide);
+        }
+        if updates > 0 && rng.chance(faults.nan_price_rate) {
+            let at = update_offset(rng.below(updates)) + 1;
+            record.bytes[at..at + 8].copy_from_slice(&f64::NAN.to_bits().to_le_bytes());
+            record.damage.push(FaultKind::NanPrice);
+        }
+        if rng.chance(faults.corrupt_rate) {
+            // anything but NumUpdates at 24..32
+            let mut at = rng.below(record.bytes.len() as u64 - 8) as usize;
+            if at >= 24 {
+                at += 8;
+            }
+            record.bytes[at] ^= 1 + rng.below(255) as u8;
+            record.damage.push(FaultKind::Corrupted);
+        }
+
+        if held.is_none() && rng.chance(faults.reorder_rate) {
+            record.damage.push(FaultKind::Reordered);
+            held = Some(record);
+            continue;
+        }
+
+        let duplicate = rng.chance(faults.duplicate_rate);
+        last = Some(record.write(&mut writer, &mut log)?);
+        if duplicate {
+            record.damage = vec![FaultKind::Duplicated];
+            last = Some(record.write(&mut writer, &mut log)?);
+        }
+
+        if let Some(held) = held.take() {
+            last = Some(held.write(&mut writer, &mut log)?);
+        }
+    }
+
+    // nothing came after the last held message
+    if let Some(held) = held.take() {
+        last = Some(held.write(&mut writer, &mut log)?);
+    }
+
+    writer.flush()?;
+    drop(writer);
+
+    if faults.truncate_incremental {
+        if let Some((fault, len)) = last {
+            truncate(incremental_path, fault.offset, len, &mut rng)?;
+            log.push(fault);
+        }
+    }
+
+    Ok((generator, log))
+}
+
+// one encoded incremental and what was done to it
+struct Record {
+    bytes: Vec<u8>,
+    seq_no: SeqNo,
+    security_id: SecurityId,
+    damage: Vec<FaultKind>,
+}
+
+impl Record {
+    fn fault(&self, kind: FaultKind, offset: u64) -> Fault {
+        Fault {
+            kind,
+            seq_no: self.seq_no,
+            security_id: self.security_id,
+            offset,
+        }
+    }
+
+    // logs the damage at the offset it ends up at, returns a fault locating the record
+    fn write<W: Write>(
+        &self,
+        writer: &mut FeedWriter<W>,
+        log: &mut Vec<Fault>,
+    ) -> Result<(Fault, usize)> {
+        let offset = writer.bytes_written();
+        log.extend(self.damage.iter().map(|&kind| self.fault(kind, offset)));
+        writer.write_raw(&self.bytes)?;
+        Ok((self.fault(FaultKind::Truncated, offset), self.bytes.len()))
+    }
+}
+
+/// Writes the generator's books in the `output` CSV format, the state a processor should
+/// reach on the undamaged feed. Securities with an empty book have no rows.
+pub fn write_ground_truth<W: Write>(out: &mut W, generator: &FeedGenerator) -> Result<()> {
+    let books: Vec<Lob<Basic>> = (1..=generator.config().securities)
+        .filter_map(|id| generator.book(id, Basic::new))
+        .collect();
+    output::write_csv(out, &books, None, None)?;
+    Ok(())
+}
+
+/// Writes `faults` as CSV, see [`FAULT_LOG_HEADER`].
+pub fn write_fault_log<W: Write>(out: &mut W, faults: &[Fault]) -> Result<()> {
+    writeln!(out, "{}", FAULT_LOG_HEADER)?;
+    for fault in faults {
+        writeln!(
+            out,
+            "{},{},{},{}",
+            fault.seq_no, fault.security_id, fault.kind, fault.offset
+        )?;
+    }
+    Ok(())
+}
+
+#[inline(always)]
+fn update_offset(index: u64) -> usize {
+    INCREMENTAL_HEADER_SIZE + index as usize * INCREMENTAL_SIZE
+}
+
+// cuts the record at offset somewhere strictly inside it
+fn truncate(path: &str, offset: u64, len: usize, rng: &mut SplitMix64) -> Result<()> {
+    let keep = 1 + rng.below(len as u64 - 1);
+    let file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {} for truncation", path))?;
+    file.set_len(offset + keep)?;
+    Ok(())
+}
@@ -0,0 +1,531 @@
+use crate::codec::FeedWriter;
+use crate::improved::MAX_LEVELS;
+use crate::instruments::{Instrument, Instruments, UnknownPolicy};
+use crate::*;
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+// synthetic feeds that look roughly like a real market: every security has a mid price
+// doing a random walk in ticks, messages arrive as a poisson process spread over the
+// securities by weight, and most updates hit the first few levels
+//
+// the generator keeps its own copy of every book, so what it wrote is also what a correct
+// processor must end up with. prices are tick * tick_size computed the same way every
+// time, equal ticks give bit-equal f64 prices
+
+/// Small, fast, seedable PRNG (splitmix64), good enough for test data and stable across
+/// platforms so a seed always produces the same files.
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    #[inline(always)]
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    #[inline(always)]
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in [0, n), `n` must not be 0.
+    #[inline(always)]
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    #[inline(always)]
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.next_f64() < p
+    }
+
+    /// Exponential with mean `1 / rate`, the gap between poisson arrivals.
+    pub fn exponential(&mut self, rate: f64) -> f64 {
+        -(1.0 - self.next_f64()).ln() / rate
+    }
+
+    /// Number of failures before the first success with probability `p`, capped at `max`.
+    pub fn geometric(&mut self, p: f64, max: usize) -> usize {
+        let mut k = 0;
+        while k < max && !self.chance(p) {
+            k += 1;
+        }
+        k
+    }
+}
+
+/// Shape of a generated feed, `Default` gives a small 10 security feed.
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    /// Securities are numbered from 1.
+    pub securities: u64,
+    pub messages: u64,
+    /// Levels a side is allowed to grow to, the snapshot only carries `SNAPSHOT_LEVELS`.
+    /// `ImprovedSide` keeps `MAX_LEVELS`, deeper books make it drop levels.
+    pub depth: usize,
+    /// Securities `1..=hot_securities` get `hot_weight` times the traffic of the others.
+    pub hot_securities: u64,
+    pub hot_weight: f64,
+    /// Mean arrival rate of incrementals over all securities.
+    pub messages_per_second: f64,
+    pub start_timestamp: u64,
+    pub tick_size: f64,
+    /// Quantities are multiples of `lot_size` up to `max_lots` lots.
+    pub lot_size: Qty,
+    pub max_lots: u64,
+    /// Chance that the mid moves one tick before a message is generated.
+    pub move_probability: f64,
+    pub max_updates_per_message: u64,
+    /// Relative weights of new levels, quantity changes and deletes.
+    pub add_weight: f64,
+    pub change_weight: f64,
+    pub delete_weight: f64,
+    /// Per level chance of stopping at it when picking a level, higher means updates
+    /// closer to the top.
+    pub top_bias: f64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            securities: 10,
+            messages: 1000,
+            depth: 10,
+            hot_securities: 2,
+            hot_weight: 10.0,
+            messages_per_second: 10_000.0,
+            start_timestamp: 1_700_000_000_000,
+            tick_size: 0.01,
+            lot_size: 100,
+            max_lots: 50,
+            move_probability: 0.1,
+            max_updates_per_message: 4,
+            add_weight: 0.4,
+            change_weight: 0.4,
+            delete_weight: 0.2,
+            top_bias: 0.5,
+        }
+    }
+}
+
+impl GeneratorConfig {
+    fn check(&self) -> Result<()> {
+        if self.securities == 0 {
+            bail!("Generator needs at least one security");
+        }
+        if self.depth == 0 {
+            bail!("Generator depth must be at least 1");
+        }
+        if !(self.tick_size > 0.0 && self.tick_size.is_finite()) {
+            bail!("Invalid tick size {}", self.tick_size);
+        }
+        if !(self.messages_per_second > 0.0 && self.messages_per_second.is_finite()) {
+            bail!("Invalid message rate {}", self.messages_per_second);
+        }
+        if self.lot_size == 0 || self.max_lots == 0 || self.max_updates_per_message == 0 {
+            bail!("Lot size, max lots and max updates per message must be at least 1");
+        }
+        let weights = [
+            self.hot_weight,
+            self.add_weight,
+            self.change_weight,
+            self.delete_weight,
+        ];
+        if weights.iter().any(|w| !(*w >= 0.0 && w.is_finite())) {
+            bail!("Generator weights must be finite and not negative");
+        }
+        if self.add_weight + self.change_weight + self.delete_weight <= 0.0 {
+            bail!("At least one of the add, change and delete weights must be positive");
+        }
+        // NaN fails the contains as well
+        if !(0.0..=1.0).contains(&self.move_probability) {
+            bail!(
+                "Move probability must be between 0 and 1, got {}",
+                self.move_probability
+            );
+        }
+        if !(0.0..=1.0).contains(&self.top_bias) {
+            bail!("Top bias must be between 0 and 1, got {}", self.top_bias);
+        }
+        Ok(())
+    }
+}
+
+/// One generated incremental, updates in the order they must be applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedMessage {
+    pub timestamp: u64,
+    pub seq_no: SeqNo,
+    pub security_id: SecurityId,
+    pub updates: Vec<(Side, f64, Qty)>,
+}
+
+// one security as the generator sees it, prices in ticks
+#[derive(Debug, Clone)]
+struct ModelBook {
+    // bids h to l, asks l to h
+    bids: Vec<(i64, Qty)>,
+    asks: Vec<(i64, Qty)>,
+    // highest tick a bid may have, asks start one above
+    mid: i64,
+    // last message that touched the book, the snapshot until then
+    seq_no: SeqNo,
+    timestamp: u64,
+}
+
+/// Generates a snapshot and then `messages` incrementals, see [`GeneratorConfig`].
+pub struct FeedGenerator {
+    config: GeneratorConfig,
+    rng: SplitMix64,
+    books: Vec<ModelBook>,
+    // cumulative security weights for picking the next one
+    weights: Vec<f64>,
+    seq_no: SeqNo,
+    clock: f64,
+}
+
+impl FeedGenerator {
+    pub fn new(config: GeneratorConfig) -> Result<Self> {
+        config.check()?;
+
+        let mut rng = SplitMix64::new(config.seed);
+        let mut weights = Vec::with_capacity(config.securities as usize);
+        let mut total = 0.0;
+        let mut books = Vec::with_capacity(config.securities as usize);
+
+        for security_id in 1..=config.securities {
+            total += if security_id <= config.hot_securities {
+                config.hot_weight
+            } else {
+                1.0
+            };
+            weights.push(total);
+
+            // mids between 10 and 1000 in price
+            let low = (10.0 / config.tick_size).ceil() as i64;
+            let high = (1000.0 / config.tick_size).ceil() as i64;
+            let mid = low + rng.below((high - low).max(1) as u64) as i64;
+
+            let levels = SNAPSHOT_LEVELS.min(config.depth) as i64;
+            let mut book = ModelBook {
+                bids: Vec::new(),
+                asks: Vec::new(),
+                mid,
+                seq_no: 0,
+                timestamp: config.start_timestamp,
+            };
+            for i in 0..levels {
+                book.bids.push((mid - i, random_qty(&mut rng, &config)));
+                book.asks.push((mid + 1 + i, random_qty(&mut rng, &config)));
+            }
+            books.push(book);
+        }
+
+        Ok(Self {
+            clock: config.start_timestamp as f64,
+            config,
+            rng,
+            books,
+            weights,
+            seq_no: 0,
+        })
+    }
+
+    pub fn config(&self) -> &GeneratorConfig {
+        &self.config
+    }
+
+    /// Writes the current books as the snapshot, SeqNo is the last generated one.
+    ///
+    /// Only the top `SNAPSHOT_LEVELS` fit, so it's meant to be called before any message
+    /// while the books are still that shallow.
+    pub fn write_snapshot<W: Write>(&self, out: &mut FeedWriter<W>) -> Result<()> {
+        for security_id in 1..=self.config.securities {
+            let (bids, asks) = self.levels(security_id);
+            out.write_snapshot_levels(
+                self.timestamp(),
+                self.seq_no,
+                security_id,
+                &bids[..bids.len().min(SNAPSHOT_LEVELS)],
+                &asks[..asks.len().min(SNAPSHOT_LEVELS)],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Next incremental, `None` once `messages` were generated.
+    pub fn next_message(&mut self) -> Option<GeneratedMessage> {
+        if self.seq_no >= self.config.messages {
+            return None;
+        }
+
+        self.clock += self.rng.exponential(self.config.messages_per_second) * 1000.0;
+        self.seq_no += 1;
+
+        let index = self.pick_security();
+        let mut updates = Vec::new();
+
+        if self.rng.chance(self.config.move_probability) {
+            let step = if self.rng.chance(0.5) { 1 } else { -1 };
+            self.move_mid(index, step, &mut updates);
+        }
+
+        let count = 1 + self.rng.below(self.config.max_updates_per_message);
+        for _ in 0..count {
+            let side = if self.rng.chance(0.5) {
+                Side::B
+            } else {
+                Side::A
+            };
+            self.update_side(index, side, &mut updates);
+        }
+
+        // one update per price, the last one is the level's state after the message
+        let updates: Vec<_> = updates
+            .iter()
+            .enumerate()
+            .filter(|&(i, &(side, tick, _))| {
+                !updates[i + 1..]
+                    .iter()
+                    .any(|&(later, t, _)| later == side && t == tick)
+            })
+            .map(|(_, &update)| update)
+            .collect();
+
+        let timestamp = self.timestamp();
+        let book = &mut self.books[index];
+        book.seq_no = self.seq_no;
+        book.timestamp = timestamp;
+
+        let tick_size = self.config.tick_size;
+        Some(GeneratedMessage {
+            timestamp,
+            seq_no: self.seq_no,
+            security_id: index as SecurityId + 1,
+            updates: updates
+                .into_iter()
+                .map(|(side, tick, qty)| (side, tick as f64 * tick_size, qty))
+                .collect(),
+        })
+    }
+
+    /// Writes every remaining message, returns how many.
+    pub fn write_incrementals<W: Write>(&mut self, out: &mut FeedWriter<W>) -> Result<u64> {
+        let mut written = 0;
+        while let Some(message) = self.next_message() {
+            out.write_incremental(
+                message.timestamp,
+                message.seq_no,
+                message.security_id,
+                &message.updates,
+            )?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Current levels of `security_id` as the processors should have them, bids h to l
+    /// and asks l to h. Empty for an unknown security.
+    #[allow(clippy::type_complexity)]
+    pub fn levels(&self, security_id: SecurityId) -> (Vec<(f64, Qty)>, Vec<(f64, Qty)>) {
+        let Some(book) = security_id
+            .checked_sub(1)
+            .and_then(|i| self.books.get(i as usize))
+        else {
+            return (Vec::new(), Vec::new());
+        };
+
+        let tick_size = self.config.tick_size;
+        let prices = |side: &[(i64, Qty)]| {
+            side.iter()
+                .map(|&(tick, qty)| (tick as f64 * tick_size, qty))
+                .collect()
+        };
+        (prices(&book.bids), prices(&book.asks))
+    }
+
+    /// Book of `security_id` as a correct processor has it after the messages generated so
+    /// far, `new_side` builds an empty side (`ImprovedSide::new`, `Basic::new`).
+    pub fn book<B: BookSide>(
+        &self,
+        security_id: SecurityId,
+        new_side: fn(bool) -> B,
+    ) -> Option<Lob<B>> {
+        let model = self.books.get(security_id.checked_sub(1)? as usize)?;
+        let (bids, asks) = self.levels(security_id);
+
+        let mut book = Lob::new(security_id, new_side(true), new_side(false));
+        for (price, qty) in bids {
+            book.bids.update_l(price, qty);
+        }
+        for (price, qty) in asks {
+            book.asks.update_l(price, qty);
+        }
+        book.last_update_seq = Some(model.seq_no);
+        book.last_exchange_ts = Some(model.timestamp);
+
+        Some(book)
+    }
+
+    /// Reference data of the generated securities, symbols are `SEC<id>` and max depth is
+    /// the config depth up to `MAX_LEVELS`.
+    pub fn instruments(&self, unknown: UnknownPolicy) -> Instruments {
+        let config = &self.config;
+        // decimals of the tick, 0.01 -> 2
+        let price_scale = (0..=17)
+            .find(|&d| {
+                let scaled = config.tick_size * 10f64.powi(d as i32);
+                (scaled - scaled.round()).abs() < 1e-9
+            })
+            .unwrap_or(17);
+
+        let list = (1..=config.securities)
+            .map(|security_id| Instrument {
+                security_id,
+                symbol: format!("SEC{}", security_id),
+                tick_size: config.tick_size,
+                lot_size: config.lot_size,
+                price_scale,
+                max_depth: config.depth.min(MAX_LEVELS),
+            })
+            .collect();
+
+        // ids are distinct and the config was checked in new
+        Instruments::new(list, unknown).expect("generated instruments are valid")
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.clock as u64
+    }
+
+    fn pick_security(&mut self) -> usize {
+        let total = *self.weights.last().unwrap();
+        let target = self.rng.next_f64() * total;
+        self.weights
+            .partition_point(|&w| w <= target)
+            .min(self.weights.len() - 1)
+    }
+
+    // walk the mid and drop whatever would cross it
+    fn move_mid(&mut self, index: usize, step: i64, updates: &mut Vec<(Side, i64, Qty)>) {
+        let book = &mut self.books[index];
+        book.mid += step;
+        // keep prices positive
+        book.mid = book.mid.max(1);
+        let mid = book.mid;
+
+        while let Some(&(tick, _)) = book.bids.first() {
+            if tick <= mid {
+                break;
+            }
+            book.bids.remove(0);
+            updates.push((Side::B, tick, 0));
+        }
+        while let Some(&(tick, _)) = book.asks.first() {
+            if tick > mid {
+                break;
+            }
+            book.asks.remove(0);
+            updates.push((Side::A, tick, 0));
+        }
+    }
+
+    fn update_side(&mut self, index: usize, side: Side, updates: &mut Vec<(Side, i64, Qty)>) {
+        let config = &self.config;
+        let rng = &mut self.rng;
+        let book = &mut self.books[index];
+        let (levels, best) = match side {
+            Side::B => (&mut book.bids, book.mid),
+            Side::A => (&mut book.asks, book.mid + 1),
+        };
+
+        let total = config.add_weight + config.change_weight + config.delete_weight;
+        let roll = rng.next_f64() * total;
+
+        if levels.is_empty() || roll < config.add_weight {
+            // new level k ticks away from the best allowed price
+            let k = rng.geometric(config.top_bias, config.depth * 2) as i64;
+            let tick = match side {
+                Side::B => best - k,
+                Side::A => best + k,
+            };
+            if tick <= 0 {
+                return;
+            }
+
+            let qty = random_qty(rng, config);
+            let pos = match side {
+                Side::B => levels.partition_point(|&(t, _)| t > tick),
+                Side::A => levels.partition_point(|&(t, _)| t < tick),
+            };
+
+            if levels.get(pos).is_some_and(|&(t, _)| t == tick) {
+                levels[pos].1 = qty;
+            } else if pos >= config.depth {
+                // past the depth limit, nothing to do
+                return;
+            } else {
+                levels.insert(pos, (tick, qty));
+                // the worst level falls off, tell the processors too
+                if levels.len() > config.depth {
+                    let (worst, _) = levels.pop().unwrap();
+                    updates.push((side, worst, 0));
+                }
+            }
+            updates.push((side, tick, qty));
+        } else if roll < config.add_weight + config.change_weight {
+            let i = rng.geometric(config.top_bias, levels.len() - 1);
+            let qty = random_qty(rng, config);
+            levels[i].1 = qty;
+            updates.push((side, levels[i].0, qty));
+        } else {
+            let i = rng.geometric(config.top_bias, levels.len() - 1);
+            let (tick, _) = levels.remove(i);
+            updates.push((side, tick, 0));
+        }
+    }
+}
+
+fn random_qty(rng: &mut SplitMix64, config: &GeneratorConfig) -> Qty {
+    (1 + rng.below(config.max_lots)) * config.lot_size
+}
+
+/// Generates a whole feed into `snapshot_path` and `incremental_path`.
+///
+/// Returns the generator so callers can read the final books from it.
+pub fn generate_files(
+    config: GeneratorConfig,
+    snapshot_path: &str,
+    incremental_path: &str,
+) -> Result<FeedGenerator> {
+    let mut generator = FeedGenerator::new(config)?;
+
+    let file = File::create(snapshot_path)
+        .with_context(|| format!("Failed to create snapshot file: {}", snapshot_path))?;
+    let mut writer = FeedWriter::new(BufWriter::new(file));
+    generator.write_snapshot(&mut writer)?;
+    writer.flush()?;
+
+    let file = File::create(incremental_path)
+        .with_context(|| format!("Failed to create incremental file: {}", incremental_path))?;
+    let mut writer = FeedWriter::new(BufWriter::new(file));
+    generator.write_incrementals(&mut writer)?;
+    writer.flush()?;
+
+    Ok(generator)
+}
@@ -1,13 +1,22 @@
+use crate::arena::BookArena;
+use crate::instruments::{self, Instrument, Instruments, Route};
+#[cfg(feature = "latency-metrics")]
+use crate::latency::{self, LatencyRecorder};
+use crate::simd::{self, SearchPath};
+use crate::stats::{ProcessingStats, StatsRecorder};
 use crate::*;
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, Result};
 use core_affinity;
 use crossbeam::channel::Receiver;
 use fnv::FnvHashMap;
 use memmap2::Mmap;
+#[cfg(feature = "latency-metrics")]
+use std::cell::RefCell;
 use std::fs::File;
 use std::ptr;
 
-const MAX_LEVELS: usize = 32;
+/// Levels kept per side, a new level on a full side is dropped.
+pub const MAX_LEVELS: usize = 32;
 
 // align to 32 bytes
 #[repr(C, align(32))]
@@ -29,86 +38,135 @@ impl ImprovedSide {
         }
     }
 
-    #[cfg(target_arch = "x86_64")]
     #[inline(always)]
     fn find_position(&self, price: f64) -> Result<usize, usize> {
-        use std::arch::x86_64::*;
-
         if self.count == 0 {
             return Err(0);
         }
 
-        unsafe {
-            // fill avx with price
-            let price_vec = _mm256_set1_pd(price);
-            let mut i = 0;
+        match simd::search_path() {
+            #[cfg(target_arch = "x86_64")]
+            SearchPath::Avx2 | SearchPath::Avx512 => unsafe { self.find_position_avx2(price) },
+            #[cfg(target_arch = "x86_64")]
+            SearchPath::Sse2 => unsafe { self.find_position_sse2(price) },
+            _ => self.find_position_from(price, 0),
+        }
+    }
+
+    // scalar search from start, also the tail of the vector paths
+    #[inline(always)]
+    fn find_position_from(&self, price: f64, start: usize) -> Result<usize, usize> {
+        for i in start..self.count {
+            if price == self.prices[i] {
+                return Ok(i);
+            }
+            if self.is_b {
+                if price > self.prices[i] {
+                    return Err(i);
+                }
+            } else if price < self.prices[i] {
+                return Err(i);
+            }
+        }
 
-            // chekc 4 prices
-            while i + 4 <= self.count {
-                // load 4 from vec
-                let prices = _mm256_loadu_pd(&self.prices[i]);
+        Err(self.count)
+    }
 
-                // compare
-                let eq_mask = _mm256_cmp_pd(prices, price_vec, _CMP_EQ_OQ);
-                // compare mask 4 bits
-                let eq_bits = _mm256_movemask_pd(eq_mask);
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2")]
+    unsafe fn find_position_avx2(&self, price: f64) -> Result<usize, usize> {
+        use std::arch::x86_64::*;
 
-                if eq_bits != 0 {
-                    // found our position by zeros
-                    return Ok(i + eq_bits.trailing_zeros() as usize);
-                }
+        // fill avx with price
+        let price_vec = _mm256_set1_pd(price);
+        let mut i = 0;
 
-                // where to insert
-                let cmp_mask = if self.is_b {
-                    // for bids: greater comparsion
-                    _mm256_cmp_pd(price_vec, prices, _CMP_GT_OQ)
-                } else {
-                    // for asks: lesser comparsion
-                    _mm256_cmp_pd(price_vec, prices, _CMP_LT_OQ)
-                };
+        // chekc 4 prices
+        while i + 4 <= self.count {
+            // load 4 from vec
+            let prices = _mm256_loadu_pd(&self.prices[i]);
 
-                let cmp_bits = _mm256_movemask_pd(cmp_mask);
-                if cmp_bits != 0 {
-                    // found where to insert
-                    return Err(i + cmp_bits.trailing_zeros() as usize);
-                }
+            // compare
+            let eq_mask = _mm256_cmp_pd(prices, price_vec, _CMP_EQ_OQ);
+            // compare mask 4 bits
+            let eq_bits = _mm256_movemask_pd(eq_mask);
 
-                i += 4;
+            if eq_bits != 0 {
+                // found our position by zeros
+                return Ok(i + eq_bits.trailing_zeros() as usize);
             }
 
-            // handle remaining elements
-            while i < self.count {
-                if price == self.prices[i] {
-                    return Ok(i);
-                }
-                if self.is_b {
-                    if price > self.prices[i] {
-                        return Err(i);
-                    }
-                } else if price < self.prices[i] {
-                    return Err(i);
-                }
-                i += 1;
+            // where to insert
+            let cmp_mask = if self.is_b {
+                // for bids: greater comparsion
+                _mm256_cmp_pd(price_vec, prices, _CMP_GT_OQ)
+            } else {
+                // for asks: lesser comparsion
+                _mm256_cmp_pd(price_vec, prices, _CMP_LT_OQ)
+            };
+
+            let cmp_bits = _mm256_movemask_pd(cmp_mask);
+            if cmp_bits != 0 {
+                // found where to insert
+                return Err(i + cmp_bits.trailing_zeros() as usize);
             }
 
-            Err(self.count)
+            i += 4;
         }
+
+        // handle remaining elements
+        self.find_position_from(price, i)
+    }
+
+    // same as avx2 two prices at a time, sse2 is always there on x86_64
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "sse2")]
+    unsafe fn find_position_sse2(&self, price: f64) -> Result<usize, usize> {
+        use std::arch::x86_64::*;
+
+        let price_vec = _mm_set1_pd(price);
+        let mut i = 0;
+
+        while i + 2 <= self.count {
+            let prices = _mm_loadu_pd(&self.prices[i]);
+
+            let eq_bits = _mm_movemask_pd(_mm_cmpeq_pd(prices, price_vec));
+            if eq_bits != 0 {
+                return Ok(i + eq_bits.trailing_zeros() as usize);
+            }
+
+            let cmp_mask = if self.is_b {
+                _mm_cmpgt_pd(price_vec, prices)
+            } else {
+                _mm_cmplt_pd(price_vec, prices)
+            };
+
+            let cmp_bits = _mm_movemask_pd(cmp_mask);
+            if cmp_bits != 0 {
+                return Err(i + cmp_bits.trailing_zeros() as usize);
+            }
+
+            i += 2;
+        }
+
+        self.find_position_from(price, i)
     }
 }
 
 impl BookSide for ImprovedSide {
     #[inline(always)]
-    fn update_l(&mut self, price: f64, qty: Qty) {
+    fn update_l(&mut self, price: f64, qty: Qty) -> LevelChange {
         // fast path
         if self.count > 0 && self.prices[0] == price {
             self.qtys[0] = qty;
-            return;
+            return LevelChange::Updated;
         }
 
         match self.find_position(price) {
             Ok(pos) => {
                 //  just update quantity
                 self.qtys[pos] = qty;
+                LevelChange::Updated
             }
             Err(pos) => {
                 //new price level
@@ -132,13 +190,17 @@ impl BookSide for ImprovedSide {
                     self.prices[pos] = price;
                     self.qtys[pos] = qty;
                     self.count += 1;
+                    LevelChange::Inserted
+                } else {
+                    // full, level is lost
+                    LevelChange::Dropped
                 }
             }
         }
     }
 
     #[inline(always)]
-    fn remove_l(&mut self, price: f64) {
+    fn remove_l(&mut self, price: f64) -> LevelChange {
         // fast path
         if self.count > 0 && self.prices[0] == price {
             self.count -= 1;
@@ -157,7 +219,7 @@ impl BookSide for ImprovedSide {
                     );
                 }
             }
-            return;
+            return LevelChange::Removed;
         }
 
         if let Ok(pos) = self.find_position(price) {
@@ -177,6 +239,9 @@ impl BookSide for ImprovedSide {
                     );
                 }
             }
+            LevelChange::Removed
+        } else {
+            LevelChange::NotFound
         }
     }
 
@@ -190,42 +255,192 @@ impl BookSide for ImprovedSide {
         }
         result
     }
+
+    #[inline(always)]
+    fn depth(&self) -> usize {
+        self.count
+    }
+
+    #[inline(always)]
+    fn level(&self, i: usize) -> Option<(f64, Qty)> {
+        (i < self.count).then(|| (self.prices[i], self.qtys[i]))
+    }
+}
+
+// drops snapshot levels off the instrument's tick, counted like off tick updates
+fn retain_on_tick<S: StatsRecorder>(
+    book: &mut Lob<ImprovedSide>,
+    instrument: &Instrument,
+    stats: &mut S,
+) {
+    for side in [&mut book.bids, &mut book.asks] {
+        let mut kept = 0;
+        for i in 0..side.count {
+            if instrument.on_tick(side.prices[i]) {
+                side.prices[kept] = side.prices[i];
+                side.qtys[kept] = side.qtys[i];
+                kept += 1;
+            } else {
+                stats.off_tick(book.security_id);
+            }
+        }
+        side.count = kept;
+    }
 }
 
-pub struct ImprovedProcessor;
+pub struct ImprovedProcessor {
+    // decode and apply time per message, only with the latency-metrics feature
+    #[cfg(feature = "latency-metrics")]
+    latency: RefCell<LatencyRecorder>,
+    instruments: Option<Instruments>,
+}
 
 impl ImprovedProcessor {
     #[inline(always)]
     pub fn new() -> Self {
-        Self
+        Self {
+            #[cfg(feature = "latency-metrics")]
+            latency: RefCell::new(LatencyRecorder::new()),
+            instruments: None,
+        }
+    }
+
+    /// Checks the file paths against reference data, same as
+    /// `BasicProcessor::with_instruments`. Sides stay at `MAX_LEVELS` whatever the
+    /// instrument's max depth.
+    pub fn with_instruments(mut self, instruments: Instruments) -> Self {
+        self.instruments = Some(instruments);
+        self
+    }
+
+    pub fn instruments(&self) -> Option<&Instruments> {
+        self.instruments.as_ref()
+    }
+
+    /// Decode and apply timings of every message processed so far.
+    #[cfg(feature = "latency-metrics")]
+    pub fn latency(&self) -> LatencyRecorder {
+        self.latency.borrow().clone()
     }
 
+    #[cfg(feature = "latency-metrics")]
+    pub fn reset_latency(&self) {
+        self.latency.borrow_mut().reset();
+    }
+
+    /// Books are built in a `BookArena` and copied out into the map once at the end,
+    /// `process_files_arena` skips the copy.
     pub fn process_files(
         &self,
         snapshot_path: &str,
         incremental_path: &str,
     ) -> Result<FnvHashMap<SecurityId, Lob<ImprovedSide>>> {
+        let (books, _) = self.process_files_impl(snapshot_path, incremental_path, &mut ())?;
+        Ok(books.into_books())
+    }
+
+    /// Same as `process_files`, also returning the books of unknown securities kept apart
+    /// under `UnknownPolicy::Quarantine`.
+    #[allow(clippy::type_complexity)]
+    pub fn process_files_with_quarantine(
+        &self,
+        snapshot_path: &str,
+        incremental_path: &str,
+    ) -> Result<(
+        FnvHashMap<SecurityId, Lob<ImprovedSide>>,
+        FnvHashMap<SecurityId, Lob<ImprovedSide>>,
+    )> {
+        let (books, quarantined) =
+            self.process_files_impl(snapshot_path, incremental_path, &mut ())?;
+        Ok((books.into_books(), quarantined.into_books()))
+    }
+
+    /// Same as `process_files`, books left in the arena they were built in.
+    pub fn process_files_arena(
+        &self,
+        snapshot_path: &str,
+        incremental_path: &str,
+    ) -> Result<BookArena<ImprovedSide>> {
+        let (books, _) = self.process_files_impl(snapshot_path, incremental_path, &mut ())?;
+        Ok(books)
+    }
+
+    /// Same as `process_files`, also counting what happened to every message.
+    ///
+    /// Updates with an invalid side are counted and skipped instead of failing the run.
+    #[allow(clippy::type_complexity)]
+    pub fn process_files_with_stats(
+        &self,
+        snapshot_path: &str,
+        incremental_path: &str,
+    ) -> Result<(FnvHashMap<SecurityId, Lob<ImprovedSide>>, ProcessingStats)> {
+        let mut stats = ProcessingStats::new();
+        let (books, _) = self.process_files_impl(snapshot_path, incremental_path, &mut stats)?;
+        Ok((books.into_books(), stats))
+    }
+
+    /// Same as `process_files` on buffers already in memory.
+    pub fn process_bytes(
+        &self,
+        snapshot: &[u8],
+        incremental: &[u8],
+    ) -> Result<FnvHashMap<SecurityId, Lob<ImprovedSide>>> {
+        let (books, _) = self.process_bytes_impl(snapshot, incremental, &mut ())?;
+        Ok(books.into_books())
+    }
+
+    // stats calls are no-ops for (), hot loop stays the same
+    fn process_files_impl<S: StatsRecorder>(
+        &self,
+        snapshot_path: &str,
+        incremental_path: &str,
+        stats: &mut S,
+    ) -> Result<(BookArena<ImprovedSide>, BookArena<ImprovedSide>)> {
         let snapshot_file = File::open(snapshot_path)?;
         let incremental_file = File::open(incremental_path)?;
 
         let snapshot_mmap = unsafe { Mmap::map(&snapshot_file)? };
         let incremental_mmap = unsafe { Mmap::map(&incremental_file)? };
 
-        // fnv hasher is faster
-        let mut books = FnvHashMap::with_capacity_and_hasher(
-            (snapshot_mmap.len() / SNAPSHOT_SIZE).min(1024),
-            Default::default(),
+        self.process_bytes_impl(&snapshot_mmap, &incremental_mmap, stats)
+    }
+
+    // every read below is bounds checked against the slice before the raw pointer reads
+    fn process_bytes_impl<S: StatsRecorder>(
+        &self,
+        snapshot: &[u8],
+        incremental: &[u8],
+        stats: &mut S,
+    ) -> Result<(BookArena<ImprovedSide>, BookArena<ImprovedSide>)> {
+        let instruments = self.instruments.as_ref();
+        // sides apart from the bookkeeping, ids to slots without hashing
+        let mut books = BookArena::with_capacity(
+            instruments.map_or((snapshot.len() / SNAPSHOT_SIZE).min(1024), |instruments| {
+                instruments.len()
+            }),
         );
+        let mut quarantined = BookArena::new();
         let mut offset = 0;
+
+        // known securities get their slots before any message, in id order
+        if let Some(instruments) = instruments {
+            for instrument in instruments.iter() {
+                books.insert(Lob::new(
+                    instrument.security_id,
+                    ImprovedSide::new(true),
+                    ImprovedSide::new(false),
+                ));
+            }
+        }
         let mut max_snapshot_seq = 0u64;
 
         // everything inline for speed
-        while offset + SNAPSHOT_SIZE <= snapshot_mmap.len() {
+        while offset + SNAPSHOT_SIZE <= snapshot.len() {
             unsafe {
-                let ptr = snapshot_mmap.as_ptr().add(offset);
+                let ptr = snapshot.as_ptr().add(offset);
 
                 // read without structs (faster, no prefetching needed cpu handles it better)
-                let _timestamp = ptr::read_unaligned(ptr as *const u64);
+                let timestamp = ptr::read_unaligned(ptr as *const u64);
                 let seq_no = ptr::read_unaligned(ptr.add(8) as *const u64);
                 let security_id = ptr::read_unaligned(ptr.add(16) as *const u64);
 
@@ -237,6 +452,7 @@ impl ImprovedProcessor {
                     ImprovedSide::new(false),
                 );
                 book.last_update_seq = Some(seq_no);
+                book.last_exchange_ts = Some(timestamp);
 
                 let mut pos = 24;
 
@@ -257,14 +473,20 @@ impl ImprovedProcessor {
 
                     pos += 32;
 
-                    // skip empty levels
-                    if bid_price_bits != 0 && bid_qty != 0 {
+                    // skip empty levels and NaN prices, which can't be ordered
+                    if bid_price_bits != 0
+                        && bid_qty != 0
+                        && !f64::from_bits(bid_price_bits).is_nan()
+                    {
                         bid_prices[bid_count] = f64::from_bits(bid_price_bits);
                         bid_quantities[bid_count] = bid_qty;
                         bid_count += 1;
                     }
 
-                    if ask_price_bits != 0 && ask_qty != 0 {
+                    if ask_price_bits != 0
+                        && ask_qty != 0
+                        && !f64::from_bits(ask_price_bits).is_nan()
+                    {
                         ask_prices[ask_count] = f64::from_bits(ask_price_bits);
                         ask_quantities[ask_count] = ask_qty;
                         ask_count += 1;
@@ -301,7 +523,20 @@ impl ImprovedProcessor {
                     book.asks.count = ask_count;
                 }
 
-                books.insert(security_id, book);
+                stats.snapshot(security_id);
+                match instruments::route(instruments, security_id) {
+                    Route::Book(instrument) => {
+                        if let Some(instrument) = instrument {
+                            retain_on_tick(&mut book, instrument, stats);
+                        }
+                        books.insert(book);
+                    }
+                    Route::Quarantine => {
+                        stats.unknown(security_id);
+                        quarantined.insert(book);
+                    }
+                    Route::Reject => stats.unknown(security_id),
+                }
             }
 
             offset += SNAPSHOT_SIZE;
@@ -309,91 +544,146 @@ impl ImprovedProcessor {
 
         offset = 0;
 
-        while offset + INCREMENTAL_HEADER_SIZE <= incremental_mmap.len() {
+        while offset + INCREMENTAL_HEADER_SIZE <= incremental.len() {
+            #[cfg(feature = "latency-metrics")]
+            let start = latency::now_ticks();
+
             unsafe {
-                let ptr = incremental_mmap.as_ptr().add(offset);
+                let ptr = incremental.as_ptr().add(offset);
 
-                let _timestamp = ptr::read_unaligned(ptr as *const u64);
+                let timestamp = ptr::read_unaligned(ptr as *const u64);
                 let seq_no = ptr::read_unaligned(ptr.add(8) as *const u64);
                 let security_id = ptr::read_unaligned(ptr.add(16) as *const u64);
                 let num_updates = ptr::read_unaligned(ptr.add(24) as *const u64);
 
+                #[cfg(feature = "latency-metrics")]
+                let decoded = latency::now_ticks();
+
                 let mut pos = 32;
 
-                //sanity check
-                if offset + pos + (num_updates as usize * INCREMENTAL_SIZE) > incremental_mmap.len()
-                {
-                    bail!("Not enough data for updates");
-                }
+                //sanity check, num_updates is untrusted and can overflow the multiply
+                let size = match codec::incremental_record_size(num_updates) {
+                    Some(size) if size <= incremental.len() - offset => size,
+                    _ => bail!("Not enough data for updates"),
+                };
+
+                stats.incremental(security_id);
 
                 if seq_no > max_snapshot_seq {
-                    if let Some(book) = books.get_mut(&security_id) {
-                        // hot path
-                        for _ in 0..num_updates {
-                            let side = *ptr.add(pos);
-                            let price_bits = ptr::read_unaligned(ptr.add(pos + 1) as *const u64);
-                            let price = f64::from_bits(price_bits);
-                            let qty = ptr::read_unaligned(ptr.add(pos + 9) as *const u64);
-
-                            match side {
-                                0 => {
-                                    // b update
-                                    if qty == 0 {
-                                        book.bids.remove_l(price);
-                                    } else {
-                                        book.bids.update_l(price, qty);
+                    let target = match instruments::route(instruments, security_id) {
+                        Route::Book(instrument) => Some((&mut books, instrument)),
+                        Route::Quarantine => {
+                            stats.unknown(security_id);
+                            Some((&mut quarantined, None))
+                        }
+                        Route::Reject => {
+                            stats.unknown(security_id);
+                            None
+                        }
+                    };
+
+                    if let Some((books, instrument)) = target {
+                        if let Some(slot) = books.slot(security_id) {
+                            // hot path
+                            let book = books.sides_mut(slot);
+                            for _ in 0..num_updates {
+                                let side = *ptr.add(pos);
+                                let price_bits =
+                                    ptr::read_unaligned(ptr.add(pos + 1) as *const u64);
+                                let price = f64::from_bits(price_bits);
+                                let qty = ptr::read_unaligned(ptr.add(pos + 9) as *const u64);
+
+                                let side = match Side::of_update(side, price) {
+                                    Ok(side) => side,
+                                    Err(invalid) => {
+                                        stats.skip_invalid(security_id, invalid)?;
+                                        pos += INCREMENTAL_SIZE;
+                                        continue;
                                     }
+                                };
+
+                                // off the instrument's tick, never applied
+                                if instrument.is_some_and(|instrument| !instrument.on_tick(price)) {
+                                    stats.off_tick(security_id);
+                                    pos += INCREMENTAL_SIZE;
+                                    continue;
                                 }
-                                1 => {
-                                    // a update
-                                    if qty == 0 {
-                                        book.asks.remove_l(price);
-                                    } else {
-                                        book.asks.update_l(price, qty);
+
+                                let levels = match side {
+                                    Side::B => &mut book.bids,
+                                    Side::A => &mut book.asks,
+                                };
+                                let change = if qty == 0 {
+                                    levels.remove_l(price)
+                                } else {
+                                    levels.update_l(price, qty)
+                                };
+                                stats.change(security_id, change);
+
+                                pos += INCREMENTAL_SIZE;
+                            }
+
+                            let meta = books.meta_mut(slot);
+                            meta.last_update_seq = Some(seq_no);
+                            meta.last_exchange_ts = Some(timestamp);
+                        } else {
+                            // cold path new book
+                            let mut book = Lob::new(
+                                security_id,
+                                ImprovedSide::new(true),
+                                ImprovedSide::new(false),
+                            );
+                            stats.book_created(security_id);
+
+                            for _ in 0..num_updates {
+                                let side = *ptr.add(pos);
+                                let price_bits =
+                                    ptr::read_unaligned(ptr.add(pos + 1) as *const u64);
+                                let price = f64::from_bits(price_bits);
+                                let qty = ptr::read_unaligned(ptr.add(pos + 9) as *const u64);
+
+                                // same checks as the hot path
+                                let side = match Side::of_update(side, price) {
+                                    Ok(side) => side,
+                                    Err(invalid) => {
+                                        stats.skip_invalid(security_id, invalid)?;
+                                        pos += INCREMENTAL_SIZE;
+                                        continue;
                                     }
+                                };
+                                if instrument.is_some_and(|instrument| !instrument.on_tick(price)) {
+                                    stats.off_tick(security_id);
+                                    pos += INCREMENTAL_SIZE;
+                                    continue;
                                 }
-                                _ => {
-                                    //never happen with valid data
-                                    debug_assert!(false, "Invalid side: {}", side);
-                                }
+                                stats.change(security_id, book.update(side, price, qty));
+
+                                pos += INCREMENTAL_SIZE;
                             }
 
-                            pos += INCREMENTAL_SIZE;
+                            book.last_update_seq = Some(seq_no);
+                            book.last_exchange_ts = Some(timestamp);
+                            books.insert(book);
                         }
-
-                        book.last_update_seq = Some(seq_no);
                     } else {
-                        // cold path new book
-                        let mut book = Lob::new(
-                            security_id,
-                            ImprovedSide::new(true),
-                            ImprovedSide::new(false),
-                        );
-
-                        for _ in 0..num_updates {
-                            let side = *ptr.add(pos);
-                            let price_bits = ptr::read_unaligned(ptr.add(pos + 1) as *const u64);
-                            let price = f64::from_bits(price_bits);
-                            let qty = ptr::read_unaligned(ptr.add(pos + 9) as *const u64);
-
-                            book.update(Side::from_u8(side).context("Invalid side")?, price, qty);
-
-                            pos += INCREMENTAL_SIZE;
-                        }
-
-                        book.last_update_seq = Some(seq_no);
-                        books.insert(security_id, book);
+                        pos = size;
                     }
                 } else {
                     // skip old
-                    pos += num_updates as usize * INCREMENTAL_SIZE;
+                    stats.stale(security_id, num_updates);
+                    pos = size;
                 }
 
+                #[cfg(feature = "latency-metrics")]
+                self.latency
+                    .borrow_mut()
+                    .record(start, decoded, latency::now_ticks());
+
                 offset += pos;
             }
         }
 
-        Ok(books)
+        Ok((books, quarantined))
     }
 
     pub fn process_stream(
@@ -401,131 +691,210 @@ impl ImprovedProcessor {
         receiver: Receiver<StreamMessage>,
         processor_core: usize,
     ) -> Result<FnvHashMap<SecurityId, Lob<ImprovedSide>>> {
+        self.process_stream_with(receiver, Some(processor_core), &mut ())
+    }
+
+    /// Same as `process_stream`, `observer` sees every book right after it changes.
+    ///
+    /// The thread is pinned to `processor_core` if given, left to the scheduler otherwise.
+    /// Fails on a processor built `with_instruments`, reference data is only checked on
+    /// the file paths.
+    pub fn process_stream_with<O: StreamObserver<ImprovedSide>>(
+        &self,
+        receiver: Receiver<StreamMessage>,
+        processor_core: Option<usize>,
+        observer: &mut O,
+    ) -> Result<FnvHashMap<SecurityId, Lob<ImprovedSide>>> {
+        if self.instruments.is_some() {
+            bail!("Instrument reference data isn't supported on the stream path");
+        }
         // pin this thread, same parsing logic,  no additional calls to parsing logic in separate method for both parses, redundant but faster
-        core_affinity::set_for_current(core_affinity::CoreId { id: processor_core });
+        if let Some(id) = processor_core {
+            core_affinity::set_for_current(core_affinity::CoreId { id });
+        }
+        // the wall clock is only read for observers that use it
+        let stamp_apply = observer.wants_apply_ts();
 
+        // observers see whole books, so no arena here
         let mut books = FnvHashMap::with_capacity_and_hasher(1024, Default::default());
         let mut in_snapshot_phase = true;
         let mut max_snapshot_seq = 0u64;
+        let mut skips = StreamSkips::default();
 
         loop {
             match receiver.recv() {
-                Ok(StreamMessage::Data(msg_type, data)) => match msg_type {
-                    MessageType::Snapshot if in_snapshot_phase => unsafe {
-                        let ptr = data.as_ptr();
-
-                        let _timestamp = ptr::read_unaligned(ptr as *const u64);
-                        let seq_no = ptr::read_unaligned(ptr.add(8) as *const u64);
-                        let security_id = ptr::read_unaligned(ptr.add(16) as *const u64);
-
-                        max_snapshot_seq = max_snapshot_seq.max(seq_no);
-
-                        let mut book = Lob::new(
-                            security_id,
-                            ImprovedSide::new(true),
-                            ImprovedSide::new(false),
-                        );
-                        book.last_update_seq = Some(seq_no);
-
-                        let mut bid_count = 0;
-                        let mut ask_count = 0;
-                        let mut pos = 24;
-
-                        for _ in 0..5 {
-                            let bid_price_bits = ptr::read_unaligned(ptr.add(pos) as *const u64);
-                            let bid_qty = ptr::read_unaligned(ptr.add(pos + 8) as *const u64);
-                            let ask_price_bits =
-                                ptr::read_unaligned(ptr.add(pos + 16) as *const u64);
-                            let ask_qty = ptr::read_unaligned(ptr.add(pos + 24) as *const u64);
-
-                            if bid_price_bits != 0 && bid_qty != 0 {
-                                book.bids.prices[bid_count] = f64::from_bits(bid_price_bits);
-                                book.bids.qtys[bid_count] = bid_qty;
-                                bid_count += 1;
-                            }
+                Ok(StreamMessage::Data(msg_type, data)) => {
+                    observer.on_message(msg_type);
+
+                    // datagrams are untrusted, the raw reads below rely on this check
+                    if let Err(rejection) = codec::check_datagram(msg_type, &data) {
+                        skips.rejected(observer, msg_type, rejection);
+                        continue;
+                    }
+
+                    match msg_type {
+                        MessageType::Snapshot if in_snapshot_phase => unsafe {
+                            #[cfg(feature = "latency-metrics")]
+                            let start = latency::now_ticks();
+
+                            let ptr = data.as_ptr();
+
+                            let timestamp = ptr::read_unaligned(ptr as *const u64);
+                            let seq_no = ptr::read_unaligned(ptr.add(8) as *const u64);
+                            let security_id = ptr::read_unaligned(ptr.add(16) as *const u64);
+
+                            #[cfg(feature = "latency-metrics")]
+                            let decoded = latency::now_ticks();
+
+                            max_snapshot_seq = max_snapshot_seq.max(seq_no);
+
+                            let mut book = Lob::new(
+                                security_id,
+                                ImprovedSide::new(true),
+                                ImprovedSide::new(false),
+                            );
+                            book.last_update_seq = Some(seq_no);
+                            book.last_exchange_ts = Some(timestamp);
+
+                            let mut bid_count = 0;
+                            let mut ask_count = 0;
+                            let mut pos = 24;
+
+                            for _ in 0..5 {
+                                let bid_price_bits =
+                                    ptr::read_unaligned(ptr.add(pos) as *const u64);
+                                let bid_qty = ptr::read_unaligned(ptr.add(pos + 8) as *const u64);
+                                let ask_price_bits =
+                                    ptr::read_unaligned(ptr.add(pos + 16) as *const u64);
+                                let ask_qty = ptr::read_unaligned(ptr.add(pos + 24) as *const u64);
+
+                                if bid_price_bits != 0
+                                    && bid_qty != 0
+                                    && !f64::from_bits(bid_price_bits).is_nan()
+                                {
+                                    book.bids.prices[bid_count] = f64::from_bits(bid_price_bits);
+                                    book.bids.qtys[bid_count] = bid_qty;
+                                    bid_count += 1;
+                                }
+
+                                if ask_price_bits != 0
+                                    && ask_qty != 0
+                                    && !f64::from_bits(ask_price_bits).is_nan()
+                                {
+                                    book.asks.prices[ask_count] = f64::from_bits(ask_price_bits);
+                                    book.asks.qtys[ask_count] = ask_qty;
+                                    ask_count += 1;
+                                }
 
-                            if ask_price_bits != 0 && ask_qty != 0 {
-                                book.asks.prices[ask_count] = f64::from_bits(ask_price_bits);
-                                book.asks.qtys[ask_count] = ask_qty;
-                                ask_count += 1;
+                                pos += 32;
                             }
 
-                            pos += 32;
-                        }
+                            book.bids.count = bid_count;
+                            book.asks.count = ask_count;
 
-                        book.bids.count = bid_count;
-                        book.asks.count = ask_count;
-
-                        books.insert(security_id, book);
-                    },
-                    MessageType::Incremental if !in_snapshot_phase => unsafe {
-                        let ptr = data.as_ptr();
-
-                        let _timestamp = ptr::read_unaligned(ptr as *const u64);
-                        let seq_no = ptr::read_unaligned(ptr.add(8) as *const u64);
-                        let security_id = ptr::read_unaligned(ptr.add(16) as *const u64);
-                        let num_updates = ptr::read_unaligned(ptr.add(24) as *const u64);
-
-                        if seq_no > max_snapshot_seq {
-                            let mut pos = 32;
-
-                            if let Some(book) = books.get_mut(&security_id) {
-                                for _ in 0..num_updates {
-                                    let side = *ptr.add(pos);
-                                    let price_bits =
-                                        ptr::read_unaligned(ptr.add(pos + 1) as *const u64);
-                                    let price = f64::from_bits(price_bits);
-                                    let qty = ptr::read_unaligned(ptr.add(pos + 9) as *const u64);
-
-                                    if side == 0 {
-                                        if qty == 0 {
-                                            book.bids.remove_l(price);
-                                        } else {
-                                            book.bids.update_l(price, qty);
-                                        }
-                                    } else if side == 1 {
-                                        if qty == 0 {
-                                            book.asks.remove_l(price);
-                                        } else {
-                                            book.asks.update_l(price, qty);
+                            if stamp_apply {
+                                book.last_apply_ts = Some(unix_nanos());
+                            }
+                            observer.on_snapshot(&book);
+                            books.insert(security_id, book);
+
+                            #[cfg(feature = "latency-metrics")]
+                            self.latency
+                                .borrow_mut()
+                                .record(start, decoded, latency::now_ticks());
+                        },
+                        MessageType::Incremental if !in_snapshot_phase => unsafe {
+                            #[cfg(feature = "latency-metrics")]
+                            let start = latency::now_ticks();
+
+                            let ptr = data.as_ptr();
+
+                            let timestamp = ptr::read_unaligned(ptr as *const u64);
+                            let seq_no = ptr::read_unaligned(ptr.add(8) as *const u64);
+                            let security_id = ptr::read_unaligned(ptr.add(16) as *const u64);
+                            let num_updates = ptr::read_unaligned(ptr.add(24) as *const u64);
+
+                            #[cfg(feature = "latency-metrics")]
+                            let decoded = latency::now_ticks();
+
+                            if seq_no > max_snapshot_seq {
+                                let mut pos = 32;
+
+                                if let Some(book) = books.get_mut(&security_id) {
+                                    for _ in 0..num_updates {
+                                        let side = *ptr.add(pos);
+                                        let price_bits =
+                                            ptr::read_unaligned(ptr.add(pos + 1) as *const u64);
+                                        let price = f64::from_bits(price_bits);
+                                        let qty =
+                                            ptr::read_unaligned(ptr.add(pos + 9) as *const u64);
+
+                                        // broken updates are skipped and counted
+                                        match Side::of_update(side, price) {
+                                            Ok(side) => {
+                                                book.update(side, price, qty);
+                                            }
+                                            Err(invalid) => {
+                                                skips.invalid_update(observer, security_id, invalid)
+                                            }
                                         }
+
+                                        pos += INCREMENTAL_SIZE;
                                     }
 
-                                    pos += INCREMENTAL_SIZE;
-                                }
+                                    book.last_update_seq = Some(seq_no);
+                                    book.last_exchange_ts = Some(timestamp);
+                                    if stamp_apply {
+                                        book.last_apply_ts = Some(unix_nanos());
+                                    }
+                                    observer.on_applied(book);
+                                } else {
+                                    let mut book = Lob::new(
+                                        security_id,
+                                        ImprovedSide::new(true),
+                                        ImprovedSide::new(false),
+                                    );
+
+                                    for _ in 0..num_updates {
+                                        let side = *ptr.add(pos);
+                                        let price_bits =
+                                            ptr::read_unaligned(ptr.add(pos + 1) as *const u64);
+                                        let price = f64::from_bits(price_bits);
+                                        let qty =
+                                            ptr::read_unaligned(ptr.add(pos + 9) as *const u64);
+
+                                        match Side::of_update(side, price) {
+                                            Ok(side) => {
+                                                book.update(side, price, qty);
+                                            }
+                                            Err(invalid) => {
+                                                skips.invalid_update(observer, security_id, invalid)
+                                            }
+                                        }
 
-                                book.last_update_seq = Some(seq_no);
-                            } else {
-                                let mut book = Lob::new(
-                                    security_id,
-                                    ImprovedSide::new(true),
-                                    ImprovedSide::new(false),
-                                );
-
-                                for _ in 0..num_updates {
-                                    let side = *ptr.add(pos);
-                                    let price_bits =
-                                        ptr::read_unaligned(ptr.add(pos + 1) as *const u64);
-                                    let price = f64::from_bits(price_bits);
-                                    let qty = ptr::read_unaligned(ptr.add(pos + 9) as *const u64);
-
-                                    if side == 0 {
-                                        book.update(Side::B, price, qty);
-                                    } else if side == 1 {
-                                        book.update(Side::A, price, qty);
+                                        pos += INCREMENTAL_SIZE;
                                     }
 
-                                    pos += INCREMENTAL_SIZE;
+                                    book.last_update_seq = Some(seq_no);
+                                    book.last_exchange_ts = Some(timestamp);
+                                    if stamp_apply {
+                                        book.last_apply_ts = Some(unix_nanos());
+                                    }
+                                    observer.on_applied(&book);
+                                    books.insert(security_id, book);
                                 }
-
-                                book.last_update_seq = Some(seq_no);
-                                books.insert(security_id, book);
                             }
-                        }
-                    },
-                    _ => {}
-                },
+
+                            #[cfg(feature = "latency-metrics")]
+                            self.latency
+                                .borrow_mut()
+                                .record(start, decoded, latency::now_ticks());
+                        },
+                        _ => {}
+                    }
+                }
                 Ok(StreamMessage::EndOfSnapshot) => {
+                    observer.on_message(MessageType::EndOfSnapshot);
                     in_snapshot_phase = false;
                     eprintln!("Snapshot phase completed, max_seq: {}", max_snapshot_seq);
                 }
@@ -533,6 +902,8 @@ impl ImprovedProcessor {
             }
         }
 
+        skips.report();
+
         Ok(books)
     }
 }
@@ -0,0 +1,345 @@
+use crate::codec::{self, IncrementalRecord, Incrementals};
+use crate::*;
+use anyhow::{bail, Context, Result};
+use fnv::{FnvHashMap, FnvHasher};
+use memmap2::Mmap;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{BufWriter, Write};
+
+// sidecar index for incremental.bin: security -> (SeqNo, Timestamp, offset) in file order
+//
+// layout, all little endian:
+//Magic	8 bytes	"LOBIDX02"
+//DataLen	u64	Length of the indexed incremental file
+//Fingerprint	u64	Hash of sampled windows of the indexed incremental file, see `fingerprint`
+//then per message:
+//SecurityID	u64
+//SeqNo	u64
+//Timestamp	u64
+//Offset	u64	Offset of the message in the incremental file
+
+const INDEX_MAGIC: &[u8; 8] = b"LOBIDX02";
+const INDEX_HEADER_SIZE: usize = 8 + 8 + 8;
+const INDEX_ENTRY_SIZE: usize = 8 + 8 + 8 + 8;
+
+// fingerprint reads this many evenly spaced windows of this size, first and last included
+const FINGERPRINT_WINDOWS: usize = 16;
+const FINGERPRINT_WINDOW_SIZE: usize = 4096;
+
+/// Content fingerprint of an incremental file, stored in the index to detect a stale one.
+///
+/// FNV-1a over the length and 16 evenly spaced 4 KiB windows, so opening an index stays cheap
+/// on large files. Files up to 64 KiB are hashed whole, past that an edit that keeps the
+/// length and misses every window goes unnoticed.
+pub fn fingerprint(data: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write_u64(data.len() as u64);
+
+    if data.len() <= FINGERPRINT_WINDOWS * FINGERPRINT_WINDOW_SIZE {
+        hasher.write(data);
+    } else {
+        let last = data.len() - FINGERPRINT_WINDOW_SIZE;
+        for i in 0..FINGERPRINT_WINDOWS {
+            let start = last * i / (FINGERPRINT_WINDOWS - 1);
+            hasher.write(&data[start..start + FINGERPRINT_WINDOW_SIZE]);
+        }
+    }
+
+    hasher.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexEntry {
+    pub seq_no: SeqNo,
+    pub timestamp: u64,
+    pub offset: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct IncrementalIndex {
+    data_len: u64,
+    fingerprint: u64,
+    entries: FnvHashMap<SecurityId, Vec<IndexEntry>>,
+}
+
+impl IncrementalIndex {
+    /// Indexes every message of an incremental file already in memory.
+    pub fn build(data: &[u8]) -> Result<Self> {
+        let mut entries: FnvHashMap<SecurityId, Vec<IndexEntry>> = FnvHashMap::default();
+
+        for record in Incrementals::new(data) {
+            let record = record?;
+            entries
+                .entry(record.header.security_id)
+                .or_default()
+                .push(IndexEntry {
+                    seq_no: record.header.seq_no,
+                    timestamp: record.header.timestamp,
+                    offset: record.offset as u64,
+                });
+        }
+
+        Ok(Self {
+            data_len: data.len() as u64,
+            fingerprint: fingerprint(data),
+            entries,
+        })
+    }
+
+    pub fn build_file(incremental_path: &str) -> Result<Self> {
+        let file = File::open(incremental_path)
+            .with_context(|| format!("Failed to open incremental file: {}", incremental_path))?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        Self::build(&mmap)
+    }
+
+    /// Sidecar path used when none is given, `incremental.bin` -> `incremental.bin.idx`.
+    pub fn default_path(incremental_path: &str) -> String {
+        format!("{}.idx", incremental_path)
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        out.write_all(INDEX_MAGIC)?;
+        out.write_all(&self.data_len.to_le_bytes())?;
+        out.write_all(&self.fingerprint.to_le_bytes())?;
+
+        // sorted so the same file always gives the same index
+        let mut ids: Vec<_> = self.entries.keys().copied().collect();
+        ids.sort();
+
+        for id in ids {
+            for entry in &self.entries[&id] {
+                out.write_all(&id.to_le_bytes())?;
+                out.write_all(&entry.seq_no.to_le_bytes())?;
+                out.write_all(&entry.timestamp.to_le_bytes())?;
+                out.write_all(&entry.offset.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&self, index_path: &str) -> Result<()> {
+        let file = File::create(index_path)
+            .with_context(|| format!("Failed to create index file: {}", index_path))?;
+        let mut out = BufWriter::new(file);
+        self.write(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn load(index_path: &str) -> Result<Self> {
+        let file = File::open(index_path)
+            .with_context(|| format!("Failed to open index file: {}", index_path))?;
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < INDEX_HEADER_SIZE || &mmap[..8] != INDEX_MAGIC {
+            bail!("Not an incremental index: {}", index_path);
+        }
+        if !(mmap.len() - INDEX_HEADER_SIZE).is_multiple_of(INDEX_ENTRY_SIZE) {
+            bail!("Index file is broken, not enough data: {}", index_path);
+        }
+
+        let read = |pos: usize| u64::from_le_bytes(mmap[pos..pos + 8].try_into().unwrap());
+
+        let mut entries: FnvHashMap<SecurityId, Vec<IndexEntry>> = FnvHashMap::default();
+        let mut pos = INDEX_HEADER_SIZE;
+        while pos < mmap.len() {
+            entries.entry(read(pos)).or_default().push(IndexEntry {
+                seq_no: read(pos + 8),
+                timestamp: read(pos + 16),
+                offset: read(pos + 24),
+            });
+            pos += INDEX_ENTRY_SIZE;
+        }
+
+        Ok(Self {
+            data_len: read(8),
+            fingerprint: read(16),
+            entries,
+        })
+    }
+
+    /// True if the index was built from `data`, compares length and [`fingerprint`].
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.data_len == data.len() as u64 && self.fingerprint == fingerprint(data)
+    }
+
+    /// Messages of `security_id` in file order.
+    pub fn entries(&self, security_id: SecurityId) -> &[IndexEntry] {
+        self.entries
+            .get(&security_id)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    pub fn securities(&self) -> impl Iterator<Item = SecurityId> + '_ {
+        self.entries.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Reads one security's messages from an incremental file through its index.
+pub struct IndexedReader {
+    incremental_mmap: Mmap,
+    index: IncrementalIndex,
+}
+
+impl IndexedReader {
+    pub fn open(incremental_path: &str, index: IncrementalIndex) -> Result<Self> {
+        let file = File::open(incremental_path)
+            .with_context(|| format!("Failed to open incremental file: {}", incremental_path))?;
+        let incremental_mmap = unsafe { Mmap::map(&file)? };
+
+        if !index.matches(&incremental_mmap) {
+            bail!(
+                "Index is stale: built for another version of {}",
+                incremental_path
+            );
+        }
+
+        Ok(Self {
+            incremental_mmap,
+            index,
+        })
+    }
+
+    /// Opens with the sidecar at the default path, (re)building and saving it if missing
+    /// or stale.
+    pub fn open_or_build(incremental_path: &str) -> Result<Self> {
+        let index_path = IncrementalIndex::default_path(incremental_path);
+        let file = File::open(incremental_path)
+            .with_context(|| format!("Failed to open incremental file: {}", incremental_path))?;
+        let incremental_mmap = unsafe { Mmap::map(&file)? };
+
+        let index = match IncrementalIndex::load(&index_path) {
+            Ok(index) if index.matches(&incremental_mmap) => index,
+            _ => {
+                let index = IncrementalIndex::build(&incremental_mmap)?;
+                index.save(&index_path)?;
+                index
+            }
+        };
+
+        Ok(Self {
+            incremental_mmap,
+            index,
+        })
+    }
+
+    pub fn index(&self) -> &IncrementalIndex {
+        &self.index
+    }
+
+    /// All messages of `security_id` in file order.
+    pub fn messages(
+        &self,
+        security_id: SecurityId,
+    ) -> impl Iterator<Item = Result<IncrementalRecord<'_>>> {
+        self.messages_from(security_id, 0)
+    }
+
+    /// Messages of `security_id` starting at the first one with SeqNo >= `seq_no`.
+    ///
+    /// Fails on an entry whose offset doesn't hold the security and SeqNo the index says.
+    pub fn messages_from(
+        &self,
+        security_id: SecurityId,
+        seq_no: SeqNo,
+    ) -> impl Iterator<Item = Result<IncrementalRecord<'_>>> {
+        let entries = self.index.entries(security_id);
+        let start = entries
+            .iter()
+            .position(|e| e.seq_no >= seq_no)
+            .unwrap_or(entries.len());
+
+        entries[start..].iter().map(move |entry| {
+            let offset = usize::try_from(entry.offset)
+                .ok()
+                .filter(|&offset| offset < self.incremental_mmap.len())
+                .with_context(|| {
+                    format!("Index offset {} is past the end of the file", entry.offset)
+                })?;
+            let record = Incrementals::from_offset(&self.incremental_mmap, offset)
+                .next()
+                .with_context(|| format!("No incremental at index offset {}", offset))??;
+
+            if record.header.security_id != security_id || record.header.seq_no != entry.seq_no {
+                bail!(
+                    "Index entry for {} SeqNo {} does not match the incremental at offset {}",
+                    security_id,
+                    entry.seq_no,
+                    offset
+                );
+            }
+            Ok(record)
+        })
+    }
+
+    /// Builds one security's book like `process_files` would, reading only its messages.
+    ///
+    /// `None` if the security is neither in the snapshot nor in the incrementals.
+    pub fn process_security<B: BookSide>(
+        &self,
+        snapshot_path: &str,
+        security_id: SecurityId,
+        new_side: fn(bool) -> B,
+    ) -> Result<Option<Lob<B>>> {
+        let snapshot_file = File::open(snapshot_path)
+            .with_context(|| format!("Failed to open snapshot file: {}", snapshot_path))?;
+        let snapshot_mmap = unsafe { Mmap::map(&snapshot_file)? };
+
+        // max SeqNo over all snapshots decides staleness, same as process_files
+        let mut max_snapshot_seq = 0u64;
+        let mut book = None;
+        let mut offset = 0;
+
+        while offset + SNAPSHOT_SIZE <= snapshot_mmap.len() {
+            let record = codec::decode_snapshot(&snapshot_mmap[offset..])?;
+            max_snapshot_seq = max_snapshot_seq.max(record.seq_no);
+
+            if record.security_id == security_id {
+                book = Some(record.to_lob(new_side(true), new_side(false)));
+            }
+
+            offset += SNAPSHOT_SIZE;
+        }
+
+        for record in self.messages_from(security_id, max_snapshot_seq.saturating_add(1)) {
+            let record = record?;
+            let seq_no = record.header.seq_no;
+
+            if seq_no <= max_snapshot_seq {
+                continue;
+            }
+
+            let book =
+                book.get_or_insert_with(|| Lob::new(security_id, new_side(true), new_side(false)));
+
+            for (side, price, qty) in record.updates() {
+                let side = match Side::of_update(side, price) {
+                    Ok(side) => side,
+                    Err(InvalidUpdate::Side(side)) => {
+                        bail!("Invalid side: {} in SeqNo {}", side, seq_no)
+                    }
+                    // skipped like the processors do
+                    Err(InvalidUpdate::NanPrice) => continue,
+                };
+                book.update(side, price, qty);
+            }
+            book.last_update_seq = Some(seq_no);
+            book.last_exchange_ts = Some(record.header.timestamp);
+        }
+
+        Ok(book)
+    }
+}
@@ -0,0 +1,264 @@
+use crate::codec::{self, Incrementals};
+use crate::*;
+use anyhow::Result;
+use fnv::FnvHashSet;
+use std::fmt;
+use std::io::Write;
+
+// human readable dump of the raw files, every field as stored, nothing is applied
+
+/// Something in a record the processors would choke on or silently mis-handle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anomaly {
+    /// Side byte other than 0 or 1.
+    InvalidSide(u8),
+    NanPrice,
+    /// Zero price with a non-zero quantity, an empty snapshot level has both at zero.
+    ZeroPriceWithQty,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::InvalidSide(side) => write!(f, "invalid side {}", side),
+            Anomaly::NanPrice => write!(f, "NaN price"),
+            Anomaly::ZeroPriceWithQty => write!(f, "zero price with qty"),
+        }
+    }
+}
+
+/// Anomaly of one snapshot level, if any.
+pub fn level_anomaly(price: f64, qty: Qty) -> Option<Anomaly> {
+    if price.is_nan() {
+        Some(Anomaly::NanPrice)
+    } else if price == 0.0 && qty != 0 {
+        Some(Anomaly::ZeroPriceWithQty)
+    } else {
+        None
+    }
+}
+
+/// Anomaly of one incremental update, if any. An invalid side wins over a bad price.
+pub fn update_anomaly(side: u8, price: f64, qty: Qty) -> Option<Anomaly> {
+    if Side::from_u8(side).is_none() {
+        Some(Anomaly::InvalidSide(side))
+    } else {
+        level_anomaly(price, qty)
+    }
+}
+
+/// Which records are printed, everything by default. Ranges are inclusive.
+#[derive(Debug, Clone, Default)]
+pub struct InspectFilter {
+    pub securities: Option<FnvHashSet<SecurityId>>,
+    pub min_seq: Option<SeqNo>,
+    pub max_seq: Option<SeqNo>,
+    /// Timestamps in ms, same as the files.
+    pub min_timestamp: Option<u64>,
+    pub max_timestamp: Option<u64>,
+    /// Only records with at least one anomaly.
+    pub anomalies_only: bool,
+}
+
+impl InspectFilter {
+    pub fn matches(&self, timestamp: u64, seq_no: SeqNo, security_id: SecurityId) -> bool {
+        self.securities
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&security_id))
+            && self.min_seq.is_none_or(|min| seq_no >= min)
+            && self.max_seq.is_none_or(|max| seq_no <= max)
+            && self.min_timestamp.is_none_or(|min| timestamp >= min)
+            && self.max_timestamp.is_none_or(|max| timestamp <= max)
+    }
+}
+
+/// What one pass over a file found.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InspectSummary {
+    pub records: u64,
+    /// Records that passed the filter and were printed.
+    pub shown: u64,
+    /// Anomalies in all records, filtered or not.
+    pub anomalies: u64,
+    /// Offset of a truncated record at the end of the file, decoding stops there.
+    pub truncated_at: Option<usize>,
+}
+
+impl fmt::Display for InspectSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} records, {} shown, {} anomalies",
+            self.records, self.shown, self.anomalies
+        )?;
+        if let Some(offset) = self.truncated_at {
+            write!(f, ", truncated at offset {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints decoded records to `out`, one header line per record and one line per level
+/// or update. Anomalies are marked with `<-` at the end of the line.
+pub struct Inspector<W: Write> {
+    out: W,
+    filter: InspectFilter,
+}
+
+impl<W: Write> Inspector<W> {
+    pub fn new(out: W, filter: InspectFilter) -> Self {
+        Self { out, filter }
+    }
+
+    /// Dumps a `snapshot.bin` buffer.
+    pub fn snapshots(&mut self, data: &[u8]) -> Result<InspectSummary> {
+        let mut summary = InspectSummary::default();
+
+        for (i, chunk) in data.chunks(SNAPSHOT_SIZE).enumerate() {
+            let offset = i * SNAPSHOT_SIZE;
+            if chunk.len() < SNAPSHOT_SIZE {
+                writeln!(
+                    self.out,
+                    "@{} truncated snapshot, {} of {} bytes",
+                    offset,
+                    chunk.len(),
+                    SNAPSHOT_SIZE
+                )?;
+                summary.truncated_at = Some(offset);
+                break;
+            }
+
+            let record = codec::decode_snapshot(chunk)?;
+            summary.records += 1;
+
+            let anomalies: Vec<_> = record
+                .bids
+                .iter()
+                .chain(record.asks.iter())
+                .map(|&(price, qty)| level_anomaly(price, qty))
+                .collect();
+            let count = anomalies.iter().flatten().count() as u64;
+            summary.anomalies += count;
+
+            if !self.show(record.timestamp, record.seq_no, record.security_id, count) {
+                continue;
+            }
+            summary.shown += 1;
+
+            writeln!(
+                self.out,
+                "@{} snapshot ts {} seq {} sec {}",
+                offset, record.timestamp, record.seq_no, record.security_id
+            )?;
+            for level in 0..SNAPSHOT_LEVELS {
+                let (bid_price, bid_qty) = record.bids[level];
+                let (ask_price, ask_qty) = record.asks[level];
+                write!(
+                    self.out,
+                    "  {} bid {} x {} ask {} x {}",
+                    level + 1,
+                    bid_price,
+                    bid_qty,
+                    ask_price,
+                    ask_qty
+                )?;
+                let marks = [
+                    (anomalies[level], "bid"),
+                    (anomalies[SNAPSHOT_LEVELS + level], "ask"),
+                ];
+                self.mark(marks.iter().filter_map(|&(a, side)| a.map(|a| (side, a))))?;
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Dumps an `incremental.bin` buffer.
+    pub fn incrementals(&mut self, data: &[u8]) -> Result<InspectSummary> {
+        let mut summary = InspectSummary::default();
+        let mut end = 0;
+
+        for record in Incrementals::new(data) {
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    writeln!(self.out, "@{} {:#}", end, e)?;
+                    summary.truncated_at = Some(end);
+                    return Ok(summary);
+                }
+            };
+
+            summary.records += 1;
+            end = record.offset + INCREMENTAL_HEADER_SIZE + record.body.len();
+
+            let header = record.header;
+            let count = record
+                .updates()
+                .filter_map(|(side, price, qty)| update_anomaly(side, price, qty))
+                .count() as u64;
+            summary.anomalies += count;
+
+            if !self.show(header.timestamp, header.seq_no, header.security_id, count) {
+                continue;
+            }
+            summary.shown += 1;
+
+            writeln!(
+                self.out,
+                "@{} incremental ts {} seq {} sec {} updates {}",
+                record.offset,
+                header.timestamp,
+                header.seq_no,
+                header.security_id,
+                header.num_updates
+            )?;
+            for (side, price, qty) in record.updates() {
+                let side_name = match Side::from_u8(side) {
+                    Some(Side::B) => "bid",
+                    Some(Side::A) => "ask",
+                    None => "side?",
+                };
+                write!(self.out, "  {} {} x {}", side_name, price, qty)?;
+                self.mark(update_anomaly(side, price, qty).map(|a| ("", a)))?;
+            }
+        }
+
+        // a tail shorter than a header, the iterator skips it
+        if end < data.len() {
+            writeln!(
+                self.out,
+                "@{} truncated incremental, {} bytes",
+                end,
+                data.len() - end
+            )?;
+            summary.truncated_at = Some(end);
+        }
+
+        Ok(summary)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn show(&self, timestamp: u64, seq_no: SeqNo, security_id: SecurityId, anomalies: u64) -> bool {
+        (!self.filter.anomalies_only || anomalies > 0)
+            && self.filter.matches(timestamp, seq_no, security_id)
+    }
+
+    // ends the current line, with the anomalies if there are any
+    fn mark<'a>(&mut self, anomalies: impl IntoIterator<Item = (&'a str, Anomaly)>) -> Result<()> {
+        let mut first = true;
+        for (what, anomaly) in anomalies {
+            let sep = if first { "  <- " } else { ", " };
+            if what.is_empty() {
+                write!(self.out, "{}{}", sep, anomaly)?;
+            } else {
+                write!(self.out, "{}{} {}", sep, what, anomaly)?;
+            }
+            first = false;
+        }
+        writeln!(self.out)?;
+        Ok(())
+    }
+}
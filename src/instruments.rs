@@ -0,0 +1,293 @@
+use crate::improved::MAX_LEVELS;
+use crate::*;
+use anyhow::{bail, Context, Result};
+use fnv::FnvHashMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::hash::BuildHasher;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+// reference data loaded before the feed. with it the processors create every known book up
+// front, skip updates off the instrument's tick and decide what happens to messages for
+// ids the file doesn't know, instead of quietly opening a book for any id that shows up
+//
+// scope: only the file paths use it, process_stream_with refuses a processor built with
+// instruments. the processors read the tick size and max depth, lot size and price scale
+// are carried for the caller and written back out but never checked
+//
+// text file, one instrument per line, blank lines and # comments skipped, optional header:
+//security_id,symbol,tick_size,lot_size,price_scale,max_depth
+//1,AAPL,0.01,100,2,10
+
+const HEADER: &str = "security_id,symbol,tick_size,lot_size,price_scale,max_depth";
+
+// prices are f64 quotients of the tick, this much of a tick off still counts as on it
+const TICK_TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instrument {
+    pub security_id: SecurityId,
+    pub symbol: String,
+    pub tick_size: f64,
+    /// Quantities are meant to be multiples of this, not checked by the processors.
+    pub lot_size: Qty,
+    /// Decimals prices are quoted with, not used by the processors.
+    pub price_scale: u32,
+    /// Levels per side basic books are preallocated for, a capacity hint and not a limit:
+    /// basic books grow past it, improved books always hold `MAX_LEVELS`.
+    pub max_depth: usize,
+}
+
+impl Instrument {
+    /// Whether `price` is a whole number of ticks.
+    #[inline(always)]
+    pub fn on_tick(&self, price: f64) -> bool {
+        let ticks = price / self.tick_size;
+        (ticks - ticks.round()).abs() <= TICK_TOLERANCE
+    }
+}
+
+/// What the processors do with messages for securities not in the reference file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownPolicy {
+    /// Open a book for them as if there were no reference file.
+    #[default]
+    Create,
+    /// Skip their messages.
+    Reject,
+    /// Build their books apart from the others, see `process_files_with_quarantine`.
+    Quarantine,
+}
+
+impl FromStr for UnknownPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "create" => Ok(UnknownPolicy::Create),
+            "reject" => Ok(UnknownPolicy::Reject),
+            "quarantine" => Ok(UnknownPolicy::Quarantine),
+            _ => bail!(
+                "Unknown policy '{}', expected create, reject or quarantine",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for UnknownPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            UnknownPolicy::Create => "create",
+            UnknownPolicy::Reject => "reject",
+            UnknownPolicy::Quarantine => "quarantine",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Where a message for one security goes.
+#[derive(Debug, Clone, Copy)]
+pub enum Route<'a> {
+    /// The normal books, checked against the instrument when there is one.
+    Book(Option<&'a Instrument>),
+    Reject,
+    Quarantine,
+}
+
+/// The instrument universe, ordered by security id.
+#[derive(Debug, Clone, Default)]
+pub struct Instruments {
+    list: Vec<Instrument>,
+    by_id: FnvHashMap<SecurityId, usize>,
+    by_symbol: FnvHashMap<String, usize>,
+    pub unknown: UnknownPolicy,
+}
+
+impl Instruments {
+    /// Fails on duplicate ids or symbols and on values no book could use.
+    pub fn new(mut list: Vec<Instrument>, unknown: UnknownPolicy) -> Result<Self> {
+        list.sort_by_key(|instrument| instrument.security_id);
+
+        let mut by_id = FnvHashMap::with_capacity_and_hasher(list.len(), Default::default());
+        let mut by_symbol = FnvHashMap::with_capacity_and_hasher(list.len(), Default::default());
+        for (i, instrument) in list.iter().enumerate() {
+            check(instrument)?;
+            if by_id.insert(instrument.security_id, i).is_some() {
+                bail!("Duplicate security id {}", instrument.security_id);
+            }
+            if let Some(other) = by_symbol.insert(instrument.symbol.clone(), i) {
+                bail!(
+                    "Symbol {} used by securities {} and {}",
+                    instrument.symbol,
+                    list[other].security_id,
+                    instrument.security_id
+                );
+            }
+        }
+
+        Ok(Self {
+            list,
+            by_id,
+            by_symbol,
+            unknown,
+        })
+    }
+
+    pub fn parse(text: &str, unknown: UnknownPolicy) -> Result<Self> {
+        let mut list = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || (list.is_empty() && line == HEADER) {
+                continue;
+            }
+            list.push(parse_line(line).with_context(|| format!("line {}", i + 1))?);
+        }
+
+        Self::new(list, unknown)
+    }
+
+    pub fn load(path: &str, unknown: UnknownPolicy) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read instrument file: {}", path))?;
+        Self::parse(&text, unknown).with_context(|| format!("Invalid instrument file: {}", path))
+    }
+
+    /// Same format `parse` reads, header included.
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        for i in &self.list {
+            writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                i.security_id, i.symbol, i.tick_size, i.lot_size, i.price_scale, i.max_depth
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn get(&self, security_id: SecurityId) -> Option<&Instrument> {
+        self.by_id.get(&security_id).map(|&i| &self.list[i])
+    }
+
+    pub fn by_symbol(&self, symbol: &str) -> Option<&Instrument> {
+        self.by_symbol.get(symbol).map(|&i| &self.list[i])
+    }
+
+    /// Symbol of `security_id`, `None` for a security not in the file.
+    pub fn symbol(&self, security_id: SecurityId) -> Option<&str> {
+        self.get(security_id)
+            .map(|instrument| instrument.symbol.as_str())
+    }
+
+    /// Book of the security listed as `symbol`, from books keyed by id.
+    pub fn book<'a, B: BookSide, S: BuildHasher>(
+        &self,
+        books: &'a HashMap<SecurityId, Lob<B>, S>,
+        symbol: &str,
+    ) -> Option<&'a Lob<B>> {
+        books.get(&self.by_symbol(symbol)?.security_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Instrument> {
+        self.list.iter()
+    }
+
+    /// Where a message for `security_id` goes under the `unknown` policy.
+    #[inline(always)]
+    pub fn route(&self, security_id: SecurityId) -> Route<'_> {
+        match (self.get(security_id), self.unknown) {
+            (Some(instrument), _) => Route::Book(Some(instrument)),
+            (None, UnknownPolicy::Create) => Route::Book(None),
+            (None, UnknownPolicy::Reject) => Route::Reject,
+            (None, UnknownPolicy::Quarantine) => Route::Quarantine,
+        }
+    }
+}
+
+/// `route` for processors that may run without reference data.
+#[inline(always)]
+pub fn route(instruments: Option<&Instruments>, security_id: SecurityId) -> Route<'_> {
+    match instruments {
+        Some(instruments) => instruments.route(security_id),
+        None => Route::Book(None),
+    }
+}
+
+fn parse_line(line: &str) -> Result<Instrument> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let [security_id, symbol, tick_size, lot_size, price_scale, max_depth] = fields[..] else {
+        bail!("Expected 6 fields ({}), got {}", HEADER, fields.len());
+    };
+
+    fn field<T: FromStr>(name: &str, value: &str) -> Result<T>
+    where
+        T::Err: fmt::Display,
+    {
+        value
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid {} '{}': {}", name, value, e))
+    }
+
+    Ok(Instrument {
+        security_id: field("security_id", security_id)?,
+        symbol: symbol.to_string(),
+        tick_size: field("tick_size", tick_size)?,
+        lot_size: field("lot_size", lot_size)?,
+        price_scale: field("price_scale", price_scale)?,
+        max_depth: field("max_depth", max_depth)?,
+    })
+}
+
+fn check(instrument: &Instrument) -> Result<()> {
+    let id = instrument.security_id;
+    // the file is comma separated, symbols are typed on the command line and printed in
+    // json and csv as they are
+    let ok = |c: char| c.is_ascii_graphic() && !matches!(c, ',' | '"' | '\\');
+    if instrument.symbol.is_empty() || !instrument.symbol.chars().all(ok) {
+        bail!("Invalid symbol '{}' for security {}", instrument.symbol, id);
+    }
+    if !(instrument.tick_size > 0.0 && instrument.tick_size.is_finite()) {
+        bail!(
+            "Invalid tick size {} for security {}",
+            instrument.tick_size,
+            id
+        );
+    }
+    if instrument.lot_size == 0 || instrument.max_depth == 0 {
+        bail!(
+            "Lot size and max depth must be at least 1 for security {}",
+            id
+        );
+    }
+    // a hint both backends can honour, improved sides never hold more
+    if instrument.max_depth > MAX_LEVELS {
+        bail!(
+            "Max depth {} for security {} is over {}",
+            instrument.max_depth,
+            id,
+            MAX_LEVELS
+        );
+    }
+    // f64 carries about 17 significant digits
+    if instrument.price_scale > 17 {
+        bail!(
+            "Invalid price scale {} for security {}",
+            instrument.price_scale,
+            id
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,296 @@
+use crate::*;
+use fnv::FnvHashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+// log-linear buckets: values below 16 are exact, every power of two above that is split
+// into 16 linear sub buckets, so any value is within ~6% of its bucket bound
+const SUB_BITS: u32 = 4;
+const SUB_COUNT: usize = 1 << SUB_BITS;
+const BUCKETS: usize = (64 - SUB_BITS as usize + 1) * SUB_COUNT;
+
+/// Fixed size latency histogram in nanoseconds, recording never allocates.
+#[derive(Clone)]
+pub struct LatencyHistogram {
+    counts: [u64; BUCKETS],
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            counts: [0; BUCKETS],
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn record(&mut self, value: u64) {
+        self.counts[bucket_index(value)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (a, b) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *a += b;
+        }
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.count == 0 {
+            0
+        } else {
+            self.min
+        }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.count as f64
+        }
+    }
+
+    /// Value at `percentile` (0..=100), reported as the upper bound of its bucket.
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+
+        for (i, &n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return bucket_upper(i).min(self.max);
+            }
+        }
+
+        self.max
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "count {} min {} p50 {} p90 {} p99 {} p99.9 {} max {} (ns)",
+            self.count,
+            self.min(),
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.percentile(99.9),
+            self.max
+        )
+    }
+}
+
+#[inline(always)]
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_COUNT as u64 {
+        return value as usize;
+    }
+
+    let exp = 63 - value.leading_zeros();
+    let shift = exp - SUB_BITS;
+    let group = (shift + 1) as usize;
+    let sub = ((value >> shift) as usize) & (SUB_COUNT - 1);
+
+    group * SUB_COUNT + sub
+}
+
+fn bucket_upper(index: usize) -> u64 {
+    if index < SUB_COUNT {
+        return index as u64;
+    }
+
+    let shift = (index / SUB_COUNT - 1) as u32;
+    let sub = (index % SUB_COUNT) as u64;
+    let lower = (SUB_COUNT as u64 + sub) << shift;
+
+    lower + ((1u64 << shift) - 1)
+}
+
+/// Raw timestamp for measuring short intervals, `rdtsc` on x86_64.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub fn now_ticks() -> u64 {
+    #[allow(unused_unsafe)]
+    unsafe {
+        std::arch::x86_64::_rdtsc()
+    }
+}
+
+/// Raw timestamp for measuring short intervals, nanoseconds since the first call.
+#[cfg(not(target_arch = "x86_64"))]
+#[inline(always)]
+pub fn now_ticks() -> u64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+/// Nanoseconds per `now_ticks` tick, measured once against `Instant` (takes ~10ms).
+pub fn nanos_per_tick() -> f64 {
+    use std::sync::OnceLock;
+    use std::time::{Duration, Instant};
+
+    static NANOS_PER_TICK: OnceLock<f64> = OnceLock::new();
+
+    *NANOS_PER_TICK.get_or_init(|| {
+        if cfg!(not(target_arch = "x86_64")) {
+            return 1.0;
+        }
+
+        let start = Instant::now();
+        let start_ticks = now_ticks();
+        while start.elapsed() < Duration::from_millis(10) {
+            std::hint::spin_loop();
+        }
+        let ticks = now_ticks().wrapping_sub(start_ticks).max(1);
+
+        start.elapsed().as_nanos() as f64 / ticks as f64
+    })
+}
+
+/// Decode and apply time of every message handled by a processor.
+///
+/// Filled by the processors only with the `latency-metrics` feature, the clock is
+/// calibrated when the recorder is created so recording stays cheap.
+#[derive(Clone)]
+pub struct LatencyRecorder {
+    pub decode: LatencyHistogram,
+    pub apply: LatencyHistogram,
+    nanos_per_tick: f64,
+}
+
+impl LatencyRecorder {
+    pub fn new() -> Self {
+        Self {
+            decode: LatencyHistogram::new(),
+            apply: LatencyHistogram::new(),
+            nanos_per_tick: nanos_per_tick(),
+        }
+    }
+
+    /// Records one message from three `now_ticks` readings.
+    #[inline(always)]
+    pub fn record(&mut self, start: u64, decoded: u64, applied: u64) {
+        self.decode
+            .record((decoded.wrapping_sub(start) as f64 * self.nanos_per_tick) as u64);
+        self.apply
+            .record((applied.wrapping_sub(decoded) as f64 * self.nanos_per_tick) as u64);
+    }
+
+    pub fn reset(&mut self) {
+        self.decode.reset();
+        self.apply.reset();
+    }
+
+    pub fn report<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "decode {}", self.decode)?;
+        writeln!(out, "apply {}", self.apply)
+    }
+}
+
+impl Default for LatencyRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per security exchange-to-apply latency, fed from `process_stream_with`.
+///
+/// Latency is `last_apply_ts` minus `last_exchange_ts` converted to nanoseconds, so it
+/// includes any clock offset between the exchange and this host.
+#[derive(Default)]
+pub struct LatencyTracker {
+    per_security: FnvHashMap<SecurityId, Box<LatencyHistogram>>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline(always)]
+    pub fn record<B: BookSide>(&mut self, book: &Lob<B>) {
+        if let (Some(exchange_ts), Some(apply_ts)) = (book.last_exchange_ts, book.last_apply_ts) {
+            let latency = apply_ts.saturating_sub(exchange_ts.saturating_mul(1_000_000));
+            self.per_security
+                .entry(book.security_id)
+                .or_default()
+                .record(latency);
+        }
+    }
+
+    pub fn histogram(&self, security_id: SecurityId) -> Option<&LatencyHistogram> {
+        self.per_security.get(&security_id).map(|h| h.as_ref())
+    }
+
+    /// All securities merged.
+    pub fn total(&self) -> LatencyHistogram {
+        let mut total = LatencyHistogram::new();
+        for histogram in self.per_security.values() {
+            total.merge(histogram);
+        }
+        total
+    }
+
+    /// One line per security ordered by id, then the total.
+    pub fn report<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut ids: Vec<_> = self.per_security.keys().copied().collect();
+        ids.sort();
+
+        for id in ids {
+            writeln!(out, "sec id {} latency {}", id, self.per_security[&id])?;
+        }
+        writeln!(out, "total latency {}", self.total())
+    }
+}
+
+impl<B: BookSide> StreamObserver<B> for LatencyTracker {
+    fn on_applied(&mut self, book: &Lob<B>) {
+        self.record(book);
+    }
+
+    fn wants_apply_ts(&self) -> bool {
+        true
+    }
+}
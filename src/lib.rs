@@ -1,5 +1,27 @@
+pub mod analytics;
+pub mod arena;
 pub mod basic;
+pub mod codec;
+pub mod conflate;
+pub mod diff;
+pub mod differential;
+pub mod faults;
+pub mod generator;
 pub mod improved;
+pub mod index;
+pub mod inspect;
+pub mod instruments;
+pub mod latency;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod output;
+pub mod replay;
+pub mod simd;
+pub mod stats;
+pub mod tick;
+pub mod validate;
+
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub type SecurityId = u64;
 pub type SeqNo = u64;
@@ -14,6 +36,8 @@ pub type Qty = u64;
 //AskQtyN	u64	Quantity at the lowest ask price
 // 5 levels
 
+pub const SNAPSHOT_LEVELS: usize = 5;
+
 pub const SNAPSHOT_SIZE: usize = 8 + 8 + 8 + (8 + 8) * 10;
 
 //Timestamp	u64	Timestamp in milliseconds
@@ -68,6 +92,25 @@ impl Side {
             _ => None,
         }
     }
+
+    /// Side of an incremental update, if a book can take the update at all.
+    #[inline(always)]
+    pub fn of_update(side: u8, price: f64) -> Result<Self, InvalidUpdate> {
+        let side = Side::from_u8(side).ok_or(InvalidUpdate::Side(side))?;
+        // NaN can't be ordered against the levels
+        if price.is_nan() {
+            return Err(InvalidUpdate::NanPrice);
+        }
+        Ok(side)
+    }
+}
+
+/// Why an incremental update can't be applied to a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidUpdate {
+    /// Side byte other than 0 or 1.
+    Side(u8),
+    NanPrice,
 }
 
 #[derive(Debug, Clone)]
@@ -76,17 +119,211 @@ pub struct Level {
     pub quantity: Qty,
 }
 
+/// What a single level update did to a side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelChange {
+    Inserted,
+    Updated,
+    Removed,
+    /// Removal of a price that isn't in the book.
+    NotFound,
+    /// New level not stored because the side is full.
+    Dropped,
+}
+
 pub trait BookSide {
-    fn update_l(&mut self, price: f64, qty: Qty);
-    fn remove_l(&mut self, price: f64);
+    fn update_l(&mut self, price: f64, qty: Qty) -> LevelChange;
+    fn remove_l(&mut self, price: f64) -> LevelChange;
     fn get_l(&self) -> Vec<Level>;
+
+    /// Number of levels, `get_l().len()` without the copy.
+    #[inline(always)]
+    fn depth(&self) -> usize {
+        self.get_l().len()
+    }
+
+    /// Price and quantity of level `i`, 0 for the best, without copying the side.
+    #[inline(always)]
+    fn level(&self, i: usize) -> Option<(f64, Qty)> {
+        self.get_l()
+            .get(i)
+            .map(|level| (level.price, level.quantity))
+    }
+}
+
+/// Local wall clock in nanoseconds since the unix epoch, for `Lob::last_apply_ts`.
+#[inline(always)]
+pub fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Hooks called by the processors' `process_stream_with` as books change.
+pub trait StreamObserver<B: BookSide> {
+    /// Called for every message taken off the channel, before it is handled.
+    #[inline(always)]
+    fn on_message(&mut self, _msg_type: MessageType) {}
+
+    /// Called after a book was built from a snapshot message.
+    #[inline(always)]
+    fn on_snapshot(&mut self, _book: &Lob<B>) {}
+
+    /// Called after an incremental was applied to `book`.
+    #[inline(always)]
+    fn on_applied(&mut self, _book: &Lob<B>) {}
+
+    /// Called for a message dropped unread because its length is wrong for its type.
+    #[inline(always)]
+    fn on_rejected(&mut self, _msg_type: MessageType, _rejection: codec::Rejection) {}
+
+    /// Called for an update skipped because `Side::of_update` refused it.
+    #[inline(always)]
+    fn on_invalid_update(&mut self, _security_id: SecurityId, _invalid: InvalidUpdate) {}
+
+    /// True if the observer reads `Lob::last_apply_ts`, the processors only read the wall
+    /// clock per message then.
+    #[inline(always)]
+    fn wants_apply_ts(&self) -> bool {
+        false
+    }
+}
+
+// no-op observer for plain process_stream
+impl<B: BookSide> StreamObserver<B> for () {}
+
+impl<B: BookSide, O: StreamObserver<B> + ?Sized> StreamObserver<B> for &mut O {
+    #[inline(always)]
+    fn on_message(&mut self, msg_type: MessageType) {
+        (**self).on_message(msg_type);
+    }
+
+    #[inline(always)]
+    fn on_snapshot(&mut self, book: &Lob<B>) {
+        (**self).on_snapshot(book);
+    }
+
+    #[inline(always)]
+    fn on_applied(&mut self, book: &Lob<B>) {
+        (**self).on_applied(book);
+    }
+
+    #[inline(always)]
+    fn on_rejected(&mut self, msg_type: MessageType, rejection: codec::Rejection) {
+        (**self).on_rejected(msg_type, rejection);
+    }
+
+    #[inline(always)]
+    fn on_invalid_update(&mut self, security_id: SecurityId, invalid: InvalidUpdate) {
+        (**self).on_invalid_update(security_id, invalid);
+    }
+
+    #[inline(always)]
+    fn wants_apply_ts(&self) -> bool {
+        (**self).wants_apply_ts()
+    }
+}
+
+// two observers on one stream, e.g. a LatencyTracker and a MetricsObserver
+impl<B: BookSide, X: StreamObserver<B>, Y: StreamObserver<B>> StreamObserver<B> for (X, Y) {
+    #[inline(always)]
+    fn on_message(&mut self, msg_type: MessageType) {
+        self.0.on_message(msg_type);
+        self.1.on_message(msg_type);
+    }
+
+    #[inline(always)]
+    fn on_snapshot(&mut self, book: &Lob<B>) {
+        self.0.on_snapshot(book);
+        self.1.on_snapshot(book);
+    }
+
+    #[inline(always)]
+    fn on_applied(&mut self, book: &Lob<B>) {
+        self.0.on_applied(book);
+        self.1.on_applied(book);
+    }
+
+    #[inline(always)]
+    fn on_rejected(&mut self, msg_type: MessageType, rejection: codec::Rejection) {
+        self.0.on_rejected(msg_type, rejection);
+        self.1.on_rejected(msg_type, rejection);
+    }
+
+    #[inline(always)]
+    fn on_invalid_update(&mut self, security_id: SecurityId, invalid: InvalidUpdate) {
+        self.0.on_invalid_update(security_id, invalid);
+        self.1.on_invalid_update(security_id, invalid);
+    }
+
+    #[inline(always)]
+    fn wants_apply_ts(&self) -> bool {
+        self.0.wants_apply_ts() || self.1.wants_apply_ts()
+    }
+}
+
+// what the stream paths skipped instead of failing, printed once the channel closes
+#[derive(Default)]
+pub(crate) struct StreamSkips {
+    rejected: u64,
+    invalid_sides: u64,
+    invalid_prices: u64,
+}
+
+impl StreamSkips {
+    #[inline(always)]
+    pub(crate) fn rejected<B: BookSide, O: StreamObserver<B>>(
+        &mut self,
+        observer: &mut O,
+        msg_type: MessageType,
+        rejection: codec::Rejection,
+    ) {
+        self.rejected += 1;
+        observer.on_rejected(msg_type, rejection);
+    }
+
+    #[inline(always)]
+    pub(crate) fn invalid_update<B: BookSide, O: StreamObserver<B>>(
+        &mut self,
+        observer: &mut O,
+        security_id: SecurityId,
+        invalid: InvalidUpdate,
+    ) {
+        match invalid {
+            InvalidUpdate::Side(_) => self.invalid_sides += 1,
+            InvalidUpdate::NanPrice => self.invalid_prices += 1,
+        }
+        observer.on_invalid_update(security_id, invalid);
+    }
+
+    pub(crate) fn report(&self) {
+        if self.rejected > 0 {
+            eprintln!("Rejected {} malformed messages", self.rejected);
+        }
+        if self.invalid_sides > 0 {
+            eprintln!(
+                "Skipped {} updates with an invalid side",
+                self.invalid_sides
+            );
+        }
+        if self.invalid_prices > 0 {
+            eprintln!("Skipped {} updates with a NaN price", self.invalid_prices);
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct Lob<B: BookSide> {
     pub security_id: SecurityId,
     pub bids: B,
     pub asks: B,
     pub last_update_seq: Option<SeqNo>,
+    /// Timestamp (ms) of the last applied snapshot or incremental.
+    pub last_exchange_ts: Option<u64>,
+    /// Local time (ns since epoch) the last message was applied, stream path only and only
+    /// for observers whose `wants_apply_ts` is true.
+    pub last_apply_ts: Option<u64>,
 }
 
 impl<B: BookSide> Lob<B> {
@@ -97,24 +334,26 @@ impl<B: BookSide> Lob<B> {
             bids,
             asks,
             last_update_seq: None,
+            last_exchange_ts: None,
+            last_apply_ts: None,
         }
     }
 
     #[inline(always)]
-    pub fn update(&mut self, side: Side, price: f64, qty: Qty) {
+    pub fn update(&mut self, side: Side, price: f64, qty: Qty) -> LevelChange {
         match side {
             Side::B => {
                 if qty == 0 {
-                    self.bids.remove_l(price);
+                    self.bids.remove_l(price)
                 } else {
-                    self.bids.update_l(price, qty);
+                    self.bids.update_l(price, qty)
                 }
             }
             Side::A => {
                 if qty == 0 {
-                    self.asks.remove_l(price);
+                    self.asks.remove_l(price)
                 } else {
-                    self.asks.update_l(price, qty);
+                    self.asks.update_l(price, qty)
                 }
             }
         }
@@ -1,44 +1,697 @@
-use anyhow::{bail, Result};
+mod cli;
+
+use anyhow::{Context, Result};
+use cli::{Backend, BookOptions, Command, Format, Reference, SecurityFilter};
+use crossbeam::channel::{self, Receiver};
 use fnv::FnvHashMap;
-use lob_processor::{BookSide, Lob};
+use lob_processor::basic::{Basic, BasicProcessor};
+use lob_processor::codec;
+use lob_processor::faults::FaultConfig;
+use lob_processor::generator::GeneratorConfig;
+use lob_processor::improved::{ImprovedProcessor, ImprovedSide};
+use lob_processor::inspect::{InspectFilter, Inspector};
+use lob_processor::instruments::{Instruments, UnknownPolicy};
+use lob_processor::latency::LatencyTracker;
+#[cfg(feature = "metrics")]
+use lob_processor::metrics::{Metrics, MetricsObserver, MetricsServer};
+use lob_processor::replay::{Pacing, Replay};
+use lob_processor::stats::ProcessingStats;
+use lob_processor::validate::ValidationReport;
+use lob_processor::*;
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+#[cfg(feature = "metrics")]
+use std::sync::Arc;
+#[cfg(feature = "metrics")]
+use std::time::Duration;
+
+// exit codes: 0 ok, 1 validation or check found problems, 2 the command itself failed
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let command = match Command::parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("error: {:#}\nrun `lob_processor help` for usage", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(command) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+// false when the input was readable but not valid
+fn run(command: Command) -> Result<bool> {
+    match command {
+        Command::Process {
+            snapshot,
+            incremental,
+            mut books,
+            latency,
+            metrics,
+            core,
+            reference,
+        } => {
+            let instruments = load_instruments(reference.as_ref())?;
+            books.filter.resolve(instruments.as_ref())?;
+            match books.backend {
+                Backend::Basic => process(
+                    &BasicProcessor::with_reference(instruments),
+                    &snapshot,
+                    &incremental,
+                    &books,
+                    latency,
+                    metrics.as_deref(),
+                    core,
+                )?,
+                Backend::Improved => process(
+                    &ImprovedProcessor::with_reference(instruments),
+                    &snapshot,
+                    &incremental,
+                    &books,
+                    latency,
+                    metrics.as_deref(),
+                    core,
+                )?,
+            }
+        }
+        Command::Stats {
+            snapshot,
+            incremental,
+            backend,
+            mut filter,
+            reference,
+        } => {
+            let instruments = load_instruments(reference.as_ref())?;
+            filter.resolve(instruments.as_ref())?;
+            let mut stats = match backend {
+                Backend::Basic => {
+                    BasicProcessor::with_reference(instruments.clone())
+                        .books_with_stats(&snapshot, &incremental)?
+                        .1
+                }
+                Backend::Improved => {
+                    ImprovedProcessor::with_reference(instruments.clone())
+                        .books_with_stats(&snapshot, &incremental)?
+                        .1
+                }
+            };
+            stats.per_security.retain(|&id, _| filter.matches(id));
+            stats.report(&mut io::stdout().lock(), instruments.as_ref())?;
+        }
+        Command::Inspect {
+            snapshot,
+            incremental,
+            filter,
+        } => inspect(snapshot.as_deref(), incremental.as_deref(), filter)?,
+        Command::Validate {
+            snapshot,
+            incremental,
+            max_issues,
+        } => return validate(snapshot.as_deref(), incremental.as_deref(), max_issues),
+        Command::Convert {
+            snapshot,
+            incremental,
+            output,
+            backend,
+            mut filter,
+            reference,
+        } => {
+            let instruments = load_instruments(reference.as_ref())?;
+            filter.resolve(instruments.as_ref())?;
+            match backend {
+                Backend::Basic => {
+                    let books = BasicProcessor::with_reference(instruments)
+                        .books(&snapshot, &incremental)?;
+                    convert(&books, &output, &filter)?
+                }
+                Backend::Improved => {
+                    let books = ImprovedProcessor::with_reference(instruments)
+                        .books(&snapshot, &incremental)?;
+                    convert(&books, &output, &filter)?
+                }
+            }
+        }
+        Command::Generate {
+            snapshot,
+            incremental,
+            config,
+            faults,
+            truth,
+            fault_log,
+            instruments,
+        } => generate(
+            &snapshot,
+            &incremental,
+            config,
+            faults,
+            truth.as_deref(),
+            fault_log.as_deref(),
+            instruments.as_deref(),
+        )?,
+        Command::Replay {
+            snapshot,
+            incremental,
+            books,
+            until,
+            speed,
+        } => {
+            let pacing = speed.map_or(Pacing::AsFastAsPossible, |speed| Pacing::Realtime { speed });
+            match books.backend {
+                Backend::Basic => {
+                    let replay = Replay::open(&snapshot, &incremental, Basic::new)?;
+                    replay_books(replay.with_pacing(pacing)?, until, &books)?
+                }
+                Backend::Improved => {
+                    let replay = Replay::open(&snapshot, &incremental, ImprovedSide::new)?;
+                    replay_books(replay.with_pacing(pacing)?, until, &books)?
+                }
+            }
+        }
+        Command::Diff {
+            from,
+            to,
+            output,
+            backend,
+            filter,
+        } => match backend {
+            Backend::Basic => diff(&from, &to, output.as_deref(), &filter, Basic::new)?,
+            Backend::Improved => diff(&from, &to, output.as_deref(), &filter, ImprovedSide::new)?,
+        },
+        Command::Check {
+            snapshot,
+            incremental,
+        } => {
+            let comparison = differential::compare_files(&snapshot, &incremental)?;
+            comparison.report(&mut io::stdout().lock())?;
+            return Ok(comparison.is_match());
+        }
+        Command::Help => println!("{}", cli::USAGE),
+    }
+
+    Ok(true)
+}
+
+// what the commands need from a processor, both return books ordered by security id
+trait Processor: Sized {
+    type Side: BookSide;
+
+    fn with_reference(instruments: Option<Instruments>) -> Self;
+
+    fn reference(&self) -> Option<&Instruments>;
+
+    fn books(&self, snapshot: &str, incremental: &str) -> Result<Vec<Lob<Self::Side>>>;
+
+    /// Books, then the books of unknown securities quarantined apart from them.
+    #[allow(clippy::type_complexity)]
+    fn books_with_quarantine(
+        &self,
+        snapshot: &str,
+        incremental: &str,
+    ) -> Result<(Vec<Lob<Self::Side>>, Vec<Lob<Self::Side>>)>;
+
+    fn books_with_stats(
+        &self,
+        snapshot: &str,
+        incremental: &str,
+    ) -> Result<(Vec<Lob<Self::Side>>, ProcessingStats)>;
+
+    fn stream_books<O: StreamObserver<Self::Side>>(
+        &self,
+        receiver: Receiver<StreamMessage>,
+        core: Option<usize>,
+        observer: &mut O,
+    ) -> Result<Vec<Lob<Self::Side>>>;
+
+    fn report_latency<W: Write>(&self, out: &mut W) -> io::Result<()>;
+}
+
+macro_rules! impl_processor {
+    ($processor:ty, $side:ty) => {
+        impl Processor for $processor {
+            type Side = $side;
+
+            fn with_reference(instruments: Option<Instruments>) -> Self {
+                match instruments {
+                    Some(instruments) => Self::new().with_instruments(instruments),
+                    None => Self::new(),
+                }
+            }
+
+            fn reference(&self) -> Option<&Instruments> {
+                self.instruments()
+            }
+
+            fn books(&self, snapshot: &str, incremental: &str) -> Result<Vec<Lob<$side>>> {
+                Ok(sorted(self.process_files(snapshot, incremental)?))
+            }
+
+            fn books_with_quarantine(
+                &self,
+                snapshot: &str,
+                incremental: &str,
+            ) -> Result<(Vec<Lob<$side>>, Vec<Lob<$side>>)> {
+                let (books, quarantined) =
+                    self.process_files_with_quarantine(snapshot, incremental)?;
+                Ok((sorted(books), sorted(quarantined)))
+            }
 
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+            fn books_with_stats(
+                &self,
+                snapshot: &str,
+                incremental: &str,
+            ) -> Result<(Vec<Lob<$side>>, ProcessingStats)> {
+                let (books, stats) = self.process_files_with_stats(snapshot, incremental)?;
+                Ok((sorted(books), stats))
+            }
 
-    if args.len() < 2 {
-        bail!("Usage: {} <snapshot.bin> <incremental.bin>", args[0]);
+            fn stream_books<O: StreamObserver<$side>>(
+                &self,
+                receiver: Receiver<StreamMessage>,
+                core: Option<usize>,
+                observer: &mut O,
+            ) -> Result<Vec<Lob<$side>>> {
+                Ok(sorted(self.process_stream_with(receiver, core, observer)?))
+            }
+
+            #[allow(unused_variables)]
+            fn report_latency<W: Write>(&self, out: &mut W) -> io::Result<()> {
+                #[cfg(feature = "latency-metrics")]
+                self.latency().report(out)?;
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_processor!(BasicProcessor, Basic);
+impl_processor!(ImprovedProcessor, ImprovedSide);
+
+fn load_instruments(reference: Option<&Reference>) -> Result<Option<Instruments>> {
+    reference
+        .map(|reference| Instruments::load(&reference.path, reference.unknown))
+        .transpose()
+}
+
+fn sorted<B: BookSide>(books: impl IntoIterator<Item = (SecurityId, Lob<B>)>) -> Vec<Lob<B>> {
+    let mut books: Vec<_> = books.into_iter().map(|(_, book)| book).collect();
+    books.sort_by_key(|book| book.security_id);
+    books
+}
+
+fn process<P: Processor>(
+    processor: &P,
+    snapshot: &str,
+    incremental: &str,
+    options: &BookOptions,
+    latency: bool,
+    metrics: Option<&str>,
+    core: Option<usize>,
+) -> Result<()> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    #[cfg(feature = "metrics")]
+    let mut server = None;
+
+    // the stream path takes no reference data, so nothing is quarantined there
+    let (books, quarantined) = if latency || metrics.is_some() {
+        // push the files through the stream path so apply time is measured
+        let messages = codec::stream_messages(&fs::read(snapshot)?, &fs::read(incremental)?)?;
+        let (sender, receiver) = channel::unbounded();
+        for message in messages {
+            sender.send(message)?;
+        }
+        drop(sender);
+
+        let mut tracker = LatencyTracker::new();
+        let books = match metrics {
+            #[cfg(feature = "metrics")]
+            Some(addr) => {
+                let (books, started) =
+                    stream_with_metrics(processor, receiver, core, &mut tracker, addr)?;
+                server = Some(started);
+                books
+            }
+            _ => processor.stream_books(receiver, core, &mut tracker)?,
+        };
+
+        // keep json and csv output parseable
+        if latency && options.format == Format::Text {
+            tracker.report(&mut out)?;
+            processor.report_latency(&mut out)?;
+            writeln!(out)?;
+        } else if latency {
+            let mut err = io::stderr().lock();
+            tracker.report(&mut err)?;
+            processor.report_latency(&mut err)?;
+        }
+        (books, Vec::new())
+    } else {
+        processor.books_with_quarantine(snapshot, incremental)?
+    };
+
+    // kept out of the output, listed so they aren't lost silently
+    if !quarantined.is_empty() {
+        let ids: Vec<_> = quarantined.iter().map(|book| book.security_id).collect();
+        eprintln!(
+            "quarantined {} books of unknown securities: {:?}",
+            ids.len(),
+            ids
+        );
     }
 
-    let processor = lob_processor::improved::ImprovedProcessor::new();
-    let books = processor.process_files(&args[1], &args[2])?;
+    print_books(&mut out, &books, options, processor.reference())?;
+    out.flush()?;
 
-    print_order_books(&books);
+    // the final values stay up for scraping
+    #[cfg(feature = "metrics")]
+    if let Some(server) = server {
+        eprintln!(
+            "serving metrics on http://{}/metrics until killed",
+            server.local_addr()
+        );
+        loop {
+            std::thread::park();
+        }
+    }
 
     Ok(())
 }
 
-fn print_order_books<T>(books: &FnvHashMap<u64, Lob<T>>)
+// runs the stream with the tracker and a metrics observer, the server is started first
+// so the channel depth is visible while the backlog drains
+#[cfg(feature = "metrics")]
+fn stream_with_metrics<P: Processor>(
+    processor: &P,
+    receiver: Receiver<StreamMessage>,
+    core: Option<usize>,
+    tracker: &mut LatencyTracker,
+    addr: &str,
+) -> Result<(Vec<Lob<P::Side>>, MetricsServer)> {
+    let metrics = Arc::new(Metrics::new());
+    let server = MetricsServer::start(addr, metrics.clone(), Some(receiver.clone()))?;
+
+    let observer =
+        MetricsObserver::new(metrics, Duration::from_secs(5), Duration::from_millis(100));
+    let mut observers = (tracker, observer);
+    let books = processor.stream_books(receiver, core, &mut observers)?;
+    observers.1.publish();
+
+    Ok((books, server))
+}
+
+fn print_books<'a, B, W>(
+    out: &mut W,
+    books: impl IntoIterator<Item = &'a Lob<B>>,
+    options: &BookOptions,
+    instruments: Option<&Instruments>,
+) -> io::Result<()>
 where
-    T: BookSide,
+    B: BookSide + 'a,
+    W: Write,
 {
-    let mut sorted_ids: Vec<_> = books.keys().copied().collect();
-    sorted_ids.sort();
+    let mut books: Vec<_> = books
+        .into_iter()
+        .filter(|book| options.filter.matches(book.security_id))
+        .collect();
+    books.sort_by_key(|book| book.security_id);
+
+    let depth = options.depth.unwrap_or(usize::MAX);
+
+    match options.format {
+        Format::Text => {
+            for book in books {
+                match instruments.and_then(|instruments| instruments.symbol(book.security_id)) {
+                    Some(symbol) => writeln!(out, "sec id {} {}", book.security_id, symbol)?,
+                    None => writeln!(out, "sec id {}", book.security_id)?,
+                }
+
+                if options.timestamps {
+                    writeln!(
+                        out,
+                        "last exchange ts {} last apply ts {}",
+                        fmt_ts(book.last_exchange_ts),
+                        fmt_ts(book.last_apply_ts)
+                    )?;
+                }
+
+                writeln!(out, "bids:")?;
+                for level in book.bids.get_l().iter().take(depth) {
+                    writeln!(out, "  {:.2} --- {}", level.price, level.quantity)?;
+                }
+
+                writeln!(out, "asks:")?;
+                for level in book.asks.get_l().iter().take(depth) {
+                    writeln!(out, "  {:.2} --- {}", level.price, level.quantity)?;
+                }
+
+                writeln!(out)?;
+            }
+        }
+        Format::Json => output::write_json_lines(out, books, options.depth, instruments)?,
+        Format::Csv => output::write_csv(out, books, options.depth, instruments)?,
+    }
+
+    Ok(())
+}
+
+fn fmt_ts(ts: Option<u64>) -> String {
+    ts.map_or_else(|| "-".to_string(), |ts| ts.to_string())
+}
+
+fn fmt_side(side: Side) -> &'static str {
+    match side {
+        Side::B => "bid",
+        Side::A => "ask",
+    }
+}
+
+fn inspect(snapshot: Option<&str>, incremental: Option<&str>, filter: InspectFilter) -> Result<()> {
+    let mut inspector = Inspector::new(BufWriter::new(io::stdout().lock()), filter);
+    let mut summaries = Vec::new();
+
+    if let Some(path) = snapshot {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+        summaries.push((path, inspector.snapshots(&data)?));
+    }
+
+    if let Some(path) = incremental {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+        summaries.push((path, inspector.incrementals(&data)?));
+    }
+
+    let mut out = inspector.into_inner();
+    for (path, summary) in summaries {
+        writeln!(out, "{}: {}", path, summary)?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+fn validate(snapshot: Option<&str>, incremental: Option<&str>, max_issues: usize) -> Result<bool> {
+    let mut report = ValidationReport::new(max_issues);
+
+    if let Some(path) = snapshot {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+        report.check_snapshots(&data);
+    }
+
+    if let Some(path) = incremental {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+        report.check_incrementals(&data);
+    }
+
+    report.report(&mut io::stdout().lock())?;
+    Ok(report.is_valid())
+}
+
+fn convert<B: BookSide>(books: &[Lob<B>], output: &str, filter: &SecurityFilter) -> Result<()> {
+    let books: Vec<_> = books
+        .iter()
+        .filter(|book| filter.matches(book.security_id))
+        .collect();
+    let timestamp = books
+        .iter()
+        .filter_map(|book| book.last_exchange_ts)
+        .max()
+        .unwrap_or(0);
+
+    let mut out = BufWriter::new(
+        File::create(output).with_context(|| format!("Failed to create {}", output))?,
+    );
+    codec::write_snapshots(&mut out, books.iter().copied(), timestamp)?;
+    out.flush()?;
+
+    println!("wrote {} books to {}", books.len(), output);
+    Ok(())
+}
+
+fn generate(
+    snapshot: &str,
+    incremental: &str,
+    config: GeneratorConfig,
+    fault_config: Option<FaultConfig>,
+    truth: Option<&str>,
+    fault_log: Option<&str>,
+    instruments: Option<&str>,
+) -> Result<()> {
+    let (generator, injected) = match fault_config {
+        Some(fault_config) => {
+            faults::generate_faulty_files(config, &fault_config, snapshot, incremental)?
+        }
+        None => (
+            generator::generate_files(config, snapshot, incremental)?,
+            Vec::new(),
+        ),
+    };
+    let config = generator.config();
+
+    println!(
+        "wrote {} snapshots to {} and {} incrementals to {}, seed {}",
+        config.securities, snapshot, config.messages, incremental, config.seed
+    );
 
-    for id in sorted_ids {
-        let book = &books[&id];
-        println!("sec id {}", book.security_id);
+    if !injected.is_empty() {
+        println!(
+            "injected {} faults in {} securities",
+            injected.len(),
+            faults::affected_securities(&injected).len()
+        );
+    }
+
+    if let Some(path) = truth {
+        let mut out = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path))?,
+        );
+        faults::write_ground_truth(&mut out, &generator)?;
+        out.flush()?;
+    }
+
+    if let Some(path) = instruments {
+        let mut out = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path))?,
+        );
+        generator
+            .instruments(UnknownPolicy::default())
+            .write(&mut out)?;
+        out.flush()?;
+    }
+
+    if let Some(path) = fault_log {
+        let mut out = BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path))?,
+        );
+        faults::write_fault_log(&mut out, &injected)?;
+        out.flush()?;
+    }
+
+    Ok(())
+}
 
-        println!("bids:");
-        for level in book.bids.get_l() {
-            println!("  {:.2} --- {}", level.price, level.quantity);
+fn replay_books<B: BookSide>(
+    mut replay: Replay<B>,
+    until: Option<replay::Until>,
+    options: &BookOptions,
+) -> Result<()> {
+    match until {
+        Some(until) => {
+            replay.run_until(until)?;
         }
+        None => while replay.step()?.is_some() {},
+    }
+
+    eprintln!("replayed {} of {}", replay.position(), replay.len());
+    if replay.skipped_updates() > 0 {
+        eprintln!(
+            "skipped {} updates with an invalid side or price",
+            replay.skipped_updates()
+        );
+    }
+    let mut out = BufWriter::new(io::stdout().lock());
+    print_books(&mut out, replay.books().values(), options, None)?;
+    out.flush()?;
+
+    Ok(())
+}
 
-        println!("asks:");
-        for level in book.asks.get_l() {
-            println!("  {:.2} --- {}", level.price, level.quantity);
+fn load_snapshot<B: BookSide>(
+    path: &str,
+    new_side: fn(bool) -> B,
+) -> Result<FnvHashMap<SecurityId, Lob<B>>> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+    let mut books = FnvHashMap::default();
+
+    for chunk in data.chunks_exact(SNAPSHOT_SIZE) {
+        let record = codec::decode_snapshot(chunk)?;
+        books.insert(
+            record.security_id,
+            record.to_lob(new_side(true), new_side(false)),
+        );
+    }
+
+    Ok(books)
+}
+
+fn diff<B: BookSide + Clone>(
+    from: &str,
+    to: &str,
+    output: Option<&str>,
+    filter: &SecurityFilter,
+    new_side: fn(bool) -> B,
+) -> Result<()> {
+    let from_books = load_snapshot(from, new_side)?;
+    let to_books = load_snapshot(to, new_side)?;
+
+    let mut ids: Vec<_> = from_books
+        .keys()
+        .chain(to_books.keys())
+        .copied()
+        .filter(|&id| filter.matches(id))
+        .collect();
+    ids.sort();
+    ids.dedup();
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut encoded = Vec::new();
+
+    for id in ids {
+        // a security missing on one side diffs against an empty book
+        let empty = || Lob::new(id, new_side(true), new_side(false));
+        let from_book = from_books.get(&id).cloned().unwrap_or_else(empty);
+        let to_book = to_books.get(&id).cloned().unwrap_or_else(empty);
+
+        let updates = diff::diff_books(&from_book, &to_book)?;
+        if updates.is_empty() {
+            continue;
+        }
+
+        writeln!(out, "sec id {}", id)?;
+        for (side, price, qty) in &updates {
+            writeln!(out, "  {} {} x {}", fmt_side(*side), price, qty)?;
         }
 
-        println!();
+        codec::encode_incremental(
+            &mut encoded,
+            to_book.last_exchange_ts.unwrap_or(0),
+            to_book.last_update_seq.unwrap_or(0),
+            id,
+            &updates,
+        );
     }
+    out.flush()?;
+
+    if let Some(output) = output {
+        fs::write(output, &encoded).with_context(|| format!("Failed to write {}", output))?;
+    }
+
+    Ok(())
 }
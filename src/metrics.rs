@@ -0,0 +1,399 @@
+use crate::latency::LatencyHistogram;
+use crate::*;
+use anyhow::{Context, Result};
+use crossbeam::channel::Receiver;
+use fnv::FnvHashMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// prometheus text exposition for a long running stream processor
+//
+// the processing thread only does relaxed atomic adds through MetricsObserver, anything
+// that needs a scan (book count, stale books, percentiles) is computed there periodically
+// and published as gauges, the http thread just reads atomics
+
+const MESSAGE_TYPES: [(MessageType, &str); 3] = [
+    (MessageType::Snapshot, "snapshot"),
+    (MessageType::Incremental, "incremental"),
+    (MessageType::EndOfSnapshot, "end_of_snapshot"),
+];
+
+const PERCENTILES: [(f64, &str); 4] = [
+    (50.0, "0.5"),
+    (90.0, "0.9"),
+    (99.0, "0.99"),
+    (99.9, "0.999"),
+];
+
+#[inline(always)]
+fn type_index(msg_type: MessageType) -> usize {
+    msg_type as usize - 1
+}
+
+#[inline(always)]
+fn invalid_index(invalid: InvalidUpdate) -> usize {
+    match invalid {
+        InvalidUpdate::Side(_) => 0,
+        InvalidUpdate::NanPrice => 1,
+    }
+}
+
+/// Values shared between the processing thread and the http server.
+pub struct Metrics {
+    messages: [AtomicU64; 3],
+    rejected: [AtomicU64; 3],
+    // invalid side, NaN price
+    invalid_updates: [AtomicU64; 2],
+    gaps: AtomicU64,
+    books: AtomicU64,
+    stale_books: AtomicU64,
+    apply_latency: [AtomicU64; 4],
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            messages: Default::default(),
+            rejected: Default::default(),
+            invalid_updates: Default::default(),
+            gaps: AtomicU64::new(0),
+            books: AtomicU64::new(0),
+            stale_books: AtomicU64::new(0),
+            apply_latency: Default::default(),
+        }
+    }
+
+    pub fn messages(&self, msg_type: MessageType) -> u64 {
+        self.messages[type_index(msg_type)].load(Ordering::Relaxed)
+    }
+
+    /// Messages of `msg_type` dropped unread for a wrong length.
+    pub fn rejected(&self, msg_type: MessageType) -> u64 {
+        self.rejected[type_index(msg_type)].load(Ordering::Relaxed)
+    }
+
+    /// Updates skipped because `Side::of_update` refused them for this reason, the side
+    /// byte of `InvalidUpdate::Side` doesn't matter.
+    pub fn invalid_updates(&self, invalid: InvalidUpdate) -> u64 {
+        self.invalid_updates[invalid_index(invalid)].load(Ordering::Relaxed)
+    }
+
+    pub fn gaps(&self) -> u64 {
+        self.gaps.load(Ordering::Relaxed)
+    }
+
+    pub fn books(&self) -> u64 {
+        self.books.load(Ordering::Relaxed)
+    }
+
+    pub fn stale_books(&self) -> u64 {
+        self.stale_books.load(Ordering::Relaxed)
+    }
+
+    /// Prometheus text format. Rendering has no side effects, so scrapers don't disturb
+    /// each other; rates are left to the query side (`rate(lob_messages_total[1m])`).
+    pub fn render(&self, channel_depth: Option<usize>) -> String {
+        let mut out = String::with_capacity(2048);
+
+        out.push_str("# HELP lob_messages_total Stream messages received by type.\n");
+        out.push_str("# TYPE lob_messages_total counter\n");
+        for (msg_type, name) in MESSAGE_TYPES {
+            let _ = writeln!(
+                out,
+                "lob_messages_total{{type=\"{}\"}} {}",
+                name,
+                self.messages(msg_type)
+            );
+        }
+
+        out.push_str(
+            "# HELP lob_rejected_messages_total Messages dropped unread for a wrong length.\n",
+        );
+        out.push_str("# TYPE lob_rejected_messages_total counter\n");
+        for (msg_type, name) in MESSAGE_TYPES {
+            let _ = writeln!(
+                out,
+                "lob_rejected_messages_total{{type=\"{}\"}} {}",
+                name,
+                self.rejected(msg_type)
+            );
+        }
+
+        out.push_str("# HELP lob_invalid_updates_total Updates skipped for a bad side or price.\n");
+        out.push_str("# TYPE lob_invalid_updates_total counter\n");
+        for (invalid, reason) in [
+            (InvalidUpdate::Side(0), "side"),
+            (InvalidUpdate::NanPrice, "nan_price"),
+        ] {
+            let _ = writeln!(
+                out,
+                "lob_invalid_updates_total{{reason=\"{}\"}} {}",
+                reason,
+                self.invalid_updates(invalid)
+            );
+        }
+
+        out.push_str("# HELP lob_seq_gaps_total Applied incrementals whose SeqNo skipped ahead.\n");
+        out.push_str("# TYPE lob_seq_gaps_total counter\n");
+        let _ = writeln!(out, "lob_seq_gaps_total {}", self.gaps());
+
+        out.push_str("# HELP lob_books Books held by the processor.\n");
+        out.push_str("# TYPE lob_books gauge\n");
+        let _ = writeln!(out, "lob_books {}", self.books());
+
+        out.push_str("# HELP lob_stale_books Books not updated within the stale threshold.\n");
+        out.push_str("# TYPE lob_stale_books gauge\n");
+        let _ = writeln!(out, "lob_stale_books {}", self.stale_books());
+
+        if let Some(depth) = channel_depth {
+            out.push_str("# HELP lob_channel_depth Messages waiting in the processor channel.\n");
+            out.push_str("# TYPE lob_channel_depth gauge\n");
+            let _ = writeln!(out, "lob_channel_depth {}", depth);
+        }
+
+        out.push_str(
+            "# HELP lob_apply_latency_seconds Exchange to apply latency over the last interval.\n",
+        );
+        out.push_str("# TYPE lob_apply_latency_seconds gauge\n");
+        for (i, (_, quantile)) in PERCENTILES.iter().enumerate() {
+            let nanos = self.apply_latency[i].load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "lob_apply_latency_seconds{{quantile=\"{}\"}} {}",
+                quantile,
+                nanos as f64 / 1e9
+            );
+        }
+
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Feeds `Metrics` from `process_stream_with`, lives on the processing thread.
+pub struct MetricsObserver {
+    metrics: Arc<Metrics>,
+    // last apply time per book, ns since epoch
+    last_apply: FnvHashMap<SecurityId, u64>,
+    latency: LatencyHistogram,
+    last_seq: Option<SeqNo>,
+    stale_after: Duration,
+    publish_every: Duration,
+    last_publish: Instant,
+}
+
+impl MetricsObserver {
+    /// Books without an update for `stale_after` count as stale, gauges are refreshed
+    /// every `publish_every`.
+    pub fn new(metrics: Arc<Metrics>, stale_after: Duration, publish_every: Duration) -> Self {
+        Self {
+            metrics,
+            last_apply: FnvHashMap::default(),
+            latency: LatencyHistogram::new(),
+            last_seq: None,
+            stale_after,
+            publish_every,
+            last_publish: Instant::now(),
+        }
+    }
+
+    /// Refreshes the gauges now, also call it once the stream ends.
+    pub fn publish(&mut self) {
+        let threshold = unix_nanos().saturating_sub(self.stale_after.as_nanos() as u64);
+        let stale = self
+            .last_apply
+            .values()
+            .filter(|&&applied| applied < threshold)
+            .count();
+
+        self.metrics
+            .books
+            .store(self.last_apply.len() as u64, Ordering::Relaxed);
+        self.metrics
+            .stale_books
+            .store(stale as u64, Ordering::Relaxed);
+
+        // empty interval keeps the previous values
+        if self.latency.count() > 0 {
+            for (i, (percentile, _)) in PERCENTILES.iter().enumerate() {
+                self.metrics.apply_latency[i]
+                    .store(self.latency.percentile(*percentile), Ordering::Relaxed);
+            }
+            self.latency.reset();
+        }
+
+        self.last_publish = Instant::now();
+    }
+
+    #[inline(always)]
+    fn maybe_publish(&mut self) {
+        if self.last_publish.elapsed() >= self.publish_every {
+            self.publish();
+        }
+    }
+}
+
+impl<B: BookSide> StreamObserver<B> for MetricsObserver {
+    #[inline(always)]
+    fn on_message(&mut self, msg_type: MessageType) {
+        self.metrics.messages[type_index(msg_type)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    fn on_rejected(&mut self, msg_type: MessageType, _rejection: codec::Rejection) {
+        self.metrics.rejected[type_index(msg_type)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    fn on_invalid_update(&mut self, _security_id: SecurityId, invalid: InvalidUpdate) {
+        self.metrics.invalid_updates[invalid_index(invalid)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_snapshot(&mut self, book: &Lob<B>) {
+        self.last_apply
+            .insert(book.security_id, book.last_apply_ts.unwrap_or(0));
+        self.maybe_publish();
+    }
+
+    fn on_applied(&mut self, book: &Lob<B>) {
+        if let Some(seq_no) = book.last_update_seq {
+            if let Some(last) = self.last_seq {
+                if seq_no > last.saturating_add(1) {
+                    self.metrics.gaps.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            self.last_seq = Some(self.last_seq.map_or(seq_no, |last| last.max(seq_no)));
+        }
+
+        if let (Some(exchange_ts), Some(apply_ts)) = (book.last_exchange_ts, book.last_apply_ts) {
+            self.latency
+                .record(apply_ts.saturating_sub(exchange_ts.saturating_mul(1_000_000)));
+        }
+
+        self.last_apply
+            .insert(book.security_id, book.last_apply_ts.unwrap_or(0));
+        self.maybe_publish();
+    }
+
+    fn wants_apply_ts(&self) -> bool {
+        true
+    }
+}
+
+/// Minimal http server answering `GET /metrics`, one thread, one request per connection.
+pub struct MetricsServer {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Binds `addr` (port 0 picks a free one) and serves `metrics`. `channel` is only used
+    /// to report its depth, the server never receives from it.
+    pub fn start<A: ToSocketAddrs>(
+        addr: A,
+        metrics: Arc<Metrics>,
+        channel: Option<Receiver<StreamMessage>>,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr).context("Failed to bind metrics listener")?;
+        let addr = listener.local_addr()?;
+        // non blocking accept so stop() doesn't hang
+        listener.set_nonblocking(true)?;
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stop = shutdown.clone();
+
+        let handle = thread::Builder::new()
+            .name("lob-metrics".into())
+            .spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            let depth = channel.as_ref().map(Receiver::len);
+                            // a broken client must not take the server down
+                            let _ = serve(stream, &metrics, depth);
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                            thread::sleep(Duration::from_millis(10));
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(10)),
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            addr,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn stop(mut self) {
+        self.shutdown_now();
+    }
+
+    fn shutdown_now(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.shutdown_now();
+    }
+}
+
+fn serve(mut stream: TcpStream, metrics: &Metrics, depth: Option<usize>) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+    // read until the end of the headers, the body of a GET is ignored
+    let mut request = Vec::with_capacity(512);
+    let mut buf = [0u8; 512];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    // "GET /metrics HTTP/1.1"
+    let mut request_line = request.split(|&b| b == b' ');
+    let method = request_line.next().unwrap_or(&[]);
+    let path = request_line.next().unwrap_or(&[]);
+    let is_metrics = method == b"GET" && (path == b"/metrics" || path == b"/");
+
+    let (status, content_type, body) = if is_metrics {
+        ("200 OK", "text/plain; version=0.0.4", metrics.render(depth))
+    } else {
+        ("404 Not Found", "text/plain", "not found\n".to_string())
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
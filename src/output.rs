@@ -0,0 +1,160 @@
+use crate::instruments::Instruments;
+use crate::*;
+use std::io::{self, Write};
+
+// machine readable dumps of books, prices are written with `{}` which round trips every
+// f64 exactly instead of the `{:.2}` of the text listing. given instruments, books carry
+// their symbol as well, the output without them is unchanged
+
+/// Column names of [`write_csv`], one row per level.
+pub const CSV_HEADER: &str = "security,side,level,price,qty,seq";
+
+/// [`CSV_HEADER`] with instruments, symbol is empty for securities not in the file.
+pub const CSV_HEADER_SYMBOLS: &str = "security,symbol,side,level,price,qty,seq";
+
+/// Writes `book` as one JSON object on one line, at most `depth` levels per side.
+///
+/// `{"security":1,"seq":73,"exchange_ts":..,"apply_ts":..,"bids":[{"price":..,"qty":..}],"asks":[..]}`,
+/// missing seq/timestamps and non-finite prices are `null`. With `instruments` a
+/// `"symbol"` follows `"security"`, `null` for a security not in the file.
+pub fn write_json<B: BookSide, W: Write>(
+    out: &mut W,
+    book: &Lob<B>,
+    depth: Option<usize>,
+    instruments: Option<&Instruments>,
+) -> io::Result<()> {
+    write!(out, "{{\"security\":{},", book.security_id)?;
+    if let Some(instruments) = instruments {
+        // symbols are checked on load, nothing in them needs escaping
+        match instruments.symbol(book.security_id) {
+            Some(symbol) => write!(out, "\"symbol\":\"{}\",", symbol)?,
+            None => out.write_all(b"\"symbol\":null,")?,
+        }
+    }
+    write!(
+        out,
+        "\"seq\":{},\"exchange_ts\":{},\"apply_ts\":{},\"bids\":",
+        json_opt(book.last_update_seq),
+        json_opt(book.last_exchange_ts),
+        json_opt(book.last_apply_ts)
+    )?;
+    write_json_levels(out, &book.bids.get_l(), depth)?;
+    out.write_all(b",\"asks\":")?;
+    write_json_levels(out, &book.asks.get_l(), depth)?;
+    out.write_all(b"}\n")
+}
+
+/// [`write_json`] for every book, ordered by security id.
+pub fn write_json_lines<'a, B, W, I>(
+    out: &mut W,
+    books: I,
+    depth: Option<usize>,
+    instruments: Option<&Instruments>,
+) -> io::Result<()>
+where
+    B: BookSide + 'a,
+    W: Write,
+    I: IntoIterator<Item = &'a Lob<B>>,
+{
+    for book in sorted(books) {
+        write_json(out, book, depth, instruments)?;
+    }
+    Ok(())
+}
+
+/// Writes the rows of `book` without a header, level is 1 for the top of book.
+///
+/// With `instruments` the rows have the symbol column of [`CSV_HEADER_SYMBOLS`].
+pub fn write_csv_rows<B: BookSide, W: Write>(
+    out: &mut W,
+    book: &Lob<B>,
+    depth: Option<usize>,
+    instruments: Option<&Instruments>,
+) -> io::Result<()> {
+    let seq = book
+        .last_update_seq
+        .map_or_else(String::new, |seq| seq.to_string());
+    let security = match instruments {
+        Some(instruments) => format!(
+            "{},{}",
+            book.security_id,
+            instruments.symbol(book.security_id).unwrap_or("")
+        ),
+        None => book.security_id.to_string(),
+    };
+
+    for (side, levels) in [("bid", book.bids.get_l()), ("ask", book.asks.get_l())] {
+        for (i, level) in levels.iter().take(depth.unwrap_or(usize::MAX)).enumerate() {
+            writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                security,
+                side,
+                i + 1,
+                level.price,
+                level.quantity,
+                seq
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The header then the rows of every book, ordered by security id.
+pub fn write_csv<'a, B, W, I>(
+    out: &mut W,
+    books: I,
+    depth: Option<usize>,
+    instruments: Option<&Instruments>,
+) -> io::Result<()>
+where
+    B: BookSide + 'a,
+    W: Write,
+    I: IntoIterator<Item = &'a Lob<B>>,
+{
+    let header = match instruments {
+        Some(_) => CSV_HEADER_SYMBOLS,
+        None => CSV_HEADER,
+    };
+    writeln!(out, "{}", header)?;
+    for book in sorted(books) {
+        write_csv_rows(out, book, depth, instruments)?;
+    }
+    Ok(())
+}
+
+fn write_json_levels<W: Write>(
+    out: &mut W,
+    levels: &[Level],
+    depth: Option<usize>,
+) -> io::Result<()> {
+    out.write_all(b"[")?;
+    for (i, level) in levels.iter().take(depth.unwrap_or(usize::MAX)).enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write!(out, "{{\"price\":")?;
+        if level.price.is_finite() {
+            write!(out, "{}", level.price)?;
+        } else {
+            out.write_all(b"null")?;
+        }
+        write!(out, ",\"qty\":{}}}", level.quantity)?;
+    }
+    out.write_all(b"]")
+}
+
+fn json_opt(value: Option<u64>) -> String {
+    value.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+fn sorted<'a, B, I>(books: I) -> Vec<&'a Lob<B>>
+where
+    B: BookSide + 'a,
+    I: IntoIterator<Item = &'a Lob<B>>,
+{
+    let mut sorted: Vec<_> = books.into_iter().collect();
+    sorted.sort_by_key(|book| book.security_id);
+    sorted
+}
@@ -0,0 +1,255 @@
+use crate::codec::{self, IncrementalHeader, Incrementals};
+use crate::*;
+use anyhow::{bail, Context, Result};
+use fnv::FnvHashMap;
+use memmap2::Mmap;
+use std::fs::File;
+use std::time::{Duration, Instant};
+
+// replay of a recorded feed in Timestamp order, same sequencing rules as process_files:
+// all snapshots first, incrementals with SeqNo <= max snapshot SeqNo are skipped. updates
+// with an invalid side or a NaN price are skipped and counted, the rest of their message
+// still applies, so a broken message never stops the replay
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    /// No waiting between messages.
+    AsFastAsPossible,
+    /// Wait out the Timestamp gaps (milliseconds), `speed` 2.0 replays twice as fast.
+    Realtime { speed: f64 },
+}
+
+/// Where `run_until` stops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Until {
+    /// Stop before the first message with a later Timestamp.
+    Timestamp(u64),
+    /// Stop before the first message with a higher SeqNo, in replay order.
+    ///
+    /// Replay order is Timestamp order, so a message with a lower SeqNo but a later
+    /// Timestamp than the first higher one is not applied either.
+    SeqNo(SeqNo),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MessageRef {
+    offset: usize,
+    timestamp: u64,
+    seq_no: SeqNo,
+}
+
+pub struct Replay<B: BookSide> {
+    snapshot_mmap: Mmap,
+    incremental_mmap: Mmap,
+    // incrementals sorted by (Timestamp, SeqNo)
+    order: Vec<MessageRef>,
+    cursor: usize,
+    books: FnvHashMap<SecurityId, Lob<B>>,
+    max_snapshot_seq: SeqNo,
+    skipped_updates: u64,
+    new_side: fn(bool) -> B,
+    pacing: Pacing,
+    // wall clock and Timestamp of the first paced message
+    anchor: Option<(Instant, u64)>,
+}
+
+impl<B: BookSide> Replay<B> {
+    /// Opens both files and applies the snapshots, `new_side` builds an empty side
+    /// (`ImprovedSide::new`, `Basic::new`).
+    pub fn open(
+        snapshot_path: &str,
+        incremental_path: &str,
+        new_side: fn(bool) -> B,
+    ) -> Result<Self> {
+        let snapshot_file = File::open(snapshot_path)
+            .with_context(|| format!("Failed to open snapshot file: {}", snapshot_path))?;
+        let incremental_file = File::open(incremental_path)
+            .with_context(|| format!("Failed to open incremental file: {}", incremental_path))?;
+
+        let snapshot_mmap = unsafe { Mmap::map(&snapshot_file)? };
+        let incremental_mmap = unsafe { Mmap::map(&incremental_file)? };
+
+        let mut order = Vec::new();
+        for record in Incrementals::new(&incremental_mmap) {
+            let record = record?;
+            order.push(MessageRef {
+                offset: record.offset,
+                timestamp: record.header.timestamp,
+                seq_no: record.header.seq_no,
+            });
+        }
+        // equal timestamps go in SeqNo order, not file order
+        order.sort_by_key(|m| (m.timestamp, m.seq_no));
+
+        let mut replay = Self {
+            snapshot_mmap,
+            incremental_mmap,
+            order,
+            cursor: 0,
+            books: FnvHashMap::default(),
+            max_snapshot_seq: 0,
+            skipped_updates: 0,
+            new_side,
+            pacing: Pacing::AsFastAsPossible,
+            anchor: None,
+        };
+        replay.reset()?;
+
+        Ok(replay)
+    }
+
+    /// Sets the pacing, a realtime `speed` must be finite and positive.
+    pub fn with_pacing(mut self, pacing: Pacing) -> Result<Self> {
+        if let Pacing::Realtime { speed } = pacing {
+            if !speed.is_finite() || speed <= 0.0 {
+                bail!("Invalid replay speed: {}", speed);
+            }
+        }
+        self.pacing = pacing;
+        Ok(self)
+    }
+
+    /// Back to the state right after the snapshots.
+    pub fn reset(&mut self) -> Result<()> {
+        self.books.clear();
+        self.max_snapshot_seq = 0;
+        self.skipped_updates = 0;
+        self.cursor = 0;
+        self.anchor = None;
+
+        let mut offset = 0;
+        while offset + SNAPSHOT_SIZE <= self.snapshot_mmap.len() {
+            let record = codec::decode_snapshot(&self.snapshot_mmap[offset..])?;
+            self.max_snapshot_seq = self.max_snapshot_seq.max(record.seq_no);
+
+            let book = record.to_lob((self.new_side)(true), (self.new_side)(false));
+            self.books.insert(record.security_id, book);
+
+            offset += SNAPSHOT_SIZE;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the next message, waiting first if paced. `None` at the end of the feed.
+    pub fn step(&mut self) -> Result<Option<IncrementalHeader>> {
+        let Some(next) = self.order.get(self.cursor).copied() else {
+            return Ok(None);
+        };
+
+        if let Pacing::Realtime { speed } = self.pacing {
+            let (start, first_ts) = *self.anchor.get_or_insert((Instant::now(), next.timestamp));
+            let due = Duration::from_secs_f64(
+                next.timestamp.saturating_sub(first_ts) as f64 / 1000.0 / speed,
+            );
+            let elapsed = start.elapsed();
+            if due > elapsed {
+                std::thread::sleep(due - elapsed);
+            }
+        }
+
+        self.apply_next().map(Some)
+    }
+
+    /// Steps until the next message is past `until`, returns how many were applied.
+    pub fn run_until(&mut self, until: Until) -> Result<usize> {
+        let mut applied = 0;
+        while self.next_within(until) {
+            self.step()?;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    /// Book of `security_id` as of `timestamp`, rewinding if the replay is already past it.
+    ///
+    /// The snapshots are the starting point, for a `timestamp` before them the snapshot
+    /// state is returned. Pacing is ignored.
+    pub fn book_at(&mut self, security_id: SecurityId, timestamp: u64) -> Result<Option<&Lob<B>>> {
+        let past = self.cursor > 0 && self.order[self.cursor - 1].timestamp > timestamp;
+        if past {
+            self.reset()?;
+        }
+
+        while self.next_within(Until::Timestamp(timestamp)) {
+            self.apply_next()?;
+        }
+
+        Ok(self.books.get(&security_id))
+    }
+
+    /// Timestamp and SeqNo of the message `step` would apply next.
+    pub fn peek(&self) -> Option<(u64, SeqNo)> {
+        self.order.get(self.cursor).map(|m| (m.timestamp, m.seq_no))
+    }
+
+    /// Number of incrementals already replayed.
+    pub fn position(&self) -> usize {
+        self.cursor
+    }
+
+    /// Updates skipped so far because `Side::of_update` refused them.
+    pub fn skipped_updates(&self) -> u64 {
+        self.skipped_updates
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn book(&self, security_id: SecurityId) -> Option<&Lob<B>> {
+        self.books.get(&security_id)
+    }
+
+    pub fn books(&self) -> &FnvHashMap<SecurityId, Lob<B>> {
+        &self.books
+    }
+
+    fn next_within(&self, until: Until) -> bool {
+        match (self.order.get(self.cursor), until) {
+            (Some(next), Until::Timestamp(ts)) => next.timestamp <= ts,
+            (Some(next), Until::SeqNo(seq)) => next.seq_no <= seq,
+            (None, _) => false,
+        }
+    }
+
+    fn apply_next(&mut self) -> Result<IncrementalHeader> {
+        let next = self.order[self.cursor];
+
+        let record = Incrementals::from_offset(&self.incremental_mmap, next.offset)
+            .next()
+            .context("Indexed incremental disappeared")??;
+        let header = record.header;
+
+        // stale, already in the snapshot
+        if header.seq_no <= self.max_snapshot_seq {
+            self.cursor += 1;
+            return Ok(header);
+        }
+
+        self.cursor += 1;
+
+        let new_side = self.new_side;
+        let book = self
+            .books
+            .entry(header.security_id)
+            .or_insert_with(|| Lob::new(header.security_id, new_side(true), new_side(false)));
+
+        for (side, price, qty) in record.updates() {
+            match Side::of_update(side, price) {
+                Ok(side) => {
+                    book.update(side, price, qty);
+                }
+                Err(_) => self.skipped_updates += 1,
+            }
+        }
+        book.last_update_seq = Some(header.seq_no);
+        book.last_exchange_ts = Some(header.timestamp);
+
+        Ok(header)
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// which vector search the array sides use, picked at runtime so one binary runs on every
+// x86_64 box (sse2 is baseline there) and builds on anything else with the scalar loop.
+// the force-scalar, force-sse2 and force-avx2 features cap the path to test each one on
+// one machine
+
+/// Best instruction set the searches may use, ordered from least to most capable. A side
+/// without a variant for it falls back to the next one down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SearchPath {
+    Scalar,
+    Sse2,
+    Avx2,
+    /// AVX-512F, only the integer search of `TickSide` has an 8 wide variant.
+    Avx512,
+}
+
+impl fmt::Display for SearchPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SearchPath::Scalar => "scalar",
+            SearchPath::Sse2 => "sse2",
+            SearchPath::Avx2 => "avx2",
+            SearchPath::Avx512 => "avx512",
+        };
+        f.write_str(name)
+    }
+}
+
+// 0 until the first call
+static PATH: AtomicU8 = AtomicU8::new(0);
+
+/// Search used on this machine, detected on the first call and cached.
+#[inline(always)]
+pub fn search_path() -> SearchPath {
+    match PATH.load(Ordering::Relaxed) {
+        1 => SearchPath::Scalar,
+        2 => SearchPath::Sse2,
+        3 => SearchPath::Avx2,
+        4 => SearchPath::Avx512,
+        _ => {
+            let path = detect();
+            PATH.store(path as u8 + 1, Ordering::Relaxed);
+            path
+        }
+    }
+}
+
+#[cold]
+fn detect() -> SearchPath {
+    let cap = if cfg!(feature = "force-scalar") {
+        SearchPath::Scalar
+    } else if cfg!(feature = "force-sse2") {
+        SearchPath::Sse2
+    } else if cfg!(feature = "force-avx2") {
+        SearchPath::Avx2
+    } else {
+        SearchPath::Avx512
+    };
+
+    cpu_path().min(cap)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn cpu_path() -> SearchPath {
+    if is_x86_feature_detected!("avx512f") {
+        SearchPath::Avx512
+    } else if is_x86_feature_detected!("avx2") {
+        SearchPath::Avx2
+    } else {
+        SearchPath::Sse2
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn cpu_path() -> SearchPath {
+    SearchPath::Scalar
+}
@@ -0,0 +1,213 @@
+use crate::instruments::Instruments;
+use crate::*;
+use anyhow::{bail, Result};
+use fnv::FnvHashMap;
+use std::io::{self, Write};
+
+/// Counters of one `process_files` run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub snapshots: u64,
+    pub incrementals: u64,
+    /// Updates applied to a book, including the dropped and missing ones below.
+    pub updates_applied: u64,
+    /// Updates skipped because their SeqNo <= max snapshot SeqNo.
+    pub updates_stale: u64,
+    /// Books created by an incremental for a security not in the snapshot.
+    pub books_created: u64,
+    /// New levels lost because the side was at its level limit.
+    pub levels_dropped: u64,
+    /// Removals of prices that weren't in the book.
+    pub removes_missing: u64,
+    pub invalid_sides: u64,
+    /// Updates skipped because the price is NaN, which can't be ordered against a side.
+    pub invalid_prices: u64,
+    /// Messages of a security missing from the instrument file, rejected or quarantined.
+    pub unknown: u64,
+    /// Updates and snapshot levels skipped because the price isn't on the instrument's tick.
+    pub off_tick: u64,
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        self.snapshots += other.snapshots;
+        self.incrementals += other.incrementals;
+        self.updates_applied += other.updates_applied;
+        self.updates_stale += other.updates_stale;
+        self.books_created += other.books_created;
+        self.levels_dropped += other.levels_dropped;
+        self.removes_missing += other.removes_missing;
+        self.invalid_sides += other.invalid_sides;
+        self.invalid_prices += other.invalid_prices;
+        self.unknown += other.unknown;
+        self.off_tick += other.off_tick;
+    }
+}
+
+/// Hooks the processors call while reading files, `()` records nothing and compiles away.
+pub trait StatsRecorder {
+    /// Count and skip updates with an invalid side instead of failing the run.
+    const SKIP_INVALID_SIDES: bool = false;
+
+    #[inline(always)]
+    fn snapshot(&mut self, _security_id: SecurityId) {}
+
+    #[inline(always)]
+    fn incremental(&mut self, _security_id: SecurityId) {}
+
+    #[inline(always)]
+    fn stale(&mut self, _security_id: SecurityId, _updates: u64) {}
+
+    #[inline(always)]
+    fn book_created(&mut self, _security_id: SecurityId) {}
+
+    #[inline(always)]
+    fn change(&mut self, _security_id: SecurityId, _change: LevelChange) {}
+
+    #[inline(always)]
+    fn invalid_side(&mut self, _security_id: SecurityId) {}
+
+    #[inline(always)]
+    fn invalid_price(&mut self, _security_id: SecurityId) {}
+
+    #[inline(always)]
+    fn unknown(&mut self, _security_id: SecurityId) {}
+
+    #[inline(always)]
+    fn off_tick(&mut self, _security_id: SecurityId) {}
+
+    /// Counts an update `Side::of_update` refused, fails on an invalid side unless
+    /// `SKIP_INVALID_SIDES`.
+    #[inline(always)]
+    fn skip_invalid(&mut self, security_id: SecurityId, invalid: InvalidUpdate) -> Result<()> {
+        match invalid {
+            InvalidUpdate::Side(side) => {
+                if !Self::SKIP_INVALID_SIDES {
+                    bail!("Invalid side: {}", side);
+                }
+                self.invalid_side(security_id);
+            }
+            InvalidUpdate::NanPrice => self.invalid_price(security_id),
+        }
+        Ok(())
+    }
+}
+
+impl StatsRecorder for () {}
+
+/// Per security and total counters, returned by `process_files_with_stats`.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessingStats {
+    pub per_security: FnvHashMap<SecurityId, Counters>,
+}
+
+impl ProcessingStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn security(&self, security_id: SecurityId) -> Option<&Counters> {
+        self.per_security.get(&security_id)
+    }
+
+    pub fn total(&self) -> Counters {
+        let mut total = Counters::default();
+        for counters in self.per_security.values() {
+            total.add(counters);
+        }
+        total
+    }
+
+    /// One line per security ordered by id, then the total. Securities in `instruments`
+    /// are followed by their symbol.
+    pub fn report<W: Write>(
+        &self,
+        out: &mut W,
+        instruments: Option<&Instruments>,
+    ) -> io::Result<()> {
+        let mut ids: Vec<_> = self.per_security.keys().copied().collect();
+        ids.sort();
+
+        for id in ids {
+            let name = match instruments.and_then(|instruments| instruments.symbol(id)) {
+                Some(symbol) => format!("sec id {} {}", id, symbol),
+                None => format!("sec id {}", id),
+            };
+            write_counters(out, &name, &self.per_security[&id])?;
+        }
+        write_counters(out, "total", &self.total())
+    }
+
+    #[inline(always)]
+    fn counters(&mut self, security_id: SecurityId) -> &mut Counters {
+        self.per_security.entry(security_id).or_default()
+    }
+}
+
+// counting is the point, a bad side shouldn't end the report
+impl StatsRecorder for ProcessingStats {
+    const SKIP_INVALID_SIDES: bool = true;
+
+    fn snapshot(&mut self, security_id: SecurityId) {
+        self.counters(security_id).snapshots += 1;
+    }
+
+    fn incremental(&mut self, security_id: SecurityId) {
+        self.counters(security_id).incrementals += 1;
+    }
+
+    fn stale(&mut self, security_id: SecurityId, updates: u64) {
+        self.counters(security_id).updates_stale += updates;
+    }
+
+    fn book_created(&mut self, security_id: SecurityId) {
+        self.counters(security_id).books_created += 1;
+    }
+
+    fn change(&mut self, security_id: SecurityId, change: LevelChange) {
+        let counters = self.counters(security_id);
+        counters.updates_applied += 1;
+        match change {
+            LevelChange::Dropped => counters.levels_dropped += 1,
+            LevelChange::NotFound => counters.removes_missing += 1,
+            _ => {}
+        }
+    }
+
+    fn invalid_side(&mut self, security_id: SecurityId) {
+        self.counters(security_id).invalid_sides += 1;
+    }
+
+    fn invalid_price(&mut self, security_id: SecurityId) {
+        self.counters(security_id).invalid_prices += 1;
+    }
+
+    fn unknown(&mut self, security_id: SecurityId) {
+        self.counters(security_id).unknown += 1;
+    }
+
+    fn off_tick(&mut self, security_id: SecurityId) {
+        self.counters(security_id).off_tick += 1;
+    }
+}
+
+fn write_counters<W: Write>(out: &mut W, name: &str, c: &Counters) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}: snapshots {} incrementals {} applied {} stale {} books created {} \
+         levels dropped {} missing removes {} invalid sides {} invalid prices {} unknown {} \
+         off tick {}",
+        name,
+        c.snapshots,
+        c.incrementals,
+        c.updates_applied,
+        c.updates_stale,
+        c.books_created,
+        c.levels_dropped,
+        c.removes_missing,
+        c.invalid_sides,
+        c.invalid_prices,
+        c.unknown,
+        c.off_tick
+    )
+}
@@ -0,0 +1,227 @@
+use crate::improved::MAX_LEVELS;
+use crate::simd::{self, SearchPath};
+use crate::*;
+
+// ImprovedSide with the search done on integer ticks instead of f64 prices. 64-bit
+// integer compares have no NaN or signed zero cases and AVX-512 compares 8 lanes into a
+// mask directly. prices are kept next to the ticks so they come back bit for bit
+//
+// no processor or CLI backend picks it: ticks need each book's tick size, which the
+// `fn(bool) -> B` constructors don't have, and the f64 -> tick division on every update
+// makes it slower than ImprovedSide's f64 search (see bench_search). it stays a library
+// side for feeds known to be on one tick
+
+/// Tick of [`TickSide::new`], the generator's default.
+pub const DEFAULT_TICK_SIZE: f64 = 0.01;
+
+#[repr(C, align(64))]
+#[derive(Clone)]
+pub struct TickSide {
+    ticks: [i64; MAX_LEVELS],
+    prices: [f64; MAX_LEVELS],
+    qtys: [Qty; MAX_LEVELS],
+    count: usize,
+    is_b: bool,
+    tick_size: f64,
+}
+
+impl TickSide {
+    /// Side with a [`DEFAULT_TICK_SIZE`] tick, for the `fn(bool) -> B` constructors.
+    pub fn new(is_bid: bool) -> Self {
+        Self::with_tick_size(is_bid, DEFAULT_TICK_SIZE)
+    }
+
+    /// Prices are rounded to the nearest multiple of `tick_size`, two prices within half a
+    /// tick of each other are the same level.
+    pub fn with_tick_size(is_bid: bool, tick_size: f64) -> Self {
+        Self {
+            ticks: [0; MAX_LEVELS],
+            prices: [0.0; MAX_LEVELS],
+            qtys: [0; MAX_LEVELS],
+            count: 0,
+            is_b: is_bid,
+            tick_size,
+        }
+    }
+
+    pub fn tick_size(&self) -> f64 {
+        self.tick_size
+    }
+
+    /// Tick of `price`, `None` if it isn't finite or is out of i64 range.
+    #[inline(always)]
+    pub fn to_tick(&self, price: f64) -> Option<i64> {
+        let tick = (price / self.tick_size).round();
+        // i64::MAX as f64 rounds up to 2^63, which is already out of range
+        (tick.is_finite() && tick >= i64::MIN as f64 && tick < i64::MAX as f64)
+            .then_some(tick as i64)
+    }
+
+    #[inline(always)]
+    fn find_position(&self, tick: i64) -> Result<usize, usize> {
+        if self.count == 0 {
+            return Err(0);
+        }
+
+        match simd::search_path() {
+            #[cfg(target_arch = "x86_64")]
+            SearchPath::Avx512 => unsafe { self.find_position_avx512(tick) },
+            #[cfg(target_arch = "x86_64")]
+            SearchPath::Avx2 => unsafe { self.find_position_avx2(tick) },
+            // pcmpgtq needs sse4.2, sse2 boxes get the scalar loop
+            _ => self.find_position_from(tick, 0),
+        }
+    }
+
+    #[inline(always)]
+    fn find_position_from(&self, tick: i64, start: usize) -> Result<usize, usize> {
+        for i in start..self.count {
+            if tick == self.ticks[i] {
+                return Ok(i);
+            }
+            if self.is_b {
+                if tick > self.ticks[i] {
+                    return Err(i);
+                }
+            } else if tick < self.ticks[i] {
+                return Err(i);
+            }
+        }
+
+        Err(self.count)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2")]
+    unsafe fn find_position_avx2(&self, tick: i64) -> Result<usize, usize> {
+        use std::arch::x86_64::*;
+
+        let tick_vec = _mm256_set1_epi64x(tick);
+        let mut i = 0;
+
+        while i + 4 <= self.count {
+            let ticks = _mm256_loadu_si256(self.ticks.as_ptr().add(i) as *const __m256i);
+
+            let eq_bits =
+                _mm256_movemask_pd(_mm256_castsi256_pd(_mm256_cmpeq_epi64(ticks, tick_vec)));
+            if eq_bits != 0 {
+                return Ok(i + eq_bits.trailing_zeros() as usize);
+            }
+
+            // only greater than exists, asks swap the operands
+            let cmp_mask = if self.is_b {
+                _mm256_cmpgt_epi64(tick_vec, ticks)
+            } else {
+                _mm256_cmpgt_epi64(ticks, tick_vec)
+            };
+
+            let cmp_bits = _mm256_movemask_pd(_mm256_castsi256_pd(cmp_mask));
+            if cmp_bits != 0 {
+                return Err(i + cmp_bits.trailing_zeros() as usize);
+            }
+
+            i += 4;
+        }
+
+        self.find_position_from(tick, i)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx512f")]
+    unsafe fn find_position_avx512(&self, tick: i64) -> Result<usize, usize> {
+        use std::arch::x86_64::*;
+
+        let tick_vec = _mm512_set1_epi64(tick);
+        let mut i = 0;
+
+        while i + 8 <= self.count {
+            let ticks = _mm512_loadu_epi64(self.ticks.as_ptr().add(i));
+
+            // compares go straight to an 8 bit mask
+            let eq_bits = _mm512_cmpeq_epi64_mask(ticks, tick_vec);
+            if eq_bits != 0 {
+                return Ok(i + eq_bits.trailing_zeros() as usize);
+            }
+
+            let cmp_bits = if self.is_b {
+                _mm512_cmpgt_epi64_mask(tick_vec, ticks)
+            } else {
+                _mm512_cmplt_epi64_mask(tick_vec, ticks)
+            };
+            if cmp_bits != 0 {
+                return Err(i + cmp_bits.trailing_zeros() as usize);
+            }
+
+            i += 8;
+        }
+
+        self.find_position_from(tick, i)
+    }
+}
+
+impl BookSide for TickSide {
+    #[inline(always)]
+    fn update_l(&mut self, price: f64, qty: Qty) -> LevelChange {
+        // off the tick grid entirely, nowhere to put it
+        let Some(tick) = self.to_tick(price) else {
+            return LevelChange::Dropped;
+        };
+
+        match self.find_position(tick) {
+            Ok(pos) => {
+                self.qtys[pos] = qty;
+                LevelChange::Updated
+            }
+            Err(pos) => {
+                if self.count == MAX_LEVELS {
+                    // full, level is lost, same as ImprovedSide
+                    return LevelChange::Dropped;
+                }
+
+                let count = self.count;
+                self.ticks.copy_within(pos..count, pos + 1);
+                self.prices.copy_within(pos..count, pos + 1);
+                self.qtys.copy_within(pos..count, pos + 1);
+
+                self.ticks[pos] = tick;
+                self.prices[pos] = price;
+                self.qtys[pos] = qty;
+                self.count += 1;
+                LevelChange::Inserted
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn remove_l(&mut self, price: f64) -> LevelChange {
+        let Some(Ok(pos)) = self.to_tick(price).map(|tick| self.find_position(tick)) else {
+            return LevelChange::NotFound;
+        };
+
+        let count = self.count;
+        self.ticks.copy_within(pos + 1..count, pos);
+        self.prices.copy_within(pos + 1..count, pos);
+        self.qtys.copy_within(pos + 1..count, pos);
+        self.count -= 1;
+        LevelChange::Removed
+    }
+
+    fn get_l(&self) -> Vec<Level> {
+        (0..self.count)
+            .map(|i| Level {
+                price: self.prices[i],
+                quantity: self.qtys[i],
+            })
+            .collect()
+    }
+
+    #[inline(always)]
+    fn depth(&self) -> usize {
+        self.count
+    }
+
+    #[inline(always)]
+    fn level(&self, i: usize) -> Option<(f64, Qty)> {
+        (i < self.count).then(|| (self.prices[i], self.qtys[i]))
+    }
+}
@@ -0,0 +1,426 @@
+use crate::codec;
+use crate::*;
+use fnv::FnvHashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+// integrity checks on the raw files, nothing is applied so a bad file can't hide
+// behind the processors' skip rules
+
+/// Which input an issue was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Snapshot,
+    Incremental,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IssueKind {
+    /// Bytes at the end that don't make up a whole record.
+    TruncatedTail {
+        bytes: usize,
+    },
+    /// NumUpdates that can't fit even in the whole file, nothing after it can be read.
+    ImpossibleNumUpdates {
+        num_updates: u64,
+    },
+    InvalidSide {
+        side: u8,
+    },
+    /// NaN, infinite or negative price.
+    BadPrice {
+        price: f64,
+    },
+    /// SeqNo not above the previous incremental's.
+    SeqNotIncreasing {
+        seq_no: SeqNo,
+        previous: SeqNo,
+    },
+    TimestampDecreasing {
+        timestamp: u64,
+        previous: u64,
+    },
+    /// Second snapshot of a security, `first_offset` is where the first one is.
+    DuplicateSecurity {
+        first_offset: usize,
+    },
+    /// Bids not strictly descending or asks not strictly ascending.
+    UnsortedLevels {
+        side: Side,
+    },
+    /// Best bid at or above best ask.
+    CrossedBook {
+        bid: f64,
+        ask: f64,
+    },
+}
+
+const ISSUE_KINDS: usize = 9;
+
+const ISSUE_NAMES: [&str; ISSUE_KINDS] = [
+    "truncated tail",
+    "impossible NumUpdates",
+    "invalid side",
+    "bad price",
+    "SeqNo not increasing",
+    "timestamp decreasing",
+    "duplicate security",
+    "unsorted levels",
+    "crossed book",
+];
+
+impl IssueKind {
+    fn index(&self) -> usize {
+        match self {
+            IssueKind::TruncatedTail { .. } => 0,
+            IssueKind::ImpossibleNumUpdates { .. } => 1,
+            IssueKind::InvalidSide { .. } => 2,
+            IssueKind::BadPrice { .. } => 3,
+            IssueKind::SeqNotIncreasing { .. } => 4,
+            IssueKind::TimestampDecreasing { .. } => 5,
+            IssueKind::DuplicateSecurity { .. } => 6,
+            IssueKind::UnsortedLevels { .. } => 7,
+            IssueKind::CrossedBook { .. } => 8,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        ISSUE_NAMES[self.index()]
+    }
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            IssueKind::TruncatedTail { bytes } => write!(f, "truncated tail of {} bytes", bytes),
+            IssueKind::ImpossibleNumUpdates { num_updates } => {
+                write!(f, "impossible NumUpdates {}", num_updates)
+            }
+            IssueKind::InvalidSide { side } => write!(f, "invalid side {}", side),
+            IssueKind::BadPrice { price } => write!(f, "bad price {}", price),
+            IssueKind::SeqNotIncreasing { seq_no, previous } => {
+                write!(f, "SeqNo {} after {}", seq_no, previous)
+            }
+            IssueKind::TimestampDecreasing {
+                timestamp,
+                previous,
+            } => write!(f, "timestamp {} after {}", timestamp, previous),
+            IssueKind::DuplicateSecurity { first_offset } => {
+                write!(f, "duplicate security, first at offset {}", first_offset)
+            }
+            IssueKind::UnsortedLevels { side } => {
+                let side = if side == Side::B { "bid" } else { "ask" };
+                write!(f, "unsorted {} levels", side)
+            }
+            IssueKind::CrossedBook { bid, ask } => {
+                write!(f, "crossed book, bid {} ask {}", bid, ask)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Issue {
+    pub file: FileKind,
+    /// Offset of the record the issue is in.
+    pub offset: usize,
+    /// `None` when the record header couldn't be read.
+    pub security_id: Option<SecurityId>,
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file = match self.file {
+            FileKind::Snapshot => "snapshot",
+            FileKind::Incremental => "incremental",
+        };
+        write!(f, "{} @{}", file, self.offset)?;
+        if let Some(security_id) = self.security_id {
+            write!(f, " sec {}", security_id)?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+/// Result of checking one or both files.
+///
+/// Every issue is counted, only the first `max_issues` are kept with their location.
+#[derive(Debug, Clone)]
+pub struct ValidationReport {
+    pub snapshots: u64,
+    pub incrementals: u64,
+    pub issues: Vec<Issue>,
+    counts: [u64; ISSUE_KINDS],
+    max_issues: usize,
+}
+
+impl ValidationReport {
+    pub fn new(max_issues: usize) -> Self {
+        Self {
+            snapshots: 0,
+            incrementals: 0,
+            issues: Vec::new(),
+            counts: [0; ISSUE_KINDS],
+            max_issues,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.total() == 0
+    }
+
+    /// Number of issues of every kind, kept or not.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Number of issues with the same kind as `kind`, its fields are ignored.
+    pub fn count(&self, kind: IssueKind) -> u64 {
+        self.counts[kind.index()]
+    }
+
+    /// Checks a `snapshot.bin` buffer: truncation, duplicate securities, prices, level
+    /// order and crossed books.
+    pub fn check_snapshots(&mut self, data: &[u8]) {
+        let mut seen: FnvHashMap<SecurityId, usize> = FnvHashMap::default();
+
+        for (i, chunk) in data.chunks(SNAPSHOT_SIZE).enumerate() {
+            let offset = i * SNAPSHOT_SIZE;
+            let Ok(record) = codec::decode_snapshot(chunk) else {
+                self.push(
+                    FileKind::Snapshot,
+                    offset,
+                    None,
+                    IssueKind::TruncatedTail { bytes: chunk.len() },
+                );
+                break;
+            };
+
+            self.snapshots += 1;
+            let sec = Some(record.security_id);
+
+            if let Some(&first_offset) = seen.get(&record.security_id) {
+                self.push(
+                    FileKind::Snapshot,
+                    offset,
+                    sec,
+                    IssueKind::DuplicateSecurity { first_offset },
+                );
+            } else {
+                seen.insert(record.security_id, offset);
+            }
+
+            // same empty level rule as SnapshotRecord::to_lob
+            let bids: Vec<f64> = record
+                .bids
+                .iter()
+                .filter(|&&(price, qty)| price.to_bits() != 0 && qty != 0)
+                .map(|&(price, _)| price)
+                .collect();
+            let asks: Vec<f64> = record
+                .asks
+                .iter()
+                .filter(|&&(price, qty)| price.to_bits() != 0 && qty != 0)
+                .map(|&(price, _)| price)
+                .collect();
+
+            for &price in bids.iter().chain(asks.iter()) {
+                if is_bad_price(price) {
+                    self.push(
+                        FileKind::Snapshot,
+                        offset,
+                        sec,
+                        IssueKind::BadPrice { price },
+                    );
+                }
+            }
+
+            // NaN compares false both ways, already reported above
+            if bids.windows(2).any(|w| w[0] <= w[1]) {
+                self.push(
+                    FileKind::Snapshot,
+                    offset,
+                    sec,
+                    IssueKind::UnsortedLevels { side: Side::B },
+                );
+            }
+            if asks.windows(2).any(|w| w[0] >= w[1]) {
+                self.push(
+                    FileKind::Snapshot,
+                    offset,
+                    sec,
+                    IssueKind::UnsortedLevels { side: Side::A },
+                );
+            }
+
+            if let (Some(&bid), Some(&ask)) = (bids.first(), asks.first()) {
+                if bid >= ask {
+                    self.push(
+                        FileKind::Snapshot,
+                        offset,
+                        sec,
+                        IssueKind::CrossedBook { bid, ask },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Checks an `incremental.bin` buffer: truncation, NumUpdates, sides, prices and
+    /// SeqNo/Timestamp order across the whole file.
+    pub fn check_incrementals(&mut self, data: &[u8]) {
+        let mut offset = 0;
+        let mut previous: Option<(u64, SeqNo)> = None;
+
+        while offset < data.len() {
+            let Ok(header) = codec::decode_incremental_header(&data[offset..]) else {
+                self.push(
+                    FileKind::Incremental,
+                    offset,
+                    None,
+                    IssueKind::TruncatedTail {
+                        bytes: data.len() - offset,
+                    },
+                );
+                break;
+            };
+
+            let sec = Some(header.security_id);
+
+            // a record that wouldn't fit in the whole file is garbage, one that only runs
+            // past the end is a cut off write
+            let end = match header.record_size() {
+                Some(size) if size <= data.len() - offset => offset + size,
+                Some(size) if size <= data.len() => {
+                    self.push(
+                        FileKind::Incremental,
+                        offset,
+                        sec,
+                        IssueKind::TruncatedTail {
+                            bytes: data.len() - offset,
+                        },
+                    );
+                    break;
+                }
+                _ => {
+                    self.push(
+                        FileKind::Incremental,
+                        offset,
+                        sec,
+                        IssueKind::ImpossibleNumUpdates {
+                            num_updates: header.num_updates,
+                        },
+                    );
+                    break;
+                }
+            };
+
+            self.incrementals += 1;
+
+            if let Some((previous_ts, previous_seq)) = previous {
+                if header.seq_no <= previous_seq {
+                    self.push(
+                        FileKind::Incremental,
+                        offset,
+                        sec,
+                        IssueKind::SeqNotIncreasing {
+                            seq_no: header.seq_no,
+                            previous: previous_seq,
+                        },
+                    );
+                }
+                if header.timestamp < previous_ts {
+                    self.push(
+                        FileKind::Incremental,
+                        offset,
+                        sec,
+                        IssueKind::TimestampDecreasing {
+                            timestamp: header.timestamp,
+                            previous: previous_ts,
+                        },
+                    );
+                }
+            }
+            previous = Some((header.timestamp, header.seq_no));
+
+            let record = codec::IncrementalRecord {
+                offset,
+                header,
+                body: &data[offset + INCREMENTAL_HEADER_SIZE..end],
+            };
+            for (side, price, _) in record.updates() {
+                if Side::from_u8(side).is_none() {
+                    self.push(
+                        FileKind::Incremental,
+                        offset,
+                        sec,
+                        IssueKind::InvalidSide { side },
+                    );
+                }
+                if is_bad_price(price) {
+                    self.push(
+                        FileKind::Incremental,
+                        offset,
+                        sec,
+                        IssueKind::BadPrice { price },
+                    );
+                }
+            }
+
+            offset = end;
+        }
+    }
+
+    /// Record counts, the kept issues, then a count per issue kind.
+    pub fn report<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "snapshots {} incrementals {}",
+            self.snapshots, self.incrementals
+        )?;
+
+        for issue in &self.issues {
+            writeln!(out, "{}", issue)?;
+        }
+        let total = self.total();
+        if total > self.issues.len() as u64 {
+            writeln!(out, "... {} more", total - self.issues.len() as u64)?;
+        }
+
+        for (name, &count) in ISSUE_NAMES.iter().zip(self.counts.iter()) {
+            if count > 0 {
+                writeln!(out, "{}: {}", name, count)?;
+            }
+        }
+
+        if total == 0 {
+            writeln!(out, "ok")
+        } else {
+            writeln!(out, "{} issues", total)
+        }
+    }
+
+    fn push(
+        &mut self,
+        file: FileKind,
+        offset: usize,
+        security_id: Option<SecurityId>,
+        kind: IssueKind,
+    ) {
+        self.counts[kind.index()] += 1;
+        if self.issues.len() < self.max_issues {
+            self.issues.push(Issue {
+                file,
+                offset,
+                security_id,
+                kind,
+            });
+        }
+    }
+}
+
+#[inline(always)]
+fn is_bad_price(price: f64) -> bool {
+    !price.is_finite() || price < 0.0
+}
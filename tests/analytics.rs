@@ -0,0 +1,181 @@
+use crossbeam::channel;
+use lob_processor::analytics::{self, Analytics, AnalyticsConfig, AnalyticsObserver, Depth};
+use lob_processor::basic::{Basic, BasicProcessor};
+use lob_processor::codec;
+use lob_processor::generator::{FeedGenerator, GeneratorConfig};
+use lob_processor::improved::{ImprovedProcessor, ImprovedSide};
+use lob_processor::instruments::{Instruments, UnknownPolicy};
+use lob_processor::tick::TickSide;
+use lob_processor::*;
+
+fn close(a: Option<f64>, b: f64) -> bool {
+    a.is_some_and(|a| (a - b).abs() < 1e-9)
+}
+
+// bids 99.99 x 100, 99.98 x 300, 99.90 x 1000, asks 100.01 x 300, 100.02 x 100
+fn book<B: BookSide>(new_side: fn(bool) -> B) -> Lob<B> {
+    let mut book = Lob::new(1, new_side(true), new_side(false));
+    for (side, price, qty) in [
+        (Side::B, 99.99, 100),
+        (Side::B, 99.98, 300),
+        (Side::B, 99.90, 1000),
+        (Side::A, 100.01, 300),
+        (Side::A, 100.02, 100),
+    ] {
+        book.update(side, price, qty);
+    }
+    book
+}
+
+#[test]
+fn features_of_a_known_book() {
+    let book = book(Basic::new);
+
+    assert!(close(analytics::mid(&book), 100.0));
+    assert!(close(analytics::spread(&book), 0.02));
+    assert!(close(analytics::spread_ticks(&book, 0.01), 2.0));
+    assert!(close(analytics::spread_bps(&book), 2.0));
+    // more on the ask, the price leans to the bid
+    assert!(close(
+        analytics::microprice(&book),
+        (99.99 * 300.0 + 100.01 * 100.0) / 400.0
+    ));
+    assert!(close(analytics::imbalance(&book, 1), -0.5));
+    assert!(close(analytics::imbalance(&book, 2), 0.0));
+    assert!(close(analytics::imbalance(&book, 3), 1000.0 / 1800.0));
+    assert!(close(
+        analytics::weighted_mid(&book, 2),
+        ((99.99 * 100.0 + 99.98 * 300.0) / 400.0 + (100.01 * 300.0 + 100.02 * 100.0) / 400.0) / 2.0
+    ));
+    // 2.5 bps of 100 is 0.025: 99.98 is in, 99.90 is not
+    assert_eq!(
+        analytics::depth_within_bps(&book, 2.5),
+        Some(Depth {
+            bids: 400,
+            asks: 400
+        })
+    );
+    assert_eq!(
+        analytics::depth_within_bps(&book, 0.0),
+        Some(Depth { bids: 0, asks: 0 })
+    );
+}
+
+#[test]
+fn every_side_gives_the_same_features() {
+    let config = AnalyticsConfig {
+        levels: 3,
+        depth_bps: 15.0,
+        ..AnalyticsConfig::default()
+    };
+    let basic = Analytics::compute(&book(Basic::new), &config);
+    let improved = Analytics::compute(&book(ImprovedSide::new), &config);
+    let tick = Analytics::compute(&book(TickSide::new), &config);
+    assert!(basic.mid.is_some() && basic.depth.is_some());
+    assert_eq!(basic, improved);
+    assert_eq!(basic, tick);
+
+    // level reads the same levels get_l copies
+    let book = book(TickSide::new);
+    for side in [&book.bids, &book.asks] {
+        let levels = side.get_l();
+        assert_eq!(side.depth(), levels.len());
+        for (i, level) in levels.iter().enumerate() {
+            assert_eq!(side.level(i), Some((level.price, level.quantity)));
+        }
+        assert_eq!(side.level(levels.len()), None);
+    }
+}
+
+#[test]
+fn one_sided_books_have_no_features() {
+    let mut book = Lob::new(1, ImprovedSide::new(true), ImprovedSide::new(false));
+    assert_eq!(
+        Analytics::compute(&book, &AnalyticsConfig::default()),
+        Analytics::default()
+    );
+
+    book.update(Side::B, 99.99, 100);
+    let features = Analytics::compute(&book, &AnalyticsConfig::default());
+    assert_eq!(features.mid, None);
+    assert_eq!(features.microprice, None);
+    assert_eq!(features.weighted_mid, None);
+    assert_eq!(features.depth, None);
+    // the imbalance only needs quantity
+    assert_eq!(features.imbalance, Some(1.0));
+}
+
+#[test]
+fn observer_keeps_up_with_the_stream() {
+    let mut generator = FeedGenerator::new(GeneratorConfig {
+        seed: 5,
+        messages: 2000,
+        ..GeneratorConfig::default()
+    })
+    .unwrap();
+    let mut snapshot = codec::FeedWriter::new(Vec::new());
+    generator.write_snapshot(&mut snapshot).unwrap();
+    let mut incremental = codec::FeedWriter::new(Vec::new());
+    generator.write_incrementals(&mut incremental).unwrap();
+    let (snapshot, incremental) = (
+        snapshot.into_inner().unwrap(),
+        incremental.into_inner().unwrap(),
+    );
+    let send = || {
+        let (sender, receiver) = channel::unbounded();
+        for message in codec::stream_messages(&snapshot, &incremental).unwrap() {
+            sender.send(message).unwrap();
+        }
+        receiver
+    };
+
+    let config = AnalyticsConfig::default();
+    let mut basic = AnalyticsObserver::new(config.clone());
+    let basic_books = BasicProcessor::new()
+        .process_stream_with(send(), None, &mut basic)
+        .unwrap();
+    let mut improved = AnalyticsObserver::new(config.clone());
+    let improved_books = ImprovedProcessor::new()
+        .process_stream_with(send(), None, &mut improved)
+        .unwrap();
+
+    assert!(basic.updates() > 0);
+    assert_eq!(basic.updates(), improved.updates());
+    assert_eq!(basic.all().len(), basic_books.len());
+    for (id, book) in &improved_books {
+        // the last update left what a full recompute of the final book gives
+        let last = improved.get(*id).unwrap();
+        assert_eq!(*last, Analytics::compute(book, &config));
+        assert_eq!(basic.get(*id), Some(last));
+        assert_eq!(
+            *last,
+            Analytics::compute(&generator.book(*id, Basic::new).unwrap(), &config)
+        );
+    }
+}
+
+#[test]
+fn spread_ticks_use_the_instrument_tick() {
+    let book = book(Basic::new);
+    let config = AnalyticsConfig::default();
+    let instruments = Instruments::parse("1,WIDE,0.005,1,3,10\n", UnknownPolicy::Create).unwrap();
+
+    let plain = Analytics::compute(&book, &config);
+    let with = Analytics::compute_with(&book, &config, &instruments);
+    assert!(close(plain.spread_ticks, 2.0));
+    assert!(close(with.spread_ticks, 4.0));
+    assert_eq!(
+        Analytics {
+            spread_ticks: plain.spread_ticks,
+            ..with
+        },
+        plain
+    );
+
+    // books without an instrument fall back to the configured tick
+    let mut other = book.clone();
+    other.security_id = 2;
+    let mut observer = AnalyticsObserver::new(config).with_instruments(instruments);
+    assert!(close(observer.update(&book).spread_ticks, 4.0));
+    assert!(close(observer.update(&other).spread_ticks, 2.0));
+}
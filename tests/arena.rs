@@ -0,0 +1,77 @@
+use lob_processor::arena::{BookArena, DIRECT_IDS};
+use lob_processor::differential;
+use lob_processor::generator::{self, GeneratorConfig};
+use lob_processor::improved::{ImprovedProcessor, ImprovedSide};
+use lob_processor::*;
+use tempfile::TempDir;
+
+fn book(security_id: SecurityId, seq: SeqNo, bid: f64) -> Lob<ImprovedSide> {
+    let mut book = Lob::new(
+        security_id,
+        ImprovedSide::new(true),
+        ImprovedSide::new(false),
+    );
+    book.update(Side::B, bid, 10);
+    book.last_update_seq = Some(seq);
+    book
+}
+
+#[test]
+fn dense_and_sparse_ids_get_stable_slots() {
+    let ids = [1, 0, DIRECT_IDS - 1, DIRECT_IDS, u64::MAX, 7];
+    let mut arena = BookArena::new();
+    let slots: Vec<_> = ids
+        .iter()
+        .map(|&id| arena.insert(book(id, 1, 100.0)))
+        .collect();
+
+    assert_eq!(slots, (0..ids.len()).collect::<Vec<_>>());
+    assert_eq!(arena.len(), ids.len());
+    for (&id, &slot) in ids.iter().zip(slots.iter()) {
+        assert_eq!(arena.slot(id), Some(slot));
+        assert_eq!(arena.meta(slot).security_id, id);
+    }
+    assert_eq!(arena.slot(2), None);
+    assert_eq!(arena.slot(DIRECT_IDS + 1), None);
+
+    // a second book for a security takes over its slot
+    assert_eq!(arena.insert(book(DIRECT_IDS, 2, 101.0)), 3);
+    assert_eq!(arena.len(), ids.len());
+    let replaced = arena.book(DIRECT_IDS).unwrap();
+    assert_eq!(replaced.last_update_seq, Some(2));
+    assert_eq!(replaced.bids.get_l()[0].price, 101.0);
+
+    let books = arena.into_books();
+    assert_eq!(books.len(), ids.len());
+    assert!(ids.iter().all(|id| books.contains_key(id)));
+}
+
+#[test]
+fn arena_holds_the_processed_books() {
+    let dir = TempDir::new().unwrap();
+    let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+    let (snapshot, incremental) = (path("snapshot.bin"), path("incremental.bin"));
+    generator::generate_files(
+        GeneratorConfig {
+            seed: 9,
+            securities: 30,
+            messages: 3000,
+            ..GeneratorConfig::default()
+        },
+        &snapshot,
+        &incremental,
+    )
+    .unwrap();
+
+    let processor = ImprovedProcessor::new();
+    let books = processor.process_files(&snapshot, &incremental).unwrap();
+    let arena = processor
+        .process_files_arena(&snapshot, &incremental)
+        .unwrap();
+    assert_eq!(arena.len(), books.len());
+
+    for (&id, expected) in &books {
+        let book = arena.book(id).unwrap();
+        assert!(differential::compare_book(expected, &book).is_none());
+    }
+}
@@ -0,0 +1,186 @@
+use lob_processor::basic::{Basic, BasicProcessor};
+use lob_processor::codec;
+use lob_processor::improved::{ImprovedProcessor, ImprovedSide};
+use lob_processor::*;
+use std::fs;
+
+// bids 100.00 down and asks 100.05 up in 0.05 steps, `depth` levels each
+fn book<B: BookSide>(new_side: fn(bool) -> B, depth: usize) -> Lob<B> {
+    let mut book = Lob::new(42, new_side(true), new_side(false));
+    for i in 0..depth {
+        book.update(Side::B, 100.0 - i as f64 * 0.05, 100 + i as u64);
+        book.update(Side::A, 100.05 + i as f64 * 0.05, 200 + i as u64);
+    }
+    book.last_update_seq = Some(77);
+    book
+}
+
+fn levels<B: BookSide>(side: &B) -> Vec<(u64, Qty)> {
+    side.get_l()
+        .iter()
+        .map(|level| (level.price.to_bits(), level.quantity))
+        .collect()
+}
+
+// the feed written to a snapshot and an incremental file for `run`
+fn with_files<T>(snapshot: &[u8], incremental: &[u8], run: impl FnOnce(&str, &str) -> T) -> T {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+    let (snapshot_path, incremental_path) = (path("snapshot.bin"), path("incremental.bin"));
+    fs::write(&snapshot_path, snapshot).unwrap();
+    fs::write(&incremental_path, incremental).unwrap();
+    run(&snapshot_path, &incremental_path)
+}
+
+// what the snapshot should hold: the top SNAPSHOT_LEVELS of each side
+fn assert_top_levels<A: BookSide, B: BookSide>(written: &Lob<A>, read: &Lob<B>) {
+    assert_eq!(read.security_id, written.security_id);
+    assert_eq!(read.last_update_seq, written.last_update_seq);
+    for (written, read) in [(&written.bids, &read.bids), (&written.asks, &read.asks)] {
+        let mut expected = levels(written);
+        expected.truncate(SNAPSHOT_LEVELS);
+        assert_eq!(levels(read), expected);
+    }
+}
+
+#[test]
+fn snapshot_round_trips_through_both_parsers() {
+    for depth in [0, 1, SNAPSHOT_LEVELS, SNAPSHOT_LEVELS + 3] {
+        let written = book(Basic::new, depth);
+        let record = codec::encode_snapshot(&written, 1_000);
+        assert_eq!(record.len(), SNAPSHOT_SIZE);
+
+        let basic = with_files(&record, &[], |snapshot, incremental| {
+            BasicProcessor::new().process_files(snapshot, incremental)
+        })
+        .unwrap();
+        let improved = with_files(&record, &[], |snapshot, incremental| {
+            ImprovedProcessor::new().process_files(snapshot, incremental)
+        })
+        .unwrap();
+        assert_top_levels(&written, &basic[&42]);
+        assert_top_levels(&written, &improved[&42]);
+
+        let decoded = codec::decode_snapshot(&record).unwrap();
+        assert_eq!(decoded.timestamp, 1_000);
+        assert_top_levels(
+            &written,
+            &decoded.to_lob(ImprovedSide::new(true), ImprovedSide::new(false)),
+        );
+    }
+}
+
+#[test]
+fn written_snapshots_are_sorted_by_security() {
+    let books: Vec<_> = [3, 1, 2]
+        .into_iter()
+        .map(|id| Lob {
+            security_id: id,
+            ..book(ImprovedSide::new, 2)
+        })
+        .collect();
+    let mut out = Vec::new();
+    codec::write_snapshots(&mut out, &books, 5).unwrap();
+
+    assert_eq!(out.len(), 3 * SNAPSHOT_SIZE);
+    let ids: Vec<_> = out
+        .chunks(SNAPSHOT_SIZE)
+        .map(|record| codec::decode_snapshot(record).unwrap().security_id)
+        .collect();
+    assert_eq!(ids, vec![1, 2, 3]);
+
+    // a book that never saw a SeqNo is written as 0
+    let mut fresh = book(Basic::new, 1);
+    fresh.last_update_seq = None;
+    let record = codec::encode_snapshot(&fresh, 0);
+    assert_eq!(codec::decode_snapshot(&record).unwrap().seq_no, 0);
+}
+
+#[test]
+fn feed_writer_rejects_what_the_parsers_would_not_read() {
+    let mut writer = codec::FeedWriter::new(Vec::new());
+    let bids = [(100.0, 10), (99.95, 20)];
+    let asks = [(100.05, 30)];
+
+    writer.write_snapshot_levels(1, 1, 1, &bids, &asks).unwrap();
+    // unsorted, too deep, zero qty and bad prices
+    assert!(writer
+        .write_snapshot_levels(1, 1, 1, &[(99.95, 20), (100.0, 10)], &asks)
+        .is_err());
+    assert!(writer
+        .write_snapshot_levels(1, 1, 1, &bids, &[(100.1, 1), (100.05, 1)])
+        .is_err());
+    assert!(writer
+        .write_snapshot_levels(1, 1, 1, &[(100.0, 1); SNAPSHOT_LEVELS + 1], &asks)
+        .is_err());
+    assert!(writer
+        .write_snapshot_levels(1, 1, 1, &[(100.0, 0)], &asks)
+        .is_err());
+    for price in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        assert!(writer
+            .write_snapshot_levels(1, 1, 1, &[(price, 1)], &asks)
+            .is_err());
+        assert!(writer
+            .write_incremental(1, 10, 1, &[(Side::B, price, 1)])
+            .is_err());
+    }
+    // nothing was written by the failed calls
+    assert_eq!(writer.bytes_written(), SNAPSHOT_SIZE as u64);
+}
+
+#[test]
+fn feed_writer_needs_increasing_seq_no() {
+    let mut writer = codec::FeedWriter::new(Vec::new());
+    let updates = [(Side::B, 100.0, 5), (Side::A, 100.05, 0)];
+
+    writer.write_incremental(1, 5, 1, &updates).unwrap();
+    assert!(writer.write_incremental(2, 5, 2, &updates).is_err());
+    assert!(writer.write_incremental(2, 4, 1, &updates).is_err());
+    writer.write_incremental(2, 6, 2, &[]).unwrap();
+    // raw bytes skip every check
+    writer.write_raw(&[0xff; 3]).unwrap();
+
+    let record = INCREMENTAL_HEADER_SIZE + updates.len() * INCREMENTAL_SIZE;
+    assert_eq!(
+        writer.bytes_written(),
+        (record + INCREMENTAL_HEADER_SIZE + 3) as u64
+    );
+
+    let out = writer.into_inner().unwrap();
+    let mut expected = Vec::new();
+    codec::encode_incremental(&mut expected, 1, 5, 1, &updates);
+    codec::encode_incremental(&mut expected, 2, 6, 2, &[]);
+    assert_eq!(out[..out.len() - 3], expected[..]);
+
+    // NumUpdates of the first record
+    assert_eq!(out[24..32], 2u64.to_le_bytes());
+}
+
+#[test]
+fn feed_writer_output_is_processed() {
+    let mut writer = codec::FeedWriter::new(Vec::new());
+    writer
+        .write_snapshot(&book(ImprovedSide::new, SNAPSHOT_LEVELS), 1)
+        .unwrap();
+    let snapshot = writer.into_inner().unwrap();
+
+    let mut writer = codec::FeedWriter::new(Vec::new());
+    writer
+        .write_incremental(2, 78, 42, &[(Side::B, 100.0, 0), (Side::A, 100.0, 9)])
+        .unwrap();
+    let incremental = writer.into_inner().unwrap();
+
+    let basic = with_files(&snapshot, &incremental, |snapshot, incremental| {
+        BasicProcessor::new().process_files(snapshot, incremental)
+    })
+    .unwrap();
+    let improved = with_files(&snapshot, &incremental, |snapshot, incremental| {
+        ImprovedProcessor::new().process_files(snapshot, incremental)
+    })
+    .unwrap();
+    for book in [levels(&basic[&42].bids), levels(&improved[&42].bids)] {
+        assert_eq!(book[0], (99.95f64.to_bits(), 101));
+    }
+    assert_eq!(basic[&42].asks.get_l()[0].price, 100.0);
+    assert_eq!(improved[&42].last_update_seq, Some(78));
+}
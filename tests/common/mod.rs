@@ -0,0 +1,171 @@
+// fixtures shared by the integration tests, not every test file uses all of them
+#![allow(dead_code)]
+
+use crossbeam::channel::{self, Receiver};
+use lob_processor::codec::{self, FeedWriter};
+use lob_processor::faults::{self, FaultConfig};
+use lob_processor::generator::{self, FeedGenerator, GeneratorConfig};
+use lob_processor::*;
+use std::fs;
+use tempfile::TempDir;
+
+/// One incremental: timestamp, SeqNo, security id and its updates.
+pub type Message = (u64, SeqNo, SecurityId, Vec<(Side, f64, Qty)>);
+
+// xorshift, enough to vary the feed and the same for a seed everywhere
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+/// Price of the `tick`th level away from 100 on `side`, on a 0.01 tick.
+pub fn price(side: Side, tick: u64) -> f64 {
+    match side {
+        Side::B => (10_000 - tick) as f64 / 100.0,
+        Side::A => (10_000 + tick) as f64 / 100.0,
+    }
+}
+
+/// Seeded incrementals for securities 1..=`securities`, SeqNo and timestamps from 1 up.
+/// Prices are the `depth` ticks either side of 100, so a side never holds more than
+/// `depth` levels and books never cross. About a third of the updates are removes, some
+/// of levels that aren't there.
+pub fn random_messages(seed: u64, securities: u64, depth: u64, messages: u64) -> Vec<Message> {
+    let mut rng = Rng::new(seed);
+    (1..=messages)
+        .map(|seq_no| {
+            let security_id = rng.below(securities) + 1;
+            let updates = (0..=rng.below(4))
+                .map(|_| {
+                    let side = if rng.below(2) == 0 { Side::B } else { Side::A };
+                    let price = price(side, rng.below(depth) + 1);
+                    let qty = match rng.below(3) {
+                        0 => 0,
+                        _ => (rng.below(50) + 1) * 100,
+                    };
+                    (side, price, qty)
+                })
+                .collect();
+            (seq_no, seq_no, security_id, updates)
+        })
+        .collect()
+}
+
+/// Snapshot file bytes: every security at SeqNo 0 with the 5 ticks either side of 100.
+pub fn snapshot_bytes(securities: u64) -> Vec<u8> {
+    let mut writer = FeedWriter::new(Vec::new());
+    let levels = |side| -> Vec<_> {
+        (1..=5)
+            .map(|tick| (price(side, tick), tick * 100))
+            .collect()
+    };
+    for security_id in 1..=securities {
+        writer
+            .write_snapshot_levels(0, 0, security_id, &levels(Side::B), &levels(Side::A))
+            .unwrap();
+    }
+    writer.into_inner().unwrap()
+}
+
+/// Incremental file bytes of `messages`.
+pub fn incremental_bytes(messages: &[Message]) -> Vec<u8> {
+    let mut writer = FeedWriter::new(Vec::new());
+    for (timestamp, seq_no, security_id, updates) in messages {
+        writer
+            .write_incremental(*timestamp, *seq_no, *security_id, updates)
+            .unwrap();
+    }
+    writer.into_inner().unwrap()
+}
+
+/// Channel holding `messages`, closed so the stream path stops after the last one.
+pub fn channel_of(messages: Vec<StreamMessage>) -> Receiver<StreamMessage> {
+    let (sender, receiver) = channel::unbounded();
+    for message in messages {
+        sender.send(message).unwrap();
+    }
+    receiver
+}
+
+/// The files as stream messages, see `codec::stream_messages`.
+pub fn stream_of(snapshot: &[u8], incremental: &[u8]) -> Receiver<StreamMessage> {
+    channel_of(codec::stream_messages(snapshot, incremental).unwrap())
+}
+
+/// A snapshot and an incremental file in a temporary directory removed on drop.
+pub struct Feed {
+    _dir: TempDir,
+    pub snapshot: String,
+    pub incremental: String,
+}
+
+impl Feed {
+    /// Paths only, nothing written yet.
+    pub fn paths() -> Self {
+        let dir = TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        Self {
+            snapshot: path("snapshot.bin"),
+            incremental: path("incremental.bin"),
+            _dir: dir,
+        }
+    }
+
+    /// Another file in the same directory.
+    pub fn path(&self, name: &str) -> String {
+        self._dir.path().join(name).to_str().unwrap().to_string()
+    }
+
+    /// Both files written from bytes.
+    pub fn write(snapshot: &[u8], incremental: &[u8]) -> Self {
+        let feed = Self::paths();
+        fs::write(&feed.snapshot, snapshot).unwrap();
+        fs::write(&feed.incremental, incremental).unwrap();
+        feed
+    }
+
+    /// Snapshots of every security followed by `messages`.
+    pub fn random(securities: u64, messages: &[Message]) -> Self {
+        Self::write(&snapshot_bytes(securities), &incremental_bytes(messages))
+    }
+
+    /// Clean generated feed, the generator holds the books it should give.
+    pub fn generate(config: GeneratorConfig) -> (Self, FeedGenerator) {
+        let feed = Self::paths();
+        let generator =
+            generator::generate_files(config, &feed.snapshot, &feed.incremental).unwrap();
+        (feed, generator)
+    }
+
+    /// Generated feed damaged as `faults` says.
+    pub fn faulty(config: GeneratorConfig, faults: &FaultConfig) -> Self {
+        let feed = Self::paths();
+        faults::generate_faulty_files(config, faults, &feed.snapshot, &feed.incremental).unwrap();
+        feed
+    }
+}
+
+/// Clean generated feed in memory: the generator, the snapshot and the incremental bytes.
+pub fn generate_bytes(config: GeneratorConfig) -> (FeedGenerator, Vec<u8>, Vec<u8>) {
+    let mut generator = FeedGenerator::new(config).unwrap();
+    let mut snapshot = FeedWriter::new(Vec::new());
+    generator.write_snapshot(&mut snapshot).unwrap();
+    let mut incremental = FeedWriter::new(Vec::new());
+    generator.write_incrementals(&mut incremental).unwrap();
+
+    (
+        generator,
+        snapshot.into_inner().unwrap(),
+        incremental.into_inner().unwrap(),
+    )
+}
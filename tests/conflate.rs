@@ -0,0 +1,123 @@
+mod common;
+
+use crossbeam::channel::Receiver;
+use lob_processor::basic::Basic;
+use lob_processor::conflate::{ConflatedUpdate, ConflatingPublisher, ConflationMode};
+use lob_processor::improved::ImprovedProcessor;
+use lob_processor::*;
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+// price bits and quantity
+fn levels<B: BookSide>(side: &B) -> Vec<(u64, Qty)> {
+    side.get_l()
+        .iter()
+        .map(|level| (level.price.to_bits(), level.quantity))
+        .collect()
+}
+
+// applies every delta it gets, the way a downstream consumer would
+fn consume(receiver: Receiver<ConflatedUpdate>) -> HashMap<SecurityId, Lob<Basic>> {
+    let mut books = HashMap::new();
+    for update in receiver {
+        let book = books
+            .entry(update.security_id)
+            .or_insert_with(|| Lob::new(update.security_id, Basic::new(true), Basic::new(false)));
+        for (side, price, qty) in update.updates {
+            book.update(side, price, qty);
+        }
+        book.last_update_seq = Some(update.seq_no);
+    }
+    books
+}
+
+fn check_consumer_converges(mode: ConflationMode, capacity: usize, slow: bool) {
+    let snapshot = common::snapshot_bytes(10);
+    let incremental = common::incremental_bytes(&common::random_messages(4, 10, 20, 5000));
+
+    let (mut publisher, receiver) = ConflatingPublisher::channel(mode, capacity);
+    let consumer = thread::spawn(move || {
+        if slow {
+            // let the publisher fill the channel and conflate before anything is read
+            thread::sleep(Duration::from_millis(20));
+        }
+        consume(receiver)
+    });
+
+    let books = ImprovedProcessor::new()
+        .process_stream_with(
+            common::stream_of(&snapshot, &incremental),
+            None,
+            &mut publisher,
+        )
+        .unwrap();
+    while publisher.flush() > 0 {
+        thread::yield_now();
+    }
+    drop(publisher);
+    let consumed = consumer.join().unwrap();
+
+    assert_eq!(consumed.len(), books.len());
+    for (id, book) in &books {
+        let consumed = &consumed[id];
+        assert_eq!(levels(&consumed.bids), levels(&book.bids), "sec id {}", id);
+        assert_eq!(levels(&consumed.asks), levels(&book.asks), "sec id {}", id);
+        assert_eq!(consumed.last_update_seq, book.last_update_seq);
+    }
+}
+
+#[test]
+fn consumer_converges_when_ready() {
+    check_consumer_converges(ConflationMode::WhenReady, 1024, false);
+}
+
+#[test]
+fn slow_consumer_converges() {
+    check_consumer_converges(ConflationMode::WhenReady, 2, true);
+    check_consumer_converges(ConflationMode::Interval(Duration::from_millis(1)), 2, true);
+}
+
+#[test]
+fn intermediate_states_are_conflated() {
+    let (mut publisher, receiver) =
+        ConflatingPublisher::channel(ConflationMode::Interval(Duration::from_secs(3600)), 8);
+    let mut book = Lob::new(1, Basic::new(true), Basic::new(false));
+
+    for (seq, qty) in [(1, 10), (2, 20), (3, 30)] {
+        book.update(Side::B, 100.0, qty);
+        book.last_update_seq = Some(seq);
+        publisher.record(&book);
+        // the interval has not passed, nothing goes out until a flush
+        assert_eq!(publisher.poll(), 0);
+    }
+    assert_eq!(publisher.pending(), 1);
+    assert_eq!(publisher.flush(), 0);
+
+    let update = receiver.try_recv().unwrap();
+    assert_eq!(update.seq_no, 3);
+    assert_eq!(update.updates, vec![(Side::B, 100.0, 30)]);
+    assert!(receiver.try_recv().is_err());
+
+    // back to what the consumer already has, no delta to send
+    publisher.record(&book);
+    assert_eq!(publisher.flush(), 0);
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn full_channel_keeps_securities_pending() {
+    let (mut publisher, receiver) = ConflatingPublisher::channel(ConflationMode::WhenReady, 1);
+    for id in 1..=3 {
+        let mut book = Lob::new(id, Basic::new(true), Basic::new(false));
+        book.update(Side::A, 50.0, id);
+        publisher.record(&book);
+    }
+
+    assert_eq!(publisher.flush(), 2);
+    assert_eq!(receiver.try_recv().unwrap().security_id, 1);
+    assert_eq!(publisher.flush(), 1);
+    assert_eq!(receiver.try_recv().unwrap().security_id, 2);
+    assert_eq!(publisher.flush(), 0);
+    assert_eq!(receiver.try_recv().unwrap().security_id, 3);
+}
@@ -0,0 +1,252 @@
+use crossbeam::channel::{self, Receiver};
+use lob_processor::basic::BasicProcessor;
+use lob_processor::codec::Rejection;
+use lob_processor::generator::{FeedGenerator, GeneratorConfig, SplitMix64};
+use lob_processor::improved::ImprovedProcessor;
+use lob_processor::*;
+
+// the fuzz targets' inputs in small: damaged buffers must give Ok or Err, never a panic
+
+fn feed(seed: u64) -> (Vec<u8>, Vec<u8>) {
+    let mut generator = FeedGenerator::new(GeneratorConfig {
+        seed,
+        messages: 300,
+        ..GeneratorConfig::default()
+    })
+    .unwrap();
+
+    let mut snapshot = codec::FeedWriter::new(Vec::new());
+    generator.write_snapshot(&mut snapshot).unwrap();
+    let mut incremental = codec::FeedWriter::new(Vec::new());
+    generator.write_incrementals(&mut incremental).unwrap();
+
+    (
+        snapshot.into_inner().unwrap(),
+        incremental.into_inner().unwrap(),
+    )
+}
+
+fn process(snapshot: &[u8], incremental: &[u8]) {
+    let _ = BasicProcessor::new().process_bytes(snapshot, incremental);
+    let _ = ImprovedProcessor::new().process_bytes(snapshot, incremental);
+}
+
+// rejected messages, then skipped updates
+#[derive(Default)]
+struct Rejections(
+    Vec<(MessageType, Rejection)>,
+    Vec<(SecurityId, InvalidUpdate)>,
+);
+
+impl<B: BookSide> StreamObserver<B> for Rejections {
+    fn on_rejected(&mut self, msg_type: MessageType, rejection: Rejection) {
+        self.0.push((msg_type, rejection));
+    }
+
+    fn on_invalid_update(&mut self, security_id: SecurityId, invalid: InvalidUpdate) {
+        self.1.push((security_id, invalid));
+    }
+}
+
+fn channel_of(messages: Vec<StreamMessage>) -> Receiver<StreamMessage> {
+    let (sender, receiver) = channel::unbounded();
+    for message in messages {
+        sender.send(message).unwrap();
+    }
+    receiver
+}
+
+// runs both processors, returns the rejections and book count, which must agree
+fn stream(messages: impl Fn() -> Vec<StreamMessage>) -> (Vec<(MessageType, Rejection)>, usize) {
+    let mut basic = Rejections::default();
+    let basic_books = BasicProcessor::new()
+        .process_stream_with(channel_of(messages()), None, &mut basic)
+        .unwrap();
+    let mut improved = Rejections::default();
+    let improved_books = ImprovedProcessor::new()
+        .process_stream_with(channel_of(messages()), None, &mut improved)
+        .unwrap();
+
+    assert_eq!(basic.0, improved.0);
+    assert_eq!(basic.1, improved.1);
+    assert_eq!(basic_books.len(), improved_books.len());
+    (basic.0, basic_books.len())
+}
+
+#[test]
+fn random_byte_flips() {
+    let (snapshot, incremental) = feed(1);
+    let mut rng = SplitMix64::new(7);
+
+    for _ in 0..500 {
+        let mut snapshot = snapshot.clone();
+        let mut incremental = incremental.clone();
+        for _ in 0..1 + rng.below(8) {
+            let buf = if rng.chance(0.2) {
+                &mut snapshot
+            } else {
+                &mut incremental
+            };
+            let at = rng.below(buf.len() as u64) as usize;
+            buf[at] = rng.below(256) as u8;
+        }
+        process(&snapshot, &incremental);
+    }
+}
+
+#[test]
+fn every_truncation() {
+    let (snapshot, incremental) = feed(2);
+    for len in 0..=incremental.len().min(2000) {
+        process(&snapshot, &incremental[..len]);
+    }
+    for len in 0..=snapshot.len() {
+        process(&snapshot[..len], &incremental);
+    }
+}
+
+#[test]
+fn huge_num_updates_is_rejected() {
+    let (snapshot, mut incremental) = feed(3);
+    // first record's NumUpdates, a count that wraps when multiplied by the update size
+    for num_updates in [u64::MAX, u64::MAX / INCREMENTAL_SIZE as u64 + 1, 1 << 60] {
+        incremental[24..32].copy_from_slice(&num_updates.to_le_bytes());
+        assert!(BasicProcessor::new()
+            .process_bytes(&snapshot, &incremental)
+            .is_err());
+        assert!(ImprovedProcessor::new()
+            .process_bytes(&snapshot, &incremental)
+            .is_err());
+    }
+}
+
+#[test]
+fn nan_prices_are_skipped_by_both() {
+    let (snapshot, mut incremental) = feed(4);
+    // price of every update of the first record
+    let num_updates = u64::from_le_bytes(incremental[24..32].try_into().unwrap()) as usize;
+    for i in 0..num_updates {
+        let at = INCREMENTAL_HEADER_SIZE + i * INCREMENTAL_SIZE + 1;
+        incremental[at..at + 8].copy_from_slice(&f64::NAN.to_bits().to_le_bytes());
+    }
+
+    let basic = BasicProcessor::new()
+        .process_bytes(&snapshot, &incremental)
+        .unwrap();
+    let improved = ImprovedProcessor::new()
+        .process_bytes(&snapshot, &incremental)
+        .unwrap();
+    for (id, book) in &improved {
+        let prices = |levels: Vec<Level>| levels.iter().map(|l| l.price).collect::<Vec<_>>();
+        let bids = prices(book.bids.get_l());
+        let asks = prices(book.asks.get_l());
+        // no NaN level, so the sides stay sorted
+        assert!(bids.windows(2).all(|w| w[0] > w[1]), "sec id {}", id);
+        assert!(asks.windows(2).all(|w| w[0] < w[1]), "sec id {}", id);
+        assert_eq!(bids, prices(basic[id].bids.get_l()));
+        assert_eq!(asks, prices(basic[id].asks.get_l()));
+    }
+
+    // the stream path skips them as well instead of failing
+    let (rejected, count) = stream(|| codec::stream_messages(&snapshot, &incremental).unwrap());
+    assert!(rejected.is_empty());
+    assert_eq!(count, basic.len());
+}
+
+#[test]
+fn wrong_length_stream_messages_are_skipped() {
+    let (snapshot, incremental) = feed(5);
+    let books = stream(|| codec::stream_messages(&snapshot, &incremental).unwrap()).1;
+
+    for len in [0, 8, 31, SNAPSHOT_SIZE - 1, SNAPSHOT_SIZE + 1] {
+        let wrong_snapshot = || {
+            let mut data = snapshot[..SNAPSHOT_SIZE].to_vec();
+            data.resize(len, 0);
+            let mut messages = codec::stream_messages(&snapshot, &incremental).unwrap();
+            messages.insert(0, StreamMessage::Data(MessageType::Snapshot, data));
+            messages
+        };
+        let (rejected, count) = stream(wrong_snapshot);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].0, MessageType::Snapshot);
+        assert_eq!(count, books);
+    }
+
+    // first record is header plus updates, cut inside the header, inside the updates,
+    // and one byte too long
+    let first =
+        codec::incremental_record_size(u64::from_le_bytes(incremental[24..32].try_into().unwrap()))
+            .unwrap();
+    for (len, expected) in [
+        (0, Rejection::TooShort { len: 0 }),
+        (16, Rejection::TooShort { len: 16 }),
+        (first - 1, Rejection::TooShort { len: first - 1 }),
+        (first + 1, Rejection::TooLong { len: first + 1 }),
+    ] {
+        let wrong_incremental = || {
+            let mut data = incremental[..first].to_vec();
+            data.resize(len, 0);
+            let mut messages = codec::stream_messages(&snapshot, &incremental).unwrap();
+            messages.push(StreamMessage::Data(MessageType::Incremental, data));
+            messages
+        };
+        let (rejected, count) = stream(wrong_incremental);
+        assert_eq!(rejected, vec![(MessageType::Incremental, expected)]);
+        assert_eq!(count, books);
+    }
+
+    // a count that wraps when multiplied must not pass as a small record
+    let wrapping = || {
+        let mut data = incremental[..first].to_vec();
+        data[24..32].copy_from_slice(&(u64::MAX / INCREMENTAL_SIZE as u64 + 1).to_le_bytes());
+        vec![
+            StreamMessage::EndOfSnapshot,
+            StreamMessage::Data(MessageType::Incremental, data),
+        ]
+    };
+    assert_eq!(stream(wrapping).0.len(), 1);
+}
+
+#[test]
+fn invalid_stream_updates_are_skipped_by_both() {
+    let (snapshot, mut incremental) = feed(6);
+    let id = |at: usize| u64::from_le_bytes(incremental[at + 16..at + 24].try_into().unwrap());
+    // the first update of the first record gets side 7, the one of the second a NaN price
+    let second =
+        codec::incremental_record_size(u64::from_le_bytes(incremental[24..32].try_into().unwrap()))
+            .unwrap();
+    let expected = vec![
+        (id(0), InvalidUpdate::Side(7)),
+        (id(second), InvalidUpdate::NanPrice),
+    ];
+    incremental[INCREMENTAL_HEADER_SIZE] = 7;
+    let at = second + INCREMENTAL_HEADER_SIZE + 1;
+    incremental[at..at + 8].copy_from_slice(&f64::NAN.to_bits().to_le_bytes());
+
+    let send = || channel_of(codec::stream_messages(&snapshot, &incremental).unwrap());
+    let mut basic = Rejections::default();
+    let basic_books = BasicProcessor::new()
+        .process_stream_with(send(), None, &mut basic)
+        .unwrap();
+    let mut improved = Rejections::default();
+    let improved_books = ImprovedProcessor::new()
+        .process_stream_with(send(), None, &mut improved)
+        .unwrap();
+
+    // neither fails the stream, both skip the same updates and keep the rest
+    assert_eq!(basic.1, expected);
+    assert_eq!(improved.1, expected);
+    for (id, book) in &improved_books {
+        let levels = |levels: Vec<Level>| -> Vec<_> {
+            levels.iter().map(|l| (l.price, l.quantity)).collect()
+        };
+        assert_eq!(
+            levels(book.bids.get_l()),
+            levels(basic_books[id].bids.get_l())
+        );
+        assert_eq!(
+            levels(book.asks.get_l()),
+            levels(basic_books[id].asks.get_l())
+        );
+    }
+}
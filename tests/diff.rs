@@ -0,0 +1,124 @@
+mod common;
+
+use lob_processor::basic::Basic;
+use lob_processor::diff;
+use lob_processor::improved::{ImprovedSide, MAX_LEVELS};
+use lob_processor::*;
+
+// price bits and quantity, bids then asks
+type Levels = (Vec<(u64, Qty)>, Vec<(u64, Qty)>);
+
+fn levels<B: BookSide>(book: &Lob<B>) -> Levels {
+    let side = |side: &B| -> Vec<_> {
+        side.get_l()
+            .iter()
+            .map(|level| (level.price.to_bits(), level.quantity))
+            .collect()
+    };
+    (side(&book.bids), side(&book.asks))
+}
+
+// every book against the same book some messages later
+fn check_diffs_reproduce_target<B: BookSide + Clone>(new_side: fn(bool) -> B, depth: u64) {
+    let mut books: Vec<_> = (1..=10)
+        .map(|id| Lob::new(id, new_side(true), new_side(false)))
+        .collect();
+    let mut from = books.clone();
+
+    let messages = common::random_messages(8, 10, depth, 4000);
+    for (step, (_, seq_no, security_id, updates)) in messages.into_iter().enumerate() {
+        let book = &mut books[security_id as usize - 1];
+        for (side, price, qty) in updates {
+            book.update(side, price, qty);
+        }
+        book.last_update_seq = Some(seq_no);
+        if (step + 1) % 400 != 0 {
+            continue;
+        }
+
+        for (from, to) in from.iter_mut().zip(&books) {
+            let updates = diff::diff_books(from, to).unwrap();
+            for &(side, price, qty) in &updates {
+                from.update(side, price, qty);
+            }
+            assert_eq!(levels(from), levels(to));
+            // a second diff has nothing left to do
+            assert!(diff::diff_books(from, to).unwrap().is_empty());
+        }
+    }
+}
+
+#[test]
+fn applied_diff_reproduces_target() {
+    check_diffs_reproduce_target(Basic::new, 10);
+    check_diffs_reproduce_target(ImprovedSide::new, 10);
+}
+
+#[test]
+fn applied_diff_reproduces_target_on_full_sides() {
+    // removes go first, so a side at MAX_LEVELS has room for the inserts
+    check_diffs_reproduce_target(ImprovedSide::new, MAX_LEVELS as u64);
+}
+
+#[test]
+fn diff_is_minimal() {
+    let mut from = Lob::new(1, Basic::new(true), Basic::new(false));
+    for (side, price, qty) in [
+        (Side::B, 100.0, 10),
+        (Side::B, 99.9, 20),
+        (Side::A, 100.1, 30),
+        (Side::A, 100.2, 40),
+    ] {
+        from.update(side, price, qty);
+    }
+    let mut to = from.clone();
+    to.update(Side::B, 99.9, 25);
+    to.update(Side::B, 99.8, 5);
+    to.update(Side::A, 100.2, 0);
+
+    assert_eq!(
+        diff::diff_books(&from, &to).unwrap(),
+        vec![(Side::A, 100.2, 0), (Side::B, 99.9, 25), (Side::B, 99.8, 5)]
+    );
+    assert!(diff::diff_books(
+        &from,
+        &Lob {
+            security_id: 2,
+            ..to
+        }
+    )
+    .is_err());
+}
+
+#[test]
+fn diff_incremental_encodes_the_updates() {
+    let mut from = Lob::new(3, Basic::new(true), Basic::new(false));
+    from.update(Side::B, 50.0, 1);
+    assert!(diff::diff_incremental(&from, &from.clone(), 9)
+        .unwrap()
+        .is_none());
+
+    let mut to = from.clone();
+    to.update(Side::A, 50.5, 7);
+    to.last_update_seq = Some(12);
+    let record = diff::diff_incremental(&from, &to, 9).unwrap().unwrap();
+
+    // header, then the one update
+    assert_eq!(record.len(), INCREMENTAL_HEADER_SIZE + INCREMENTAL_SIZE);
+    assert_eq!(record[0..8], 9u64.to_le_bytes());
+    assert_eq!(record[8..16], 12u64.to_le_bytes());
+    assert_eq!(record[16..24], 3u64.to_le_bytes());
+    assert_eq!(record[24..32], 1u64.to_le_bytes());
+    assert_eq!(record[32], Side::A as u8);
+    assert_eq!(record[33..41], 50.5f64.to_le_bytes());
+    assert_eq!(record[41..49], 7u64.to_le_bytes());
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "aren't sorted")]
+fn unsorted_levels_are_caught_in_debug_builds() {
+    let level = |price| Level { price, quantity: 1 };
+    // bids low to high
+    diff::diff_sides(&[level(99.0), level(100.0)], &[], &[], &[]);
+}
@@ -0,0 +1,285 @@
+mod common;
+
+use common::Feed;
+use fnv::FnvHashSet;
+use lob_processor::basic::{Basic, BasicProcessor};
+use lob_processor::differential::{self, Difference};
+use lob_processor::faults::FaultConfig;
+use lob_processor::generator::{FeedGenerator, GeneratorConfig, SplitMix64};
+use lob_processor::improved::{ImprovedSide, MAX_LEVELS};
+use lob_processor::simd::{self, SearchPath};
+use lob_processor::tick::TickSide;
+use lob_processor::validate::{IssueKind, ValidationReport};
+use lob_processor::*;
+use std::fs;
+
+// basic against improved over generated feeds, clean and damaged
+
+fn compare(feed: &Feed) -> differential::Comparison {
+    differential::compare_files(&feed.snapshot, &feed.incremental).unwrap()
+}
+
+fn invalid_sides(feed: &Feed) -> u64 {
+    let mut report = ValidationReport::new(0);
+    report.check_incrementals(&fs::read(&feed.incremental).unwrap());
+    report.count(IssueKind::InvalidSide { side: 0 })
+}
+
+fn config(seed: u64) -> GeneratorConfig {
+    GeneratorConfig {
+        seed,
+        securities: 20,
+        messages: 5000,
+        ..GeneratorConfig::default()
+    }
+}
+
+fn assert_match(comparison: &differential::Comparison) {
+    let mut report = Vec::new();
+    comparison.report(&mut report).unwrap();
+    assert!(
+        comparison.is_match(),
+        "{}",
+        String::from_utf8_lossy(&report)
+    );
+}
+
+#[test]
+fn clean_feeds_match() {
+    for seed in 1..=8 {
+        let (feed, _) = Feed::generate(config(seed));
+        let comparison = compare(&feed);
+        assert_match(&comparison);
+        assert_eq!(comparison.compared, 20);
+        assert!(comparison.capped.is_empty());
+    }
+}
+
+#[test]
+fn clean_feeds_match_ground_truth() {
+    let (feed, generator) = Feed::generate(config(3));
+    let truth: Vec<Lob<Basic>> = (1..=20)
+        .filter_map(|id| generator.book(id, Basic::new))
+        .collect();
+    let none = FnvHashSet::default();
+
+    let basic = BasicProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let (compared, mismatches) = differential::compare_books(&truth, basic.values(), &none);
+    assert_eq!(compared, 20);
+    assert!(mismatches.is_empty(), "{:?}", mismatches);
+
+    let improved = improved::ImprovedProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let (_, mismatches) = differential::compare_books(&truth, improved.values(), &none);
+    assert!(mismatches.is_empty(), "{:?}", mismatches);
+}
+
+#[test]
+fn deep_books_skip_capped_securities() {
+    let (feed, _) = Feed::generate(GeneratorConfig {
+        depth: MAX_LEVELS + 16,
+        add_weight: 0.8,
+        change_weight: 0.15,
+        delete_weight: 0.05,
+        messages: 20_000,
+        ..config(5)
+    });
+    let comparison = compare(&feed);
+    assert_match(&comparison);
+    assert!(!comparison.capped.is_empty());
+    assert_eq!(comparison.compared + comparison.capped.len() as u64, 20);
+}
+
+#[test]
+fn dropped_duplicated_and_reordered_messages_match() {
+    for seed in 1..=4 {
+        let feed = Feed::faulty(
+            config(seed),
+            &FaultConfig {
+                seed,
+                drop_rate: 0.02,
+                duplicate_rate: 0.02,
+                reorder_rate: 0.02,
+                truncate_snapshot: true,
+                ..FaultConfig::default()
+            },
+        );
+        assert_match(&compare(&feed));
+    }
+}
+
+#[test]
+fn corrupted_messages_match() {
+    for seed in 1..=8 {
+        let feed = Feed::faulty(
+            config(seed),
+            &FaultConfig {
+                seed,
+                corrupt_rate: 0.005,
+                ..FaultConfig::default()
+            },
+        );
+        assert_match(&compare(&feed));
+    }
+}
+
+#[test]
+fn invalid_sides_fail_both_unless_counted() {
+    let feed = Feed::faulty(
+        config(1),
+        &FaultConfig {
+            invalid_side_rate: 0.01,
+            ..FaultConfig::default()
+        },
+    );
+    assert!(invalid_sides(&feed) > 0);
+    assert!(BasicProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .is_err());
+    assert!(improved::ImprovedProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .is_err());
+
+    // with stats both skip the bad updates and agree
+    let comparison = compare(&feed);
+    assert_match(&comparison);
+    assert!(comparison.basic_error.is_none());
+    let (_, basic) = BasicProcessor::new()
+        .process_files_with_stats(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let (_, improved) = improved::ImprovedProcessor::new()
+        .process_files_with_stats(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    assert_eq!(basic.total(), improved.total());
+    assert_eq!(basic.total().invalid_sides, invalid_sides(&feed));
+}
+
+#[test]
+fn truncated_incremental_fails_both() {
+    let feed = Feed::faulty(
+        config(1),
+        &FaultConfig {
+            truncate_incremental: true,
+            ..FaultConfig::default()
+        },
+    );
+    let comparison = compare(&feed);
+    // a tail shorter than a header is ignored by both, anything longer is rejected
+    assert_eq!(
+        comparison.basic_error.is_some(),
+        comparison.improved_error.is_some()
+    );
+    assert!(comparison.is_match());
+}
+
+#[test]
+fn level_difference_is_reported() {
+    let (_, generator) = Feed::generate(config(2));
+    let reference = generator.book(1, Basic::new).unwrap();
+    let mut candidate = generator.book(1, ImprovedSide::new).unwrap();
+    assert!(differential::compare_book(&reference, &candidate).is_none());
+
+    let top = candidate.asks.get_l()[0].clone();
+    candidate.update(Side::A, top.price, top.quantity + 1);
+    match differential::compare_book(&reference, &candidate) {
+        Some(Difference::Level {
+            side: Side::A,
+            level: 0,
+            ..
+        }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    candidate.update(Side::A, top.price, top.quantity);
+    candidate.last_update_seq = Some(u64::MAX);
+    assert!(matches!(
+        differential::compare_book(&reference, &candidate),
+        Some(Difference::LastUpdate { .. })
+    ));
+}
+
+// random single side operations, basic capped at MAX_LEVELS by hand is the model
+fn check_side_against_model<B: BookSide>(new_side: fn(bool) -> B) {
+    for (seed, is_b) in [(1, true), (2, false), (3, true), (4, false)] {
+        let mut rng = SplitMix64::new(seed);
+        let mut model = Basic::new(is_b);
+        let mut side = new_side(is_b);
+
+        for _ in 0..20_000 {
+            // a narrow price range so removes and updates hit existing levels
+            let price = 100.0 + rng.below(48) as f64 * 0.25;
+            if rng.chance(0.35) {
+                assert_eq!(model.remove_l(price), side.remove_l(price));
+            } else {
+                let qty = 1 + rng.below(1000);
+                let full = model.levels.len() == MAX_LEVELS && model.find_position(price).is_err();
+                let expected = if full {
+                    LevelChange::Dropped
+                } else {
+                    model.update_l(price, qty)
+                };
+                assert_eq!(expected, side.update_l(price, qty));
+            }
+
+            let (expected, actual) = (model.get_l(), side.get_l());
+            assert_eq!(expected.len(), actual.len());
+            for (e, a) in expected.iter().zip(actual.iter()) {
+                assert_eq!(e.price.to_bits(), a.price.to_bits());
+                assert_eq!(e.quantity, a.quantity);
+            }
+        }
+    }
+}
+
+#[test]
+fn sides_match_under_random_updates() {
+    check_side_against_model(ImprovedSide::new);
+}
+
+#[test]
+fn tick_sides_match_under_random_updates() {
+    check_side_against_model(TickSide::new);
+}
+
+#[test]
+fn tick_books_match_generated_feed() {
+    let mut generator = FeedGenerator::new(config(6)).unwrap();
+    let mut reference: Vec<_> = (1..=20)
+        .map(|id| generator.book(id, Basic::new).unwrap())
+        .collect();
+    let mut candidate: Vec<_> = (1..=20)
+        .map(|id| generator.book(id, TickSide::new).unwrap())
+        .collect();
+
+    while let Some(message) = generator.next_message() {
+        let i = message.security_id as usize - 1;
+        for &(side, price, qty) in &message.updates {
+            reference[i].update(side, price, qty);
+            candidate[i].update(side, price, qty);
+        }
+    }
+
+    for (reference, candidate) in reference.iter().zip(candidate.iter()) {
+        let difference = differential::compare_book(reference, candidate);
+        assert!(difference.is_none(), "{:?}", difference);
+    }
+}
+
+// run the suite with --features force-scalar, force-sse2 and force-avx2 to cover every path
+#[test]
+fn search_path_follows_features() {
+    let path = simd::search_path();
+    if cfg!(feature = "force-scalar") {
+        assert_eq!(path, SearchPath::Scalar);
+    } else if cfg!(feature = "force-sse2") {
+        assert!(path <= SearchPath::Sse2, "{}", path);
+    } else if cfg!(feature = "force-avx2") {
+        assert!(path <= SearchPath::Avx2, "{}", path);
+    }
+    if cfg!(target_arch = "x86_64") && !cfg!(feature = "force-scalar") {
+        assert!(path >= SearchPath::Sse2, "{}", path);
+    }
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use common::Feed;
+use lob_processor::basic::{Basic, BasicProcessor};
+use lob_processor::codec::Incrementals;
+use lob_processor::faults::{self, Fault, FaultConfig, FaultKind, FAULT_LOG_HEADER};
+use lob_processor::generator::{FeedGenerator, GeneratorConfig};
+use lob_processor::*;
+use std::fs;
+
+fn config() -> GeneratorConfig {
+    GeneratorConfig {
+        seed: 41,
+        messages: 1_000,
+        ..GeneratorConfig::default()
+    }
+}
+
+fn faulty(faults: &FaultConfig) -> (Feed, FeedGenerator, Vec<Fault>) {
+    let feed = Feed::paths();
+    let (generator, log) =
+        faults::generate_faulty_files(config(), faults, &feed.snapshot, &feed.incremental).unwrap();
+    (feed, generator, log)
+}
+
+// SeqNo and update sides/prices of the record at `offset`
+fn record_at(data: &[u8], offset: u64) -> (SeqNo, Vec<(u8, f64, Qty)>) {
+    let record = Incrementals::from_offset(data, offset as usize)
+        .next()
+        .unwrap()
+        .unwrap();
+    (record.header.seq_no, record.updates().collect())
+}
+
+#[test]
+fn no_faults_is_the_clean_feed() {
+    let (feed, _, log) = faulty(&FaultConfig::default());
+    assert!(log.is_empty());
+
+    let (_, snapshot, incremental) = common::generate_bytes(config());
+    assert_eq!(fs::read(&feed.snapshot).unwrap(), snapshot);
+    assert_eq!(fs::read(&feed.incremental).unwrap(), incremental);
+}
+
+#[test]
+fn faults_are_logged_where_they_are() {
+    let (feed, generator, log) = faulty(&FaultConfig {
+        seed: 7,
+        drop_rate: 0.02,
+        duplicate_rate: 0.02,
+        reorder_rate: 0.02,
+        invalid_side_rate: 0.02,
+        nan_price_rate: 0.02,
+        ..FaultConfig::default()
+    });
+    let data = fs::read(&feed.incremental).unwrap();
+    let seqs: Vec<SeqNo> = Incrementals::new(&data)
+        .map(|record| record.unwrap().header.seq_no)
+        .collect();
+
+    for kind in [
+        FaultKind::Dropped,
+        FaultKind::Duplicated,
+        FaultKind::Reordered,
+        FaultKind::InvalidSide,
+        FaultKind::NanPrice,
+    ] {
+        assert!(log.iter().any(|fault| fault.kind == kind), "no {}", kind);
+    }
+
+    for fault in &log {
+        match fault.kind {
+            FaultKind::Dropped => assert!(!seqs.contains(&fault.seq_no)),
+            FaultKind::Duplicated => {
+                assert_eq!(seqs.iter().filter(|&&s| s == fault.seq_no).count(), 2);
+                assert_eq!(record_at(&data, fault.offset).0, fault.seq_no);
+            }
+            FaultKind::Reordered => {
+                let (seq_no, _) = record_at(&data, fault.offset);
+                assert_eq!(seq_no, fault.seq_no);
+                let at = seqs.iter().position(|&s| s == seq_no).unwrap();
+                assert!(at > 0 && seqs[at - 1] > seq_no);
+            }
+            FaultKind::InvalidSide => {
+                let (_, updates) = record_at(&data, fault.offset);
+                assert!(updates.iter().any(|&(side, _, _)| side > 1));
+            }
+            FaultKind::NanPrice => {
+                let (_, updates) = record_at(&data, fault.offset);
+                assert!(updates.iter().any(|&(_, price, _)| price.is_nan()));
+            }
+            kind => panic!("unexpected {}", kind),
+        }
+    }
+
+    // the generator still holds the clean books
+    let (_clean_feed, clean) = Feed::generate(config());
+    for security_id in 1..=config().securities {
+        assert_eq!(
+            generator.levels(security_id),
+            clean.levels(security_id),
+            "sec id {}",
+            security_id
+        );
+    }
+}
+
+#[test]
+fn truncation_cuts_inside_the_last_record() {
+    let (feed, _, log) = faulty(&FaultConfig {
+        truncate_snapshot: true,
+        truncate_incremental: true,
+        ..FaultConfig::default()
+    });
+    let (_, snapshot, incremental) = common::generate_bytes(config());
+
+    assert_eq!(log.len(), 2);
+    assert!(log.iter().all(|fault| fault.kind == FaultKind::Truncated));
+
+    let cut = fs::read(&feed.snapshot).unwrap();
+    assert_eq!(log[0].offset, (snapshot.len() - SNAPSHOT_SIZE) as u64);
+    assert!(cut.len() > log[0].offset as usize && cut.len() < snapshot.len());
+    assert_eq!(cut[..], snapshot[..cut.len()]);
+
+    let cut = fs::read(&feed.incremental).unwrap();
+    assert_eq!(log[1].seq_no, 1_000);
+    assert!(cut.len() > log[1].offset as usize && cut.len() < incremental.len());
+}
+
+#[test]
+fn untouched_securities_reach_the_truth() {
+    let (feed, generator, log) = faulty(&FaultConfig {
+        seed: 3,
+        drop_rate: 0.01,
+        duplicate_rate: 0.05,
+        ..FaultConfig::default()
+    });
+    let affected = faults::affected_securities(&log);
+    assert!(!affected.is_empty());
+    assert!(affected.windows(2).all(|w| w[0] < w[1]));
+
+    let books = BasicProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let mut untouched = 0;
+    for security_id in 1..=config().securities {
+        if affected.contains(&security_id) {
+            continue;
+        }
+        let expected = generator.book(security_id, Basic::new).unwrap();
+        let book = &books[&security_id];
+        untouched += 1;
+        assert_eq!(book.bids.get_l().len(), expected.bids.get_l().len());
+        assert_eq!(book.asks.get_l().len(), expected.asks.get_l().len());
+        assert_eq!(book.last_update_seq, expected.last_update_seq);
+    }
+    assert!(untouched > 0);
+}
+
+#[test]
+fn logs_are_csv() {
+    let log = [
+        Fault {
+            kind: FaultKind::NanPrice,
+            seq_no: 4,
+            security_id: 2,
+            offset: 96,
+        },
+        Fault {
+            kind: FaultKind::Truncated,
+            seq_no: 0,
+            security_id: 10,
+            offset: 1656,
+        },
+    ];
+    let mut out = Vec::new();
+    faults::write_fault_log(&mut out, &log).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        format!(
+            "{}\n4,2,nan_price,96\n0,10,truncated,1656\n",
+            FAULT_LOG_HEADER
+        )
+    );
+
+    let (_, generator, _) = faulty(&FaultConfig::default());
+    let mut out = Vec::new();
+    faults::write_ground_truth(&mut out, &generator).unwrap();
+    let truth = String::from_utf8(out).unwrap();
+    let rows = truth.lines().skip(1).count();
+    let levels: usize = (1..=config().securities)
+        .map(|id| {
+            let (bids, asks) = generator.levels(id);
+            bids.len() + asks.len()
+        })
+        .sum();
+    assert_eq!(rows, levels);
+}
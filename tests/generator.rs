@@ -0,0 +1,147 @@
+mod common;
+
+use common::Feed;
+use lob_processor::basic::BasicProcessor;
+use lob_processor::generator::{FeedGenerator, GeneratorConfig};
+use lob_processor::improved::ImprovedProcessor;
+use lob_processor::*;
+
+fn config(seed: u64) -> GeneratorConfig {
+    GeneratorConfig {
+        seed,
+        messages: 2_000,
+        // deep books and frequent moves make repeated prices in one message likely
+        depth: 20,
+        move_probability: 0.5,
+        max_updates_per_message: 8,
+        ..GeneratorConfig::default()
+    }
+}
+
+#[test]
+fn same_seed_same_feed() {
+    let (_, snapshot, incremental) = common::generate_bytes(config(40));
+    let (_, again_snapshot, again_incremental) = common::generate_bytes(config(40));
+    assert_eq!(snapshot, again_snapshot);
+    assert_eq!(incremental, again_incremental);
+
+    let (_, _, other) = common::generate_bytes(config(41));
+    assert_ne!(incremental, other);
+}
+
+#[test]
+fn messages_are_ordered_on_tick_and_without_repeated_prices() {
+    let config = config(42);
+    let tick_size = config.tick_size;
+    let mut generator = FeedGenerator::new(config).unwrap();
+
+    let mut last: Option<(u64, SeqNo)> = None;
+    let mut count = 0;
+    while let Some(message) = generator.next_message() {
+        count += 1;
+        if let Some((timestamp, seq_no)) = last {
+            assert!(message.seq_no > seq_no);
+            assert!(message.timestamp >= timestamp);
+        }
+        last = Some((message.timestamp, message.seq_no));
+
+        for (i, &(side, price, qty)) in message.updates.iter().enumerate() {
+            let ticks = price / tick_size;
+            assert!(price > 0.0 && (ticks - ticks.round()).abs() < 1e-6);
+            assert_eq!(qty % 100, 0);
+            assert!(
+                !message.updates[i + 1..]
+                    .iter()
+                    .any(|&(s, p, _)| s == side && p == price),
+                "seq {} repeats {:?} {}",
+                message.seq_no,
+                side,
+                price
+            );
+        }
+    }
+    assert_eq!(count, 2_000);
+}
+
+#[test]
+fn processors_reach_the_generator_books() {
+    let (feed, generator) = Feed::generate(config(43));
+    let basic = BasicProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let improved = ImprovedProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+
+    let levels = |side: Vec<Level>| -> Vec<_> {
+        side.iter()
+            .map(|level| (level.price, level.quantity))
+            .collect()
+    };
+    for security_id in 1..=generator.config().securities {
+        let expected = generator.levels(security_id);
+        let basic = &basic[&security_id];
+        let improved = &improved[&security_id];
+        assert_eq!(
+            (levels(basic.bids.get_l()), levels(basic.asks.get_l())),
+            expected,
+            "basic sec id {}",
+            security_id
+        );
+        assert_eq!(
+            (levels(improved.bids.get_l()), levels(improved.asks.get_l())),
+            expected,
+            "improved sec id {}",
+            security_id
+        );
+    }
+}
+
+#[test]
+fn bad_configs_are_rejected() {
+    let bad = [
+        GeneratorConfig {
+            securities: 0,
+            ..GeneratorConfig::default()
+        },
+        GeneratorConfig {
+            tick_size: 0.0,
+            ..GeneratorConfig::default()
+        },
+        GeneratorConfig {
+            delete_weight: -1.0,
+            ..GeneratorConfig::default()
+        },
+        GeneratorConfig {
+            top_bias: 1.5,
+            ..GeneratorConfig::default()
+        },
+        GeneratorConfig {
+            top_bias: f64::NAN,
+            ..GeneratorConfig::default()
+        },
+        GeneratorConfig {
+            move_probability: -0.1,
+            ..GeneratorConfig::default()
+        },
+        GeneratorConfig {
+            move_probability: f64::NAN,
+            ..GeneratorConfig::default()
+        },
+    ];
+    for config in bad {
+        assert!(FeedGenerator::new(config.clone()).is_err(), "{:?}", config);
+    }
+
+    // the bounds themselves are fine
+    for (top_bias, move_probability) in [(0.0, 0.0), (1.0, 1.0)] {
+        let mut generator = FeedGenerator::new(GeneratorConfig {
+            top_bias,
+            move_probability,
+            messages: 50,
+            ..GeneratorConfig::default()
+        })
+        .unwrap();
+        while generator.next_message().is_some() {}
+    }
+}
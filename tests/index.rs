@@ -0,0 +1,121 @@
+mod common;
+
+use common::Feed;
+use lob_processor::codec::Incrementals;
+use lob_processor::improved::{ImprovedProcessor, ImprovedSide};
+use lob_processor::index::{IncrementalIndex, IndexedReader};
+use lob_processor::*;
+use std::fs;
+
+fn feed() -> Feed {
+    Feed::random(12, &common::random_messages(13, 12, 20, 3000))
+}
+
+// price bits and quantity
+fn levels<B: BookSide>(side: &B) -> Vec<(u64, Qty)> {
+    side.get_l()
+        .iter()
+        .map(|level| (level.price.to_bits(), level.quantity))
+        .collect()
+}
+
+#[test]
+fn indexed_books_match_processed_books() {
+    let feed = feed();
+    let books = ImprovedProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let reader = IndexedReader::open_or_build(&feed.incremental).unwrap();
+    assert_eq!(reader.index().len(), 3000);
+
+    for (id, book) in &books {
+        let indexed = reader
+            .process_security(&feed.snapshot, *id, ImprovedSide::new)
+            .unwrap()
+            .unwrap();
+        assert_eq!(levels(&indexed.bids), levels(&book.bids), "sec id {}", id);
+        assert_eq!(levels(&indexed.asks), levels(&book.asks), "sec id {}", id);
+        assert_eq!(indexed.last_update_seq, book.last_update_seq);
+    }
+    assert!(reader
+        .process_security(&feed.snapshot, 999, ImprovedSide::new)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn index_saves_loads_and_jumps_to_a_seq_no() {
+    let feed = feed();
+    let index = IncrementalIndex::build_file(&feed.incremental).unwrap();
+    let path = IncrementalIndex::default_path(&feed.incremental);
+    index.save(&path).unwrap();
+
+    let loaded = IncrementalIndex::load(&path).unwrap();
+    assert_eq!(loaded.len(), index.len());
+    for id in index.securities() {
+        assert_eq!(loaded.entries(id), index.entries(id));
+    }
+
+    let reader = IndexedReader::open(&feed.incremental, loaded).unwrap();
+    let entries = reader.index().entries(1);
+    let middle = entries[entries.len() / 2].seq_no;
+    let seqs: Vec<_> = reader
+        .messages_from(1, middle)
+        .map(|record| record.unwrap().header.seq_no)
+        .collect();
+    assert_eq!(seqs[0], middle);
+    assert_eq!(seqs.len(), entries.len() - entries.len() / 2);
+    assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+
+    assert!(IncrementalIndex::load(&feed.snapshot).is_err());
+}
+
+#[test]
+fn same_length_edit_makes_the_index_stale() {
+    let feed = feed();
+    let index = IncrementalIndex::build_file(&feed.incremental).unwrap();
+    let path = IncrementalIndex::default_path(&feed.incremental);
+    index.save(&path).unwrap();
+
+    // first update's price, length unchanged
+    let mut data = fs::read(&feed.incremental).unwrap();
+    data[INCREMENTAL_HEADER_SIZE + 1] ^= 0x10;
+    fs::write(&feed.incremental, &data).unwrap();
+
+    assert!(IndexedReader::open(&feed.incremental, index).is_err());
+    // rebuilt and saved again
+    let reader = IndexedReader::open_or_build(&feed.incremental).unwrap();
+    assert!(reader.index().matches(&data));
+    assert!(IncrementalIndex::load(&path).unwrap().matches(&data));
+}
+
+#[test]
+fn bad_offsets_are_errors() {
+    let feed = feed();
+    let path = IncrementalIndex::default_path(&feed.incremental);
+    IncrementalIndex::build_file(&feed.incremental)
+        .unwrap()
+        .save(&path)
+        .unwrap();
+
+    // entries start after the 24 byte header, sorted by security: the first two are
+    // security 1, set their offsets to another record's and to u64::MAX
+    let mut bytes = fs::read(&path).unwrap();
+    let entry = |i: usize| 24 + i * 32 + 24;
+    let second: [u8; 8] = bytes[entry(1)..entry(1) + 8].try_into().unwrap();
+    bytes[entry(0)..entry(0) + 8].copy_from_slice(&second);
+    bytes[entry(1)..entry(1) + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    fs::write(&path, bytes).unwrap();
+
+    let reader =
+        IndexedReader::open(&feed.incremental, IncrementalIndex::load(&path).unwrap()).unwrap();
+    let mut messages = reader.messages(1);
+    assert!(messages.next().unwrap().is_err());
+    assert!(messages.next().unwrap().is_err());
+    assert!(messages.next().unwrap().is_ok());
+
+    let data = fs::read(&feed.incremental).unwrap();
+    assert!(Incrementals::from_offset(&data, usize::MAX)
+        .next()
+        .is_none());
+}
@@ -0,0 +1,175 @@
+use lob_processor::codec;
+use lob_processor::inspect::{self, Anomaly, InspectFilter, InspectSummary, Inspector};
+use lob_processor::*;
+
+// two snapshots, the second with a zero price that has qty and a NaN ask
+fn snapshots() -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&codec::encode_snapshot_levels(
+        10,
+        1,
+        1,
+        &[(100.0, 5)],
+        &[(100.5, 6)],
+    ));
+    data.extend_from_slice(&codec::encode_snapshot_levels(
+        20,
+        2,
+        2,
+        &[(0.0, 3)],
+        &[(f64::NAN, 1)],
+    ));
+    data
+}
+
+// seq 3..=5 for securities 1, 2, 1, the middle one with an invalid side
+fn incrementals() -> Vec<u8> {
+    let mut data = Vec::new();
+    codec::encode_incremental(&mut data, 30, 3, 1, &[(Side::B, 99.5, 1)]);
+    let start = data.len();
+    codec::encode_incremental(
+        &mut data,
+        40,
+        4,
+        2,
+        &[(Side::A, 50.0, 1), (Side::B, 49.0, 2)],
+    );
+    data[start + INCREMENTAL_HEADER_SIZE] = 7;
+    codec::encode_incremental(&mut data, 50, 5, 1, &[(Side::A, 101.0, 0)]);
+    data
+}
+
+fn run(
+    filter: InspectFilter,
+    dump: impl FnOnce(&mut Inspector<Vec<u8>>) -> InspectSummary,
+) -> (String, InspectSummary) {
+    let mut inspector = Inspector::new(Vec::new(), filter);
+    let summary = dump(&mut inspector);
+    (String::from_utf8(inspector.into_inner()).unwrap(), summary)
+}
+
+#[test]
+fn anomalies_are_classified() {
+    assert_eq!(inspect::level_anomaly(0.0, 0), None);
+    assert_eq!(
+        inspect::level_anomaly(0.0, 1),
+        Some(Anomaly::ZeroPriceWithQty)
+    );
+    assert_eq!(inspect::level_anomaly(f64::NAN, 0), Some(Anomaly::NanPrice));
+    assert_eq!(inspect::update_anomaly(1, 10.0, 0), None);
+    assert_eq!(
+        inspect::update_anomaly(2, f64::NAN, 1),
+        Some(Anomaly::InvalidSide(2))
+    );
+    assert_eq!(Anomaly::InvalidSide(9).to_string(), "invalid side 9");
+}
+
+#[test]
+fn snapshots_are_dumped_with_marks() {
+    let (out, summary) = run(InspectFilter::default(), |inspector| {
+        inspector.snapshots(&snapshots()).unwrap()
+    });
+    assert_eq!(
+        summary,
+        InspectSummary {
+            records: 2,
+            shown: 2,
+            anomalies: 2,
+            truncated_at: None,
+        }
+    );
+
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines.len(), 2 * (1 + SNAPSHOT_LEVELS));
+    assert_eq!(lines[0], "@0 snapshot ts 10 seq 1 sec 1");
+    assert_eq!(lines[1], "  1 bid 100 x 5 ask 100.5 x 6");
+    assert_eq!(lines[2], "  2 bid 0 x 0 ask 0 x 0");
+    assert_eq!(lines[6], "@184 snapshot ts 20 seq 2 sec 2");
+    assert_eq!(
+        lines[7],
+        "  1 bid 0 x 3 ask NaN x 1  <- bid zero price with qty, ask NaN price"
+    );
+}
+
+#[test]
+fn incrementals_are_dumped_with_marks() {
+    let (out, summary) = run(InspectFilter::default(), |inspector| {
+        inspector.incrementals(&incrementals()).unwrap()
+    });
+    assert_eq!(
+        (summary.records, summary.shown, summary.anomalies),
+        (3, 3, 1)
+    );
+
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines[0], "@0 incremental ts 30 seq 3 sec 1 updates 1");
+    assert_eq!(lines[1], "  bid 99.5 x 1");
+    assert_eq!(lines[2], "@49 incremental ts 40 seq 4 sec 2 updates 2");
+    assert_eq!(lines[3], "  side? 50 x 1  <- invalid side 7");
+    assert_eq!(lines[4], "  bid 49 x 2");
+    assert_eq!(lines[6], "  ask 101 x 0");
+}
+
+#[test]
+fn filters_pick_records_but_anomalies_count_all() {
+    let filter = InspectFilter {
+        securities: Some([1].into_iter().collect()),
+        min_seq: Some(4),
+        ..InspectFilter::default()
+    };
+    let (out, summary) = run(filter, |inspector| {
+        inspector.incrementals(&incrementals()).unwrap()
+    });
+    assert_eq!(
+        (summary.records, summary.shown, summary.anomalies),
+        (3, 1, 1)
+    );
+    assert!(out.starts_with("@115 incremental ts 50 seq 5 sec 1"));
+
+    let filter = InspectFilter {
+        max_timestamp: Some(15),
+        ..InspectFilter::default()
+    };
+    let (_, summary) = run(filter, |inspector| {
+        inspector.snapshots(&snapshots()).unwrap()
+    });
+    assert_eq!(summary.shown, 1);
+
+    let filter = InspectFilter {
+        anomalies_only: true,
+        ..InspectFilter::default()
+    };
+    let (out, summary) = run(filter.clone(), |inspector| {
+        inspector.incrementals(&incrementals()).unwrap()
+    });
+    assert_eq!(summary.shown, 1);
+    assert!(out.starts_with("@49 "));
+    let (out, _) = run(filter, |inspector| {
+        inspector.snapshots(&snapshots()).unwrap()
+    });
+    assert!(out.starts_with("@184 "));
+}
+
+#[test]
+fn truncated_files_stop_at_the_cut() {
+    let mut data = snapshots();
+    data.truncate(SNAPSHOT_SIZE + 10);
+    let (out, summary) = run(InspectFilter::default(), |inspector| {
+        inspector.snapshots(&data).unwrap()
+    });
+    assert_eq!(summary.records, 1);
+    assert_eq!(summary.truncated_at, Some(SNAPSHOT_SIZE));
+    assert!(out.ends_with("@184 truncated snapshot, 10 of 184 bytes\n"));
+    assert!(summary.to_string().ends_with(", truncated at offset 184"));
+
+    // cut inside the last record's updates, then inside its header
+    let data = incrementals();
+    for cut in [data.len() - 5, data.len() - 40] {
+        let (out, summary) = run(InspectFilter::default(), |inspector| {
+            inspector.incrementals(&data[..cut]).unwrap()
+        });
+        assert_eq!(summary.records, 2);
+        assert_eq!(summary.truncated_at, Some(115));
+        assert!(out.lines().last().unwrap().starts_with("@115 "));
+    }
+}
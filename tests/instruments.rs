@@ -0,0 +1,341 @@
+mod common;
+
+use common::Feed;
+use fnv::FnvHashSet;
+use lob_processor::basic::BasicProcessor;
+use lob_processor::differential;
+use lob_processor::generator::{FeedGenerator, GeneratorConfig};
+use lob_processor::improved::ImprovedProcessor;
+use lob_processor::instruments::{Instrument, Instruments, UnknownPolicy};
+use lob_processor::output;
+use lob_processor::*;
+
+// generated feed of 10 securities, reference data built from the generator's own
+
+fn feed() -> (Feed, FeedGenerator) {
+    Feed::generate(GeneratorConfig {
+        seed: 11,
+        messages: 3000,
+        ..GeneratorConfig::default()
+    })
+}
+
+// the generated instruments that pass `keep`, plus `extra`
+fn instruments(
+    generator: &FeedGenerator,
+    keep: impl Fn(&Instrument) -> bool,
+    extra: Vec<Instrument>,
+    unknown: UnknownPolicy,
+) -> Instruments {
+    let mut list: Vec<_> = generator
+        .instruments(unknown)
+        .iter()
+        .filter(|instrument| keep(instrument))
+        .cloned()
+        .collect();
+    list.extend(extra);
+    Instruments::new(list, unknown).unwrap()
+}
+
+#[test]
+fn reference_file_parses_and_round_trips() {
+    let text = "\
+# venue reference
+security_id,symbol,tick_size,lot_size,price_scale,max_depth
+2, MSFT ,0.05,10,2,20
+
+1,AAPL,0.01,100,2,10
+";
+    let instruments = Instruments::parse(text, UnknownPolicy::Reject).unwrap();
+    assert_eq!(instruments.len(), 2);
+    assert_eq!(instruments.unknown, UnknownPolicy::Reject);
+    let ids: Vec<_> = instruments.iter().map(|i| i.security_id).collect();
+    assert_eq!(ids, vec![1, 2]);
+    let msft = instruments.get(2).unwrap();
+    assert_eq!(msft.symbol, "MSFT");
+    assert_eq!(msft.tick_size, 0.05);
+    assert!(msft.on_tick(100.15) && !msft.on_tick(100.17));
+
+    let mut written = Vec::new();
+    instruments.write(&mut written).unwrap();
+    let again =
+        Instruments::parse(&String::from_utf8(written).unwrap(), UnknownPolicy::Reject).unwrap();
+    assert!(instruments.iter().eq(again.iter()));
+}
+
+#[test]
+fn bad_reference_files_are_rejected() {
+    for (text, error) in [
+        ("1,A,0.01,100,2", "line 1"),
+        (
+            "1,A,0.01,100,2,10\n1,B,0.01,100,2,10",
+            "Duplicate security id 1",
+        ),
+        ("1,A,0.01,100,2,10\n2,A,0.01,100,2,10", "Symbol A"),
+        ("1,A,0,100,2,10", "Invalid tick size"),
+        ("1,A,0.01,0,2,10", "at least 1"),
+        // capacity books would abort allocating
+        ("1,A,0.01,100,2,1000000000000", "Max depth 1000000000000"),
+        ("1,A,0.01,100,2,33", "is over 32"),
+        ("1,A B,0.01,100,2,10", "Invalid symbol"),
+        ("1,\"A\",0.01,100,2,10", "Invalid symbol"),
+        ("x,A,0.01,100,2,10", "Invalid security_id 'x'"),
+    ] {
+        let e = Instruments::parse(text, UnknownPolicy::Create).unwrap_err();
+        assert!(format!("{:#}", e).contains(error), "{:#}", e);
+    }
+}
+
+#[test]
+fn known_securities_are_preallocated() {
+    let (feed, generator) = feed();
+    let empty = Instrument {
+        security_id: 100,
+        symbol: "IDLE".to_string(),
+        tick_size: 0.01,
+        lot_size: 1,
+        price_scale: 2,
+        max_depth: 5,
+    };
+    let instruments = instruments(&generator, |_| true, vec![empty], UnknownPolicy::Reject);
+
+    let basic = BasicProcessor::new()
+        .with_instruments(instruments.clone())
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let improved = ImprovedProcessor::new()
+        .with_instruments(instruments)
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+
+    // no message ever mentions 100, its book is there and empty
+    let book = &basic[&100];
+    assert!(book.bids.get_l().is_empty() && book.asks.get_l().is_empty());
+    assert_eq!(book.last_update_seq, None);
+
+    let (compared, mismatches) =
+        differential::compare_books(basic.values(), improved.values(), &FnvHashSet::default());
+    assert_eq!(compared, 11);
+    assert!(mismatches.is_empty(), "{:?}", mismatches);
+}
+
+#[test]
+fn unknown_securities_follow_the_policy() {
+    let (feed, generator) = feed();
+    let plain = BasicProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let known = |instrument: &Instrument| instrument.security_id <= 8;
+
+    for unknown in [
+        UnknownPolicy::Create,
+        UnknownPolicy::Reject,
+        UnknownPolicy::Quarantine,
+    ] {
+        let basic =
+            BasicProcessor::new().with_instruments(instruments(&generator, known, vec![], unknown));
+        let (_, stats) = basic
+            .process_files_with_stats(&feed.snapshot, &feed.incremental)
+            .unwrap();
+        let (basic_books, basic_quarantined) = basic
+            .process_files_with_quarantine(&feed.snapshot, &feed.incremental)
+            .unwrap();
+        let improved = ImprovedProcessor::new().with_instruments(instruments(
+            &generator,
+            known,
+            vec![],
+            unknown,
+        ));
+        let (improved_books, improved_quarantined) = improved
+            .process_files_with_quarantine(&feed.snapshot, &feed.incremental)
+            .unwrap();
+
+        let none = FnvHashSet::default();
+        let (_, mismatches) =
+            differential::compare_books(basic_books.values(), improved_books.values(), &none);
+        assert!(mismatches.is_empty(), "{}: {:?}", unknown, mismatches);
+
+        let (_, mismatches) = differential::compare_books(
+            basic_quarantined.values(),
+            improved_quarantined.values(),
+            &none,
+        );
+        assert!(mismatches.is_empty(), "{}: {:?}", unknown, mismatches);
+
+        let unknown_messages =
+            stats.security(9).unwrap().unknown + stats.security(10).unwrap().unknown;
+        match unknown {
+            UnknownPolicy::Create => {
+                assert_eq!(basic_books.len(), 10);
+                assert!(basic_quarantined.is_empty());
+                assert_eq!(unknown_messages, 0);
+            }
+            UnknownPolicy::Reject => {
+                assert_eq!(basic_books.len(), 8);
+                assert!(basic_quarantined.is_empty());
+                assert!(unknown_messages > 0);
+            }
+            UnknownPolicy::Quarantine => {
+                assert_eq!(basic_books.len(), 8);
+                assert!(unknown_messages > 0);
+                // quarantined books are the books they'd have been without reference data
+                let (compared, mismatches) = differential::compare_books(
+                    plain.values().filter(|book| book.security_id > 8),
+                    basic_quarantined.values(),
+                    &none,
+                );
+                assert_eq!(compared, 2);
+                assert!(mismatches.is_empty(), "{:?}", mismatches);
+            }
+        }
+        assert_eq!(stats.total().unknown, unknown_messages);
+    }
+}
+
+#[test]
+fn off_tick_updates_are_skipped() {
+    let (feed, generator) = feed();
+    // a tick twice the generator's puts every odd tick off it
+    let list = generator
+        .instruments(UnknownPolicy::Create)
+        .iter()
+        .map(|instrument| Instrument {
+            tick_size: instrument.tick_size * 2.0,
+            ..instrument.clone()
+        })
+        .collect();
+    let instruments = Instruments::new(list, UnknownPolicy::Create).unwrap();
+    let reference = instruments.clone();
+
+    let (basic, basic_stats) = BasicProcessor::new()
+        .with_instruments(instruments.clone())
+        .process_files_with_stats(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let (improved, improved_stats) = ImprovedProcessor::new()
+        .with_instruments(instruments)
+        .process_files_with_stats(&feed.snapshot, &feed.incremental)
+        .unwrap();
+
+    assert!(basic_stats.total().off_tick > 0);
+    assert_eq!(basic_stats.total(), improved_stats.total());
+    let (_, mismatches) =
+        differential::compare_books(basic.values(), improved.values(), &FnvHashSet::default());
+    assert!(mismatches.is_empty(), "{:?}", mismatches);
+
+    // snapshot levels are checked too, no book keeps a price off its tick
+    for book in basic.values() {
+        let instrument = reference.get(book.security_id).unwrap();
+        let levels = book.bids.get_l().into_iter().chain(book.asks.get_l());
+        assert!(levels
+            .into_iter()
+            .all(|level| instrument.on_tick(level.price)));
+    }
+    let no_incrementals = feed.path("empty.bin");
+    std::fs::write(&no_incrementals, []).unwrap();
+    let (_, basic_stats) = BasicProcessor::new()
+        .with_instruments(reference.clone())
+        .process_files_with_stats(&feed.snapshot, &no_incrementals)
+        .unwrap();
+    let (_, improved_stats) = ImprovedProcessor::new()
+        .with_instruments(reference)
+        .process_files_with_stats(&feed.snapshot, &no_incrementals)
+        .unwrap();
+    assert!(basic_stats.total().off_tick > 0);
+    assert_eq!(basic_stats.total(), improved_stats.total());
+}
+
+#[test]
+fn snapshot_books_keep_the_max_depth() {
+    let (feed, generator) = feed();
+    let depth = generator.config().depth;
+    let books = BasicProcessor::new()
+        .with_instruments(generator.instruments(UnknownPolicy::Create))
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let plain = BasicProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+
+    for (id, book) in &books {
+        assert!(book.bids.get_l().len() <= depth && book.asks.get_l().len() <= depth);
+        assert_eq!(book.bids.levels.capacity(), depth, "sec id {}", id);
+        assert_eq!(book.asks.levels.capacity(), depth, "sec id {}", id);
+    }
+    let (compared, mismatches) =
+        differential::compare_books(books.values(), plain.values(), &FnvHashSet::default());
+    assert_eq!(compared, 10);
+    assert!(mismatches.is_empty(), "{:?}", mismatches);
+}
+
+#[test]
+fn stream_path_refuses_instruments() {
+    let (_, generator) = feed();
+    let instruments = generator.instruments(UnknownPolicy::Create);
+    let (_, receiver) = crossbeam::channel::unbounded();
+    let e = BasicProcessor::new()
+        .with_instruments(instruments.clone())
+        .process_stream_with(receiver.clone(), None, &mut ())
+        .err()
+        .unwrap();
+    assert!(e.to_string().contains("stream path"), "{}", e);
+    assert!(ImprovedProcessor::new()
+        .with_instruments(instruments)
+        .process_stream_with(receiver, None, &mut ())
+        .is_err());
+}
+
+#[test]
+fn books_are_found_by_symbol() {
+    let (feed, generator) = feed();
+    let instruments = generator.instruments(UnknownPolicy::Create);
+    assert_eq!(instruments.by_symbol("SEC3").unwrap().security_id, 3);
+    assert_eq!(instruments.symbol(3), Some("SEC3"));
+    assert!(instruments.by_symbol("sec3").is_none());
+
+    // std and fnv maps
+    let basic = BasicProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let improved = ImprovedProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    assert_eq!(instruments.book(&basic, "SEC3").unwrap().security_id, 3);
+    assert_eq!(
+        instruments.book(&improved, "SEC10").unwrap().security_id,
+        10
+    );
+    assert!(instruments.book(&improved, "SEC11").is_none());
+}
+
+#[test]
+fn output_carries_symbols_with_instruments() {
+    let (_, generator) = feed();
+    let instruments = instruments(
+        &generator,
+        |instrument| instrument.security_id != 2,
+        vec![],
+        UnknownPolicy::Create,
+    );
+    let books: Vec<_> = (1..=2)
+        .map(|id| generator.book(id, basic::Basic::new).unwrap())
+        .collect();
+
+    let mut json = Vec::new();
+    output::write_json_lines(&mut json, &books, Some(1), Some(&instruments)).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    let lines: Vec<_> = json.lines().collect();
+    assert!(lines[0].starts_with("{\"security\":1,\"symbol\":\"SEC1\",\"seq\":"));
+    assert!(lines[1].starts_with("{\"security\":2,\"symbol\":null,\"seq\":"));
+
+    let mut plain = Vec::new();
+    output::write_json_lines(&mut plain, &books, Some(1), None).unwrap();
+    assert!(!String::from_utf8(plain).unwrap().contains("symbol"));
+
+    let mut csv = Vec::new();
+    output::write_csv(&mut csv, &books, Some(1), Some(&instruments)).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some(output::CSV_HEADER_SYMBOLS));
+    assert!(lines.next().unwrap().starts_with("1,SEC1,bid,1,"));
+    assert!(csv.lines().any(|line| line.starts_with("2,,ask,1,")));
+}
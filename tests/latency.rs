@@ -0,0 +1,126 @@
+mod common;
+
+use lob_processor::basic::BasicProcessor;
+use lob_processor::improved::ImprovedProcessor;
+use lob_processor::latency::{self, LatencyHistogram, LatencyRecorder, LatencyTracker};
+
+fn feed() -> (Vec<u8>, Vec<u8>) {
+    let messages = common::random_messages(14, 10, 20, 500);
+    (
+        common::snapshot_bytes(10),
+        common::incremental_bytes(&messages),
+    )
+}
+
+#[test]
+fn apply_time_is_only_stamped_for_observers_that_want_it() {
+    let (snapshot, incremental) = feed();
+    let stream = || common::stream_of(&snapshot, &incremental);
+
+    let plain = ImprovedProcessor::new()
+        .process_stream_with(stream(), None, &mut ())
+        .unwrap();
+    assert!(plain.values().all(|book| book.last_apply_ts.is_none()));
+    let plain = BasicProcessor::new().process_stream(stream(), 0).unwrap();
+    assert!(plain.values().all(|book| book.last_apply_ts.is_none()));
+
+    let mut tracker = LatencyTracker::new();
+    let books = BasicProcessor::new()
+        .process_stream_with(stream(), None, &mut tracker)
+        .unwrap();
+    assert!(books.values().all(|book| book.last_apply_ts.is_some()));
+    // every incremental past the snapshots is one sample
+    assert!(tracker.total().count() > 0);
+    assert!(tracker.total().count() <= 500);
+}
+
+#[test]
+fn histogram_percentiles_are_within_a_bucket() {
+    let mut histogram = LatencyHistogram::new();
+    assert_eq!(histogram.percentile(50.0), 0);
+    assert_eq!(histogram.min(), 0);
+
+    for value in 1..=10_000u64 {
+        histogram.record(value);
+    }
+    assert_eq!(histogram.count(), 10_000);
+    assert_eq!(histogram.min(), 1);
+    assert_eq!(histogram.max(), 10_000);
+    assert_eq!(histogram.mean(), 5000.5);
+
+    // reported as the bucket's upper bound, at most 1/16 above the true value
+    for (percentile, exact) in [(50.0, 5_000u64), (90.0, 9_000), (99.0, 9_900)] {
+        let value = histogram.percentile(percentile);
+        assert!(value >= exact, "p{} {}", percentile, value);
+        assert!(value <= exact + exact / 16, "p{} {}", percentile, value);
+    }
+    assert_eq!(histogram.percentile(100.0), 10_000);
+
+    // small values are exact
+    let mut small = LatencyHistogram::new();
+    for value in [3, 3, 7, 15] {
+        small.record(value);
+    }
+    assert_eq!(small.percentile(50.0), 3);
+    assert_eq!(small.percentile(75.0), 7);
+    assert_eq!(small.percentile(100.0), 15);
+
+    let mut large = LatencyHistogram::new();
+    large.record(u64::MAX);
+    assert_eq!(large.percentile(50.0), u64::MAX);
+}
+
+#[test]
+fn histograms_merge_and_reset() {
+    let mut a = LatencyHistogram::new();
+    let mut b = LatencyHistogram::new();
+    for value in [10, 20, 30] {
+        a.record(value);
+    }
+    b.record(1_000);
+
+    a.merge(&b);
+    assert_eq!(a.count(), 4);
+    assert_eq!(a.min(), 10);
+    assert_eq!(a.max(), 1_000);
+    assert_eq!(a.mean(), 265.0);
+    assert!(a.to_string().starts_with("count 4 min 10 "));
+
+    a.reset();
+    assert_eq!(a.count(), 0);
+    assert_eq!(a.max(), 0);
+}
+
+#[test]
+fn recorder_converts_ticks() {
+    let mut recorder = LatencyRecorder::new();
+    let start = latency::now_ticks();
+    recorder.record(start, start + 1_000, start + 3_000);
+    assert_eq!(recorder.decode.count(), 1);
+    assert!(recorder.apply.max() >= recorder.decode.max());
+
+    let mut report = Vec::new();
+    recorder.report(&mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert!(report.starts_with("decode count 1 "));
+    assert!(report.contains("\napply count 1 "));
+
+    recorder.reset();
+    assert_eq!(recorder.apply.count(), 0);
+}
+
+#[cfg(feature = "latency-metrics")]
+#[test]
+fn processors_record_every_message() {
+    let (snapshot, incremental) = feed();
+    let stream = || common::stream_of(&snapshot, &incremental);
+
+    let processor = ImprovedProcessor::new();
+    let books = processor.process_stream(stream(), 0).unwrap();
+    let recorded = processor.latency().decode.count();
+    assert!(recorded >= books.len() as u64);
+    assert_eq!(recorded, processor.latency().apply.count());
+
+    processor.reset_latency();
+    assert_eq!(processor.latency().decode.count(), 0);
+}
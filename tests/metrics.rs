@@ -0,0 +1,108 @@
+#![cfg(feature = "metrics")]
+
+mod common;
+
+use lob_processor::improved::ImprovedProcessor;
+use lob_processor::metrics::{Metrics, MetricsObserver, MetricsServer};
+use lob_processor::*;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+// value of the sample line starting with `name`
+fn sample(body: &str, name: &str) -> f64 {
+    body.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no {} in\n{}", name, body))
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn scrape_reports_the_stream() {
+    let snapshot = common::snapshot_bytes(10);
+    let incremental = common::incremental_bytes(&common::random_messages(35, 10, 20, 300));
+    let snapshots = snapshot.len() / SNAPSHOT_SIZE;
+
+    let metrics = Arc::new(Metrics::new());
+    let server = MetricsServer::start("127.0.0.1:0", metrics.clone(), None).unwrap();
+    let mut observer =
+        MetricsObserver::new(metrics, Duration::from_secs(60), Duration::from_secs(60));
+    let books = ImprovedProcessor::new()
+        .process_stream_with(
+            common::stream_of(&snapshot, &incremental),
+            None,
+            &mut observer,
+        )
+        .unwrap();
+    observer.publish();
+
+    let response = get(server.local_addr(), "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+
+    assert_eq!(
+        sample(body, "lob_messages_total{type=\"snapshot\"}"),
+        snapshots as f64
+    );
+    assert_eq!(
+        sample(body, "lob_messages_total{type=\"incremental\"}"),
+        300.0
+    );
+    assert_eq!(
+        sample(body, "lob_rejected_messages_total{type=\"incremental\"}"),
+        0.0
+    );
+    assert_eq!(
+        sample(body, "lob_invalid_updates_total{reason=\"side\"}"),
+        0.0
+    );
+    assert_eq!(sample(body, "lob_books"), books.len() as f64);
+    assert_eq!(sample(body, "lob_stale_books"), 0.0);
+    // rates are left to rate() on the query side
+    assert!(!body.contains("per_second"));
+
+    // scraping doesn't change anything
+    let again = get(server.local_addr(), "/metrics");
+    assert_eq!(&again[again.find("\r\n\r\n").unwrap() + 4..], body);
+
+    assert!(get(server.local_addr(), "/other").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    server.stop();
+}
+
+#[test]
+fn cli_serves_metrics_after_processing() {
+    let feed = common::Feed::random(10, &common::random_messages(36, 10, 20, 200));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lob_processor"))
+        .args(["process", &feed.snapshot, &feed.incremental])
+        .args(["--metrics", "127.0.0.1:0"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // "serving metrics on http://127.0.0.1:port/metrics until killed"
+    let stderr = BufReader::new(child.stderr.take().unwrap());
+    let addr = stderr.lines().map(Result::unwrap).find_map(|line| {
+        let rest = line.strip_prefix("serving metrics on http://")?;
+        Some(rest[..rest.find('/')?].parse::<SocketAddr>().unwrap())
+    });
+
+    let response = addr.map(|addr| get(addr, "/metrics"));
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let response = response.expect("no metrics address on stderr");
+    assert!(response.contains("\nlob_messages_total{type=\"incremental\"} 200\n"));
+}
@@ -0,0 +1,102 @@
+use lob_processor::basic::Basic;
+use lob_processor::instruments::{Instruments, UnknownPolicy};
+use lob_processor::output::{self, CSV_HEADER, CSV_HEADER_SYMBOLS};
+use lob_processor::*;
+
+fn book(security_id: SecurityId, bids: &[(f64, Qty)], asks: &[(f64, Qty)]) -> Lob<Basic> {
+    let mut book = Lob::new(security_id, Basic::new(true), Basic::new(false));
+    for &(price, qty) in bids {
+        book.update(Side::B, price, qty);
+    }
+    for &(price, qty) in asks {
+        book.update(Side::A, price, qty);
+    }
+    book
+}
+
+fn to_string(write: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>) -> String {
+    let mut out = Vec::new();
+    write(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn json_is_one_line_per_book_in_id_order() {
+    let mut first = book(2, &[(100.1, 5), (99.95, 7)], &[(100.2, 1)]);
+    first.last_update_seq = Some(73);
+    first.last_exchange_ts = Some(1_000);
+    let second = book(1, &[], &[]);
+
+    let json = to_string(|out| output::write_json_lines(out, [&first, &second], None, None));
+    assert_eq!(
+        json,
+        "{\"security\":1,\"seq\":null,\"exchange_ts\":null,\"apply_ts\":null,\"bids\":[],\"asks\":[]}\n\
+         {\"security\":2,\"seq\":73,\"exchange_ts\":1000,\"apply_ts\":null,\
+         \"bids\":[{\"price\":100.1,\"qty\":5},{\"price\":99.95,\"qty\":7}],\
+         \"asks\":[{\"price\":100.2,\"qty\":1}]}\n"
+    );
+
+    // depth limits both sides, non-finite prices are null
+    let odd = book(3, &[(f64::INFINITY, 1), (1.0, 2)], &[(2.0, 3)]);
+    let json = to_string(|out| output::write_json(out, &odd, Some(1), None));
+    assert!(
+        json.contains("\"bids\":[{\"price\":null,\"qty\":1}],\"asks\":[{\"price\":2,\"qty\":3}]}")
+    );
+}
+
+#[test]
+fn prices_round_trip_exactly() {
+    let price = 0.1 + 0.2;
+    let book = book(1, &[(price, 1)], &[]);
+
+    let json = to_string(|out| output::write_json(out, &book, None, None));
+    let start = json.find("\"price\":").unwrap() + 8;
+    let end = start + json[start..].find(',').unwrap();
+    assert_eq!(json[start..end].parse::<f64>().unwrap(), price);
+
+    let csv = to_string(|out| output::write_csv_rows(out, &book, None, None));
+    assert_eq!(
+        csv.split(',').nth(3).unwrap().parse::<f64>().unwrap(),
+        price
+    );
+}
+
+#[test]
+fn csv_has_a_header_and_a_row_per_level() {
+    let mut first = book(2, &[(10.5, 1), (10.0, 2)], &[(11.0, 3)]);
+    first.last_update_seq = Some(9);
+    let second = book(1, &[(5.0, 4)], &[]);
+
+    let csv = to_string(|out| output::write_csv(out, [&first, &second], None, None));
+    let expected = format!(
+        "{}\n1,bid,1,5,4,\n2,bid,1,10.5,1,9\n2,bid,2,10,2,9\n2,ask,1,11,3,9\n",
+        CSV_HEADER
+    );
+    assert_eq!(csv, expected);
+
+    let csv = to_string(|out| output::write_csv(out, [&first], Some(1), None));
+    assert_eq!(csv.lines().count(), 3);
+}
+
+#[test]
+fn instruments_add_the_symbol() {
+    let instruments = Instruments::parse("2,XYZ,0.5,1,1,10\n", UnknownPolicy::Create).unwrap();
+    let known = book(2, &[(10.5, 1)], &[]);
+    let unknown = book(1, &[(5.0, 4)], &[]);
+
+    let json = to_string(|out| {
+        output::write_json_lines(out, [&known, &unknown], None, Some(&instruments))
+    });
+    let lines: Vec<_> = json.lines().collect();
+    assert!(lines[0].starts_with("{\"security\":1,\"symbol\":null,\"seq\""));
+    assert!(lines[1].starts_with("{\"security\":2,\"symbol\":\"XYZ\",\"seq\""));
+
+    let csv = to_string(|out| output::write_csv(out, [&known, &unknown], None, Some(&instruments)));
+    assert_eq!(
+        csv,
+        format!(
+            "{}\n1,,bid,1,5,4,\n2,XYZ,bid,1,10.5,1,\n",
+            CSV_HEADER_SYMBOLS
+        )
+    );
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use common::Feed;
+use lob_processor::basic::Basic;
+use lob_processor::codec::{self, FeedWriter};
+use lob_processor::improved::{ImprovedProcessor, ImprovedSide};
+use lob_processor::replay::{Pacing, Replay, Until};
+use lob_processor::*;
+use std::fs;
+use std::time::{Duration, Instant};
+
+// security 1 with a 100.00 bid of 1 from a snapshot at SeqNo 0, then incrementals
+// setting that bid's qty, as (Timestamp, SeqNo, qty) in file order
+fn feed(incrementals: &[(u64, SeqNo, Qty)]) -> Feed {
+    let feed = Feed::paths();
+    let mut snapshot = FeedWriter::new(Vec::new());
+    snapshot
+        .write_snapshot_levels(0, 0, 1, &[(100.0, 1)], &[])
+        .unwrap();
+    fs::write(&feed.snapshot, snapshot.into_inner().unwrap()).unwrap();
+
+    let mut incremental = FeedWriter::new(Vec::new());
+    for &(timestamp, seq_no, qty) in incrementals {
+        incremental
+            .write_incremental(timestamp, seq_no, 1, &[(Side::B, 100.0, qty)])
+            .unwrap();
+    }
+    fs::write(&feed.incremental, incremental.into_inner().unwrap()).unwrap();
+    feed
+}
+
+// price bits and quantity
+fn levels<B: BookSide>(side: &B) -> Vec<(u64, Qty)> {
+    side.get_l()
+        .iter()
+        .map(|level| (level.price.to_bits(), level.quantity))
+        .collect()
+}
+
+fn bid(replay: &Replay<Basic>) -> Qty {
+    replay.book(1).unwrap().bids.get_l()[0].quantity
+}
+
+#[test]
+fn replay_ends_with_the_processed_books() {
+    let feed = Feed::random(10, &common::random_messages(12, 10, 20, 3000));
+    let books = ImprovedProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .unwrap();
+
+    let mut replay = Replay::open(&feed.snapshot, &feed.incremental, ImprovedSide::new).unwrap();
+    assert_eq!(replay.len(), 3000);
+    assert_eq!(replay.run_until(Until::Timestamp(u64::MAX)).unwrap(), 3000);
+    assert_eq!(replay.position(), replay.len());
+    assert!(replay.step().unwrap().is_none());
+
+    assert_eq!(replay.books().len(), books.len());
+    for (id, book) in &books {
+        let replayed = replay.book(*id).unwrap();
+        assert_eq!(levels(&replayed.bids), levels(&book.bids), "sec id {}", id);
+        assert_eq!(levels(&replayed.asks), levels(&book.asks), "sec id {}", id);
+        assert_eq!(replayed.last_update_seq, book.last_update_seq);
+    }
+}
+
+#[test]
+fn until_seq_no_follows_timestamp_order() {
+    let feed = feed(&[(30, 1, 10), (10, 2, 20), (20, 3, 30)]);
+    let mut replay = Replay::open(&feed.snapshot, &feed.incremental, Basic::new).unwrap();
+    assert_eq!(replay.peek(), Some((10, 2)));
+
+    // SeqNo 3 comes next in Timestamp order, so SeqNo 1 is not reached
+    assert_eq!(replay.run_until(Until::SeqNo(2)).unwrap(), 1);
+    assert_eq!(bid(&replay), 20);
+    assert_eq!(replay.peek(), Some((20, 3)));
+
+    assert_eq!(replay.run_until(Until::Timestamp(25)).unwrap(), 1);
+    assert_eq!(bid(&replay), 30);
+    assert_eq!(replay.run_until(Until::SeqNo(u64::MAX)).unwrap(), 1);
+    assert_eq!(bid(&replay), 10);
+}
+
+#[test]
+fn book_at_rewinds() {
+    let feed = feed(&[(10, 1, 10), (20, 2, 20), (30, 3, 30)]);
+    let mut replay = Replay::open(&feed.snapshot, &feed.incremental, Basic::new).unwrap();
+
+    let qty = |book: Option<&Lob<Basic>>| book.unwrap().bids.get_l()[0].quantity;
+    assert_eq!(qty(replay.book_at(1, 25).unwrap()), 20);
+    assert_eq!(qty(replay.book_at(1, 30).unwrap()), 30);
+    assert_eq!(qty(replay.book_at(1, 15).unwrap()), 10);
+    // before the first incremental, the snapshot state
+    assert_eq!(qty(replay.book_at(1, 0).unwrap()), 1);
+    assert_eq!(replay.position(), 0);
+}
+
+#[test]
+fn invalid_side_is_skipped_and_the_replay_goes_on() {
+    let feed = feed(&[(10, 1, 10)]);
+    // a second record whose last update has side 7, after a valid one
+    let mut record = Vec::new();
+    codec::encode_incremental(
+        &mut record,
+        20,
+        2,
+        1,
+        &[(Side::B, 100.0, 99), (Side::A, 100.5, 5)],
+    );
+    let last = record.len() - INCREMENTAL_SIZE;
+    record[last] = 7;
+    let mut incremental = fs::read(&feed.incremental).unwrap();
+    incremental.extend(record);
+    fs::write(&feed.incremental, incremental).unwrap();
+
+    let mut replay = Replay::open(&feed.snapshot, &feed.incremental, Basic::new).unwrap();
+    replay.step().unwrap();
+    assert_eq!(replay.step().unwrap().unwrap().seq_no, 2);
+    assert_eq!(replay.position(), 2);
+    // the valid update next to the bad one is applied
+    assert_eq!(bid(&replay), 99);
+    assert_eq!(replay.skipped_updates(), 1);
+    assert!(replay.step().unwrap().is_none());
+
+    replay.reset().unwrap();
+    assert_eq!(replay.skipped_updates(), 0);
+}
+
+#[test]
+fn realtime_pacing_waits_out_the_gaps() {
+    let feed = feed(&[(1_000, 1, 10), (1_200, 2, 20), (1_400, 3, 30)]);
+    let open = || Replay::open(&feed.snapshot, &feed.incremental, Basic::new).unwrap();
+
+    for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        assert!(open().with_pacing(Pacing::Realtime { speed }).is_err());
+    }
+
+    // 400 ms of feed at 10x
+    let mut replay = open()
+        .with_pacing(Pacing::Realtime { speed: 10.0 })
+        .unwrap();
+    let start = Instant::now();
+    assert_eq!(replay.run_until(Until::Timestamp(u64::MAX)).unwrap(), 3);
+    assert!(start.elapsed() >= Duration::from_millis(40));
+
+    let mut replay = open().with_pacing(Pacing::AsFastAsPossible).unwrap();
+    assert_eq!(replay.run_until(Until::Timestamp(u64::MAX)).unwrap(), 3);
+}
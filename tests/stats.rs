@@ -0,0 +1,169 @@
+mod common;
+
+use common::Feed;
+use lob_processor::basic::BasicProcessor;
+use lob_processor::codec::{self, FeedWriter};
+use lob_processor::improved::{ImprovedProcessor, MAX_LEVELS};
+use lob_processor::stats::{Counters, ProcessingStats};
+use lob_processor::*;
+use std::fs;
+
+// security 1 in the snapshot at SeqNo 5, then a stale message, changes to 1, a new book
+// for 2, a message for 1 with an invalid side and one for 2 with a NaN price
+fn feed() -> Feed {
+    let feed = Feed::paths();
+    let mut snapshot = FeedWriter::new(Vec::new());
+    snapshot
+        .write_snapshot_levels(0, 5, 1, &[(100.0, 10)], &[(100.5, 10)])
+        .unwrap();
+    fs::write(&feed.snapshot, snapshot.into_inner().unwrap()).unwrap();
+
+    let mut incremental = FeedWriter::new(Vec::new());
+    incremental
+        .write_incremental(1, 3, 1, &[(Side::B, 99.0, 1), (Side::A, 101.0, 1)])
+        .unwrap();
+    incremental
+        .write_incremental(
+            2,
+            6,
+            1,
+            &[
+                (Side::B, 99.5, 5),
+                (Side::B, 100.0, 20),
+                (Side::A, 100.5, 0),
+                (Side::A, 102.0, 0),
+            ],
+        )
+        .unwrap();
+    incremental
+        .write_incremental(3, 7, 2, &[(Side::A, 50.0, 1)])
+        .unwrap();
+    let mut bad = Vec::new();
+    codec::encode_incremental(&mut bad, 4, 8, 1, &[(Side::B, 98.0, 1), (Side::B, 97.0, 1)]);
+    bad[INCREMENTAL_HEADER_SIZE] = 9;
+    incremental.write_raw(&bad).unwrap();
+    let mut nan = Vec::new();
+    codec::encode_incremental(
+        &mut nan,
+        5,
+        9,
+        2,
+        &[(Side::B, f64::NAN, 1), (Side::A, 51.0, 1)],
+    );
+    incremental.write_raw(&nan).unwrap();
+    fs::write(&feed.incremental, incremental.into_inner().unwrap()).unwrap();
+    feed
+}
+
+fn check(stats: &ProcessingStats) {
+    assert_eq!(
+        *stats.security(1).unwrap(),
+        Counters {
+            snapshots: 1,
+            incrementals: 3,
+            updates_applied: 5,
+            updates_stale: 2,
+            removes_missing: 1,
+            invalid_sides: 1,
+            ..Counters::default()
+        }
+    );
+    assert_eq!(
+        *stats.security(2).unwrap(),
+        Counters {
+            incrementals: 2,
+            updates_applied: 2,
+            books_created: 1,
+            invalid_prices: 1,
+            ..Counters::default()
+        }
+    );
+    assert_eq!(stats.total().incrementals, 5);
+    assert_eq!(stats.total().updates_applied, 7);
+}
+
+#[test]
+fn counters_match_what_happened() {
+    let feed = feed();
+    let (basic_books, basic) = BasicProcessor::new()
+        .process_files_with_stats(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let (improved_books, improved) = ImprovedProcessor::new()
+        .process_files_with_stats(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    check(&basic);
+    check(&improved);
+
+    // the valid update next to the invalid side is applied
+    for bids in [
+        basic_books[&1].bids.get_l(),
+        improved_books[&1].bids.get_l(),
+    ] {
+        let prices: Vec<_> = bids.iter().map(|level| level.price).collect();
+        assert_eq!(prices, vec![100.0, 99.5, 97.0]);
+    }
+
+    let mut report = Vec::new();
+    basic.report(&mut report, None).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(
+        lines[0],
+        "sec id 1: snapshots 1 incrementals 3 applied 5 stale 2 books created 0 \
+         levels dropped 0 missing removes 1 invalid sides 1 invalid prices 0 unknown 0 \
+         off tick 0"
+    );
+    assert!(lines[1].starts_with("sec id 2: "));
+    assert!(lines[1].ends_with(" invalid prices 1 unknown 0 off tick 0"));
+    assert!(lines[2].starts_with("total: snapshots 1 incrementals 5 applied 7 "));
+
+    // the NaN update is skipped, the one next to it applied
+    for (bids, asks) in [
+        (basic_books[&2].bids.get_l(), basic_books[&2].asks.get_l()),
+        (
+            improved_books[&2].bids.get_l(),
+            improved_books[&2].asks.get_l(),
+        ),
+    ] {
+        assert!(bids.is_empty());
+        assert_eq!(asks.len(), 2);
+    }
+}
+
+#[test]
+fn invalid_side_fails_a_run_without_stats() {
+    let feed = feed();
+    assert!(BasicProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .is_err());
+    assert!(ImprovedProcessor::new()
+        .process_files(&feed.snapshot, &feed.incremental)
+        .is_err());
+}
+
+#[test]
+fn full_sides_count_dropped_levels() {
+    let feed = Feed::paths();
+    fs::write(&feed.snapshot, []).unwrap();
+    let mut incremental = FeedWriter::new(Vec::new());
+    let bids: Vec<_> = (0..MAX_LEVELS + 3)
+        .map(|i| (Side::B, 100.0 - i as f64, 1))
+        .collect();
+    incremental.write_incremental(1, 1, 3, &bids).unwrap();
+    fs::write(&feed.incremental, incremental.into_inner().unwrap()).unwrap();
+
+    let (_, stats) = ImprovedProcessor::new()
+        .process_files_with_stats(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    let counters = stats.security(3).unwrap();
+    assert_eq!(counters.updates_applied, MAX_LEVELS as u64 + 3);
+    assert_eq!(counters.levels_dropped, 3);
+    assert_eq!(counters.books_created, 1);
+
+    // basic has no level limit
+    let (_, stats) = BasicProcessor::new()
+        .process_files_with_stats(&feed.snapshot, &feed.incremental)
+        .unwrap();
+    assert_eq!(stats.security(3).unwrap().levels_dropped, 0);
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use common::Feed;
+use lob_processor::codec;
+use lob_processor::validate::{FileKind, Issue, IssueKind, ValidationReport};
+use lob_processor::*;
+use std::fs;
+use std::process::{Command, Output};
+
+fn report_of(snapshot: &[u8], incremental: &[u8], max_issues: usize) -> ValidationReport {
+    let mut report = ValidationReport::new(max_issues);
+    report.check_snapshots(snapshot);
+    report.check_incrementals(incremental);
+    report
+}
+
+fn lob_processor(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lob_processor"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn generated_feed_is_valid() {
+    let snapshot = common::snapshot_bytes(10);
+    let incremental = common::incremental_bytes(&common::random_messages(39, 10, 20, 500));
+    let report = report_of(&snapshot, &incremental, 10);
+    assert!(report.is_valid(), "{:?}", report.issues);
+    assert_eq!(report.snapshots, (snapshot.len() / SNAPSHOT_SIZE) as u64);
+    assert_eq!(report.incrementals, 500);
+
+    let mut out = Vec::new();
+    report.report(&mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().ends_with("\nok\n"));
+}
+
+#[test]
+fn snapshot_issues_are_found() {
+    let mut data = Vec::new();
+    // crossed
+    data.extend_from_slice(&codec::encode_snapshot_levels(
+        0,
+        1,
+        1,
+        &[(101.0, 1)],
+        &[(100.0, 1)],
+    ));
+    // unsorted bids and an infinite ask
+    data.extend_from_slice(&codec::encode_snapshot_levels(
+        0,
+        1,
+        2,
+        &[(99.0, 1), (99.5, 1)],
+        &[(f64::INFINITY, 1)],
+    ));
+    // same security again, then half a record
+    data.extend_from_slice(&codec::encode_snapshot_levels(0, 1, 1, &[], &[]));
+    data.extend_from_slice(&[0; 20]);
+
+    let report = report_of(&data, &[], 100);
+    assert_eq!(report.snapshots, 3);
+    let kinds: Vec<_> = report.issues.iter().map(|issue| issue.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            IssueKind::CrossedBook {
+                bid: 101.0,
+                ask: 100.0
+            },
+            IssueKind::BadPrice {
+                price: f64::INFINITY
+            },
+            IssueKind::UnsortedLevels { side: Side::B },
+            IssueKind::DuplicateSecurity { first_offset: 0 },
+            IssueKind::TruncatedTail { bytes: 20 },
+        ]
+    );
+    assert_eq!(
+        report.issues[3],
+        Issue {
+            file: FileKind::Snapshot,
+            offset: 2 * SNAPSHOT_SIZE,
+            security_id: Some(1),
+            kind: IssueKind::DuplicateSecurity { first_offset: 0 },
+        }
+    );
+    assert_eq!(
+        report.issues[4].to_string(),
+        "snapshot @552: truncated tail of 20 bytes"
+    );
+}
+
+#[test]
+fn incremental_issues_are_found() {
+    let mut data = Vec::new();
+    codec::encode_incremental(&mut data, 10, 5, 1, &[(Side::B, 1.0, 1)]);
+    // SeqNo and Timestamp go back, a NaN price and an invalid side
+    let start = data.len();
+    codec::encode_incremental(
+        &mut data,
+        9,
+        5,
+        1,
+        &[(Side::B, f64::NAN, 1), (Side::A, 2.0, 1)],
+    );
+    data[start + INCREMENTAL_HEADER_SIZE + INCREMENTAL_SIZE] = 4;
+    // NumUpdates nothing could hold
+    codec::encode_incremental(&mut data, 11, 6, 1, &[]);
+    let len = data.len();
+    data[len - 8..].copy_from_slice(&u64::MAX.to_le_bytes());
+
+    let report = report_of(&[], &data, 2);
+    assert_eq!(report.incrementals, 2);
+    assert_eq!(report.total(), 5);
+    // only the first two are kept, every one is counted
+    assert_eq!(report.issues.len(), 2);
+    assert_eq!(
+        report.count(IssueKind::SeqNotIncreasing {
+            seq_no: 0,
+            previous: 0
+        }),
+        1
+    );
+    assert_eq!(report.count(IssueKind::BadPrice { price: 0.0 }), 1);
+    assert_eq!(report.count(IssueKind::InvalidSide { side: 0 }), 1);
+    assert_eq!(
+        report.count(IssueKind::ImpossibleNumUpdates { num_updates: 0 }),
+        1
+    );
+
+    let mut out = Vec::new();
+    report.report(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("snapshots 0 incrementals 2\nincremental @49 sec 1: SeqNo 5 after 5\n"));
+    assert!(out.contains("\n... 3 more\n"));
+    assert!(out.ends_with("\n5 issues\n"));
+
+    // a record cut short is a truncated tail, not garbage
+    let mut report = ValidationReport::new(10);
+    report.check_incrementals(&data[..start + 10]);
+    assert_eq!(
+        report.issues.last().unwrap().kind,
+        IssueKind::TruncatedTail { bytes: 10 }
+    );
+}
+
+#[test]
+fn validate_exit_codes() {
+    let feed = Feed::random(10, &common::random_messages(40, 10, 20, 100));
+
+    let ok = lob_processor(&[
+        "validate",
+        "--snapshot",
+        &feed.snapshot,
+        "--incremental",
+        &feed.incremental,
+    ]);
+    assert_eq!(ok.status.code(), Some(0));
+    assert!(String::from_utf8(ok.stdout).unwrap().ends_with("ok\n"));
+
+    let mut data = fs::read(&feed.incremental).unwrap();
+    data.truncate(data.len() - 3);
+    fs::write(&feed.incremental, data).unwrap();
+    let bad = lob_processor(&["validate", "--incremental", &feed.incremental]);
+    assert_eq!(bad.status.code(), Some(1));
+    assert!(String::from_utf8(bad.stdout)
+        .unwrap()
+        .contains("truncated tail: 1\n"));
+
+    let missing = lob_processor(&["validate", "--snapshot", &feed.path("missing.bin")]);
+    assert_eq!(missing.status.code(), Some(2));
+    let usage = lob_processor(&["validate"]);
+    assert_eq!(usage.status.code(), Some(2));
+    assert!(String::from_utf8(usage.stderr)
+        .unwrap()
+        .starts_with("error: "));
+}